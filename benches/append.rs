@@ -0,0 +1,126 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+//! Per-record cost of the append path: building the GELF message, left to the background thread
+//! to serialize, and building and serializing it with `set_deferred_serialization(false)`.
+//!
+//! The memory budget is set to a byte so every record is dropped right after being encoded, which
+//! keeps the queue and the network out of the measurement. The `filtered` group measures records
+//! ruled out by the level, target and field filters, whose costly dynamic field must not run.
+
+#[macro_use]
+extern crate criterion;
+extern crate log;
+extern crate log4rs;
+extern crate log4rs_gelf;
+
+use criterion::{Criterion, Throughput};
+use log::{Level, LevelFilter, Record};
+use log4rs::append::Append;
+use log4rs_gelf::{BufferAppender, Value};
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::Duration;
+
+fn appender(deferred_serialization: bool) -> BufferAppender {
+    let builder = BufferAppender::builder()
+        .set_hostname("127.0.0.1")
+        .set_port(12202)
+        .set_max_memory_bytes(0)
+        .set_deferred_serialization(deferred_serialization)
+        .put_additional_field("component", Value::String("bench".to_string()))
+        .put_additional_field("environment", Value::String("production".to_string()))
+        .put_additional_field("region", Value::String("eu-west-1".to_string()))
+        .put_additional_field("instance", Value::U64(42));
+
+    #[cfg(feature = "tls")]
+    let builder = builder.set_use_tls(false);
+
+    builder.build().expect("Failed to create appender")
+}
+
+fn append(c: &mut Criterion) {
+    let mut group = c.benchmark_group("append");
+    group.throughput(Throughput::Elements(1));
+    let body = "x".repeat(4096);
+    for &(suffix, deferred_serialization) in &[("", true), ("_serialized", false)] {
+        let appender = appender(deferred_serialization);
+        group.bench_function(format!("short_message{}", suffix), |b| b.iter(|| {
+            appender.append(&Record::builder()
+                .args(format_args!("user {} logged in", 42))
+                .level(Level::Info)
+                .target("bench::auth")
+                .module_path_static(Some("bench::auth"))
+                .file_static(Some("benches/append.rs"))
+                .line(Some(42))
+                .build())
+        }));
+        group.bench_function(format!("large_message{}", suffix), |b| b.iter(|| {
+            appender.append(&Record::builder()
+                .args(format_args!("request failed: {}", body))
+                .level(Level::Warn)
+                .target("bench::http")
+                .module_path_static(Some("bench::http"))
+                .file_static(Some("benches/append.rs"))
+                .line(Some(42))
+                .build())
+        }));
+    }
+    group.finish();
+}
+
+/// An appender taking `Info` records but not those of `bench::noisy`, nor those with
+/// `health_check: true`, with a dynamic field costing 100µs.
+fn filtering_appender() -> BufferAppender {
+    let mut drop_if = BTreeMap::new();
+    drop_if.insert("health_check".to_string(), Value::Bool(true));
+    let builder = BufferAppender::builder()
+        .set_hostname("127.0.0.1")
+        .set_port(12202)
+        .set_max_memory_bytes(0)
+        .set_target_level("bench::noisy", LevelFilter::Off)
+        .set_drop_if(drop_if)
+        .put_dynamic_field("expensive", || {
+            thread::sleep(Duration::from_micros(100));
+            Value::Bool(true)
+        });
+
+    #[cfg(feature = "tls")]
+    let builder = builder.set_use_tls(false);
+
+    builder.build().expect("Failed to create appender")
+}
+
+fn filtered(c: &mut Criterion) {
+    let appender = filtering_appender();
+    let mut group = c.benchmark_group("filtered");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("by_level", |b| b.iter(|| {
+        appender.append(&Record::builder()
+            .args(format_args!("cache miss for {}", 42))
+            .level(Level::Debug)
+            .target("bench::cache")
+            .build())
+    }));
+    group.bench_function("by_target", |b| b.iter(|| {
+        appender.append(&Record::builder()
+            .args(format_args!("polling {}", 42))
+            .level(Level::Info)
+            .target("bench::noisy")
+            .build())
+    }));
+    let health_check: &[(&str, log::kv::Value)] = &[("health_check", log::kv::Value::from(true))];
+    group.bench_function("by_field", |b| b.iter(|| {
+        appender.append(&Record::builder()
+            .args(format_args!("GET /health"))
+            .level(Level::Info)
+            .target("bench::http")
+            .key_values(&health_check)
+            .build())
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, append, filtered);
+criterion_main!(benches);
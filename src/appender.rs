@@ -2,103 +2,482 @@
 // license that can be found in the LICENSE file.
 // Copyright 2009 The log4rs-gelf Authors. All rights reserved.
 
-use anyhow::Context;
-use gelf_logger::Value;
-use gelf_logger::{Builder, GelfLogger};
-use log::{Level, Log, Record};
+use anyhow::{bail, Context};
+use background::Background;
+use capture::{Capture, DEFAULT_CAPTURE_MAX_BYTES};
+use encoder::{EncodedField, Template, DEFAULT_ENCODER_FIELD};
+use enrich;
+use error::{panic_message, ConfigError, Error};
+#[cfg(feature = "async")]
+use error::FlushError;
+use fallback::Fallback;
+use filter::{self, FieldFilter, TargetLevels};
+use fold::{self, Folder};
+use handle::{self, GelfHandle};
+use hooks::{ConnectionHooks, Diagnostics};
+use log::{Level, LevelFilter, Metadata, Record};
 use log4rs::append::Append;
+use log4rs::encode::Encode;
+use monitor;
+use naming::FieldSchema;
+use message::{field_name, truncate_field, DynamicFields, EmptyFieldPolicy, Envelope, GelfMessage, LevelField, MessageOptions, OutputFormat, StaticFields, TargetFields};
+use message::{GELF_FIELDS, GELF_VERSION};
+use redact::{Redaction, Redactor};
+use sanitize::{sanitize_string, sanitize_value};
+use serde::Serialize;
+use sender::{BatchPolicy, Delivery, Entry, OrderingPolicy, OverflowPolicy, PendingMessage, Sender, SenderConfig, DEFAULT_MAX_BATCH_RECORDS};
+#[cfg(feature = "async")]
+use report::ErrorSender;
+use sender::{DEFAULT_ERROR_REPORT_INTERVAL, DEFAULT_MAX_MEMORY_BYTES};
+use serde_value::Value;
+use stats::{Stats, StatsCollector, DEFAULT_DROP_STATS_TARGETS};
+use spans::SpanTracker;
+use std::cmp;
 use std::collections::BTreeMap;
 use std::fmt;
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
 use std::time::Duration;
+use timestamp::Clock;
+use transport::{new_transport, Framing, GelfTransport, Protocol, Resolver, Secret, TransportConfig, TransportSlot, DEFAULT_HTTP_PATH};
+#[cfg(feature = "tls")]
+use transport::parse_fingerprint;
+use windows;
+
+/// Reported when clones of a builder given a transport build several appenders.
+const TRANSPORT_TAKEN: &str = "the transport given to with_transport is used by another appender";
+
+/// Parts of field names whose values are masked when the settings are described.
+const SECRET_NAMES: [&str; 7] = ["password", "passwd", "secret", "token", "api_key", "apikey", "credential"];
+
+/// Environment variables overriding the `pkg_name` and `pkg_version` default fields.
+const PKG_NAME_ENV: &str = "LOG4RS_GELF_PKG_NAME";
+const PKG_VERSION_ENV: &str = "LOG4RS_GELF_PKG_VERSION";
 
 /// Struct to handle the GELF buffer.
 ///
+/// ## Output
+///
+/// The keys of the JSON messages always come in the same order:
+///
+/// 1. `version`, `host`, `short_message`, `timestamp` and `level`;
+/// 2. the MDC entries, sorted by key, then the record key-values in the order they were given. A
+///    key-value replacing an MDC entry takes its place;
+/// 3. `_target`, `_module_path`, `_file` and `_line`;
+/// 4. the dynamic fields in the order they were added, the target fields, most specific target
+///    first, the encoder field and `_priority`;
+/// 5. the static additional fields, sorted by key.
+///
+/// Fields left out of a message don't change the order of the others. Folding stack trace lines
+/// into a message with [`set_fold_window`](struct.BufferAppenderBuilder.html#method.set_fold_window)
+/// keeps its keys in order, and adds `full_message` last if it had none.
+///
+/// The `timestamp` is the time of the logging call, unless the record has a `timestamp` or
+/// `event_time` key-value, in seconds since the UNIX epoch or as an RFC 3339 date: backfill and
+/// replay tools can keep the original time of the events. The last valid one wins. Values which
+/// aren't a valid time are sent as additional fields.
+///
+/// ## Global state
+///
+/// Building an appender neither installs a logger nor changes the maximum level of the `log`
+/// crate: it's a plain log4rs appender, which can be handed to any log4rs configuration, or
+/// called directly from another logger. Only [`init_config`](fn.init_config.html),
+/// [`init_file`](fn.init_file.html) and [`ConfigBuilder::init`](struct.ConfigBuilder.html#method.init)
+/// install one. Appenders don't share anything but process-wide registries: the handle of a
+/// named appender, until it's dropped or another appender takes the name, and the senders
+/// [`post_fork`](fn.post_fork.html) restarts.
+///
+/// ## Reconfiguration
+///
+/// An appender replaced by `log4rs::Handle::set_config` is dropped once the new configuration is
+/// in place: it stops and joins its threads, sending the message still held back by
+/// `fold_window`, writes out what's queued, tries once if the server can't be reached, and
+/// closes its connections. Threads still blocked writing after 5 seconds, on a server which
+/// stopped reading without `write_timeout`, are left to finish on their own so that `set_config`
+/// returns. The new appender opens connections of its own, takes over the name of the old one,
+/// and appends to the capture file the old one may still write its last batches to.
+///
+/// ```rust
+/// extern crate log;
+/// extern crate log4rs;
+/// extern crate log4rs_gelf;
+///
+/// use log::LevelFilter;
+/// use log4rs::config::{Appender, Config, Root};
+/// use std::io::Read;
+/// use std::net::TcpListener;
+///
+/// # #[cfg(not(feature = "tcp"))]
+/// # fn main() {}
+/// # #[cfg(feature = "tcp")]
+/// fn main() {
+///     let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+///     let port = listener.local_addr().unwrap().port();
+///     let config = || {
+///         let builder = log4rs_gelf::BufferAppender::builder()
+///             .set_hostname("127.0.0.1")
+///             .set_port(port);
+///         # #[cfg(feature = "tls")]
+///         let builder = builder.set_use_tls(false);
+///         let appender = builder
+///             .set_name("graylog")
+///             .build()
+///             .unwrap();
+///         Config::builder()
+///             .appender(Appender::builder().build("gelf", Box::new(appender)))
+///             .build(Root::builder().appender("gelf").build(LevelFilter::Info))
+///             .unwrap()
+///     };
+///     let handle = log4rs::init_config(config()).unwrap();
+///     for round in 0..5 {
+///         log::info!("round {}", round);
+///         handle.set_config(config());
+///         // The replaced appender sent its record and closed its connection.
+///         let mut received = Vec::new();
+///         listener.accept().unwrap().0.read_to_end(&mut received).unwrap();
+///         assert!(String::from_utf8_lossy(&received).contains(&format!("round {}", round)));
+///         assert!(log4rs_gelf::handle("graylog").is_some());
+///     }
+/// }
+/// ```
+///
 /// ## Example
 ///
 /// ```rust
 /// extern crate log;
-/// extern crate serde_gelf;
-/// extern crate gelf_logger;
 ///
 /// use log::Level;
-/// use serde_gelf::GelfLevel;
-/// use gelf_logger::Value;
+/// use log4rs_gelf::Value;
 /// use std::time::Duration;
 ///
 /// fn main() {
-///     let buffer = log4rs_gelf::BufferAppender::builder()
+///     let builder = log4rs_gelf::BufferAppender::builder()
 ///         .set_level(Level::Info)
 ///         .set_hostname("localhost")
-///         .set_port(12202)
-///         .set_use_tls(false)
+///         .set_port(12202);
+///     # #[cfg(feature = "tls")]
+///     let builder = builder.set_use_tls(false);
+///     let buffer = builder
 ///         .set_null_character(true)
-///         .set_buffer_size(Some(5))
+///         .set_max_batch_records(5)
 ///         .put_additional_field("component", Value::String("rust-cs".to_string()))
 ///         .build()
 ///         .expect("Failed to create appender");
 /// }
 /// ```
 pub struct BufferAppender {
-    gelf_logger: GelfLogger
+    level: Level,
+    target_levels: TargetLevels,
+    field_filter: FieldFilter,
+    message: MessageOptions,
+    fallback: Fallback,
+    fallback_level: Level,
+    flush_timeout: Option<Duration>,
+    delivery: Delivery,
+    /// Queues the messages for the background thread to serialize.
+    deferred_serialization: bool,
+    stats: Arc<StatsCollector>,
+    /// Holds messages back to fold the stack traces following them in.
+    folder: Option<Arc<Folder>>,
+    /// Follows the spans of `tracing`.
+    spans: Option<Arc<SpanTracker>>,
+    /// Stops and joins the self-monitoring thread when dropped, before the sender.
+    _monitor: Option<Background>,
+    /// Stops and joins the folding thread when dropped, before the sender, sending the message
+    /// held back.
+    _folding: Option<Background>,
+    /// Name the handle of the appender is registered under.
+    name: Option<String>,
+    sender: Sender,
 }
 
 /// Builder for [`BufferAppender`](struct.BufferAppender.html).
 ///
+/// A base configuration can be cloned, then specialized for several appenders. Clones compare
+/// equal until their settings differ; callbacks, encoders and transports are compared by
+/// identity.
+///
 /// ## Example
 ///
 /// ```rust
 /// extern crate log;
-/// extern crate serde_gelf;
-/// extern crate gelf_logger;
 ///
 /// use log::Level;
-/// use serde_gelf::GelfLevel;
-/// use gelf_logger::Value;
+/// use log4rs_gelf::Value;
 /// use std::time::Duration;
 ///
 /// fn main() {
 /// let builder = log4rs_gelf::BufferAppenderBuilder::default()
 ///         .set_level(Level::Info)
 ///         .set_hostname("localhost")
-///         .set_port(12202)
-///         .set_use_tls(false)
+///         .set_port(12202);
+/// # #[cfg(feature = "tls")]
+/// let builder = builder.set_use_tls(false);
+/// let builder = builder
 ///         .set_null_character(true)
-///         .set_buffer_size(Some(5))
+///         .set_max_batch_records(5)
 ///         .put_additional_field("component", Value::String("rust-cs".to_string()));
 /// }
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BufferAppenderBuilder {
     level: Level,
+    target_levels: TargetLevels,
+    directives: Option<String>,
+    field_filter: FieldFilter,
+    protocol: Protocol,
     hostname: String,
     port: u16,
     #[cfg(feature = "tls")]
     use_tls: bool,
-    null_character: bool,
-    buffer_size: Option<usize>,
+    #[cfg(feature = "tls")]
+    tls_pinned_cert_sha256: Option<String>,
+    #[cfg(feature = "tls")]
+    tls_verify_ca: bool,
+    /// PEM certificate chain and PKCS #8 key presented to the remote server.
+    #[cfg(feature = "tls")]
+    tls_client_identity: Option<Secret<(String, String)>>,
+    framing: Framing,
+    http_path: String,
+    http_authorization: Option<Secret<String>>,
+    http_idle_timeout: Option<Duration>,
+    resolver: Option<Resolver>,
+    compression_threshold: Option<usize>,
+    batch: BatchPolicy,
     additional_fields: BTreeMap<String, Value>,
+    /// Additional fields kept out of the description of the settings.
+    secret_fields: BTreeMap<String, Secret<String>>,
+    default_fields: bool,
+    /// The first additional field which couldn't be serialized, reported by `build`.
+    field_error: Option<String>,
+    dynamic_fields: DynamicFields,
+    target_fields: TargetFields,
+    enrichers: Vec<String>,
+    tracing_spans: bool,
+    field_schema: FieldSchema,
     connect_timeout: Option<Duration>,
     write_timeout: Option<Duration>,
+    max_memory_bytes: usize,
+    queue_capacity: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    delivery: Delivery,
+    fold_window: Option<Duration>,
+    drop_stats_targets: usize,
+    error_report_interval: Duration,
+    capture_to_file: Option<PathBuf>,
+    capture_max_bytes: u64,
+    synchronous: bool,
+    deferred_serialization: bool,
+    connections: usize,
+    ordering: OrderingPolicy,
+    record_ttl: Option<Duration>,
+    flush_timeout: Option<Duration>,
+    stats_interval: Option<Duration>,
+    priority_queue_level: LevelFilter,
+    hooks: ConnectionHooks,
+    fallback: Fallback,
+    fallback_level: Level,
+    windows_fields: bool,
+    host_tags: Vec<String>,
+    priority_field: bool,
+    severity_field: Option<String>,
+    level_field: LevelField,
+    level_range: (u32, u32),
+    clock_offset: f64,
+    clock_offset_from_http: bool,
+    encoder: Option<Arc<dyn Encode>>,
+    encoder_field: String,
+    short_message_template: Option<String>,
+    sanitize: bool,
+    empty_fields: EmptyFieldPolicy,
+    max_field_bytes: Option<usize>,
+    full_message_chunk_bytes: Option<usize>,
+    correlation_id: bool,
+    redactions: Vec<Redaction>,
+    format: OutputFormat,
+    protocol_version: String,
+    envelope_fields: BTreeMap<String, Value>,
+    strict: bool,
+    name: Option<String>,
+    log_config: bool,
+    transport: Option<TransportSlot>,
+}
+
+impl PartialEq for BufferAppenderBuilder {
+    fn eq(&self, other: &BufferAppenderBuilder) -> bool {
+        #[cfg(feature = "tls")]
+        {
+            if self.use_tls != other.use_tls
+                || self.tls_pinned_cert_sha256 != other.tls_pinned_cert_sha256
+                || self.tls_verify_ca != other.tls_verify_ca
+                || self.tls_client_identity != other.tls_client_identity
+            {
+                return false;
+            }
+        }
+        let same_encoder = match (&self.encoder, &other.encoder) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        self.level == other.level
+            && self.target_levels == other.target_levels
+            && self.directives == other.directives
+            && self.field_filter == other.field_filter
+            && self.protocol == other.protocol
+            && self.hostname == other.hostname
+            && self.port == other.port
+            && self.framing == other.framing
+            && self.http_path == other.http_path
+            && self.http_authorization == other.http_authorization
+            && self.http_idle_timeout == other.http_idle_timeout
+            && self.resolver == other.resolver
+            && self.compression_threshold == other.compression_threshold
+            && self.batch == other.batch
+            && self.additional_fields == other.additional_fields
+            && self.secret_fields == other.secret_fields
+            && self.default_fields == other.default_fields
+            && self.field_error == other.field_error
+            && self.dynamic_fields == other.dynamic_fields
+            && self.target_fields == other.target_fields
+            && self.enrichers == other.enrichers
+            && self.tracing_spans == other.tracing_spans
+            && self.field_schema == other.field_schema
+            && self.connect_timeout == other.connect_timeout
+            && self.write_timeout == other.write_timeout
+            && self.max_memory_bytes == other.max_memory_bytes
+            && self.queue_capacity == other.queue_capacity
+            && self.overflow_policy == other.overflow_policy
+            && self.delivery == other.delivery
+            && self.fold_window == other.fold_window
+            && self.drop_stats_targets == other.drop_stats_targets
+            && self.error_report_interval == other.error_report_interval
+            && self.capture_to_file == other.capture_to_file
+            && self.capture_max_bytes == other.capture_max_bytes
+            && self.synchronous == other.synchronous
+            && self.deferred_serialization == other.deferred_serialization
+            && self.connections == other.connections
+            && self.ordering == other.ordering
+            && self.record_ttl == other.record_ttl
+            && self.flush_timeout == other.flush_timeout
+            && self.stats_interval == other.stats_interval
+            && self.priority_queue_level == other.priority_queue_level
+            && self.hooks == other.hooks
+            && self.fallback == other.fallback
+            && self.fallback_level == other.fallback_level
+            && self.windows_fields == other.windows_fields
+            && self.host_tags == other.host_tags
+            && self.priority_field == other.priority_field
+            && self.severity_field == other.severity_field
+            && self.level_field == other.level_field
+            && self.level_range == other.level_range
+            && self.clock_offset == other.clock_offset
+            && self.clock_offset_from_http == other.clock_offset_from_http
+            && same_encoder
+            && self.encoder_field == other.encoder_field
+            && self.short_message_template == other.short_message_template
+            && self.sanitize == other.sanitize
+            && self.empty_fields == other.empty_fields
+            && self.max_field_bytes == other.max_field_bytes
+            && self.full_message_chunk_bytes == other.full_message_chunk_bytes
+            && self.correlation_id == other.correlation_id
+            && self.redactions == other.redactions
+            && self.format == other.format
+            && self.protocol_version == other.protocol_version
+            && self.envelope_fields == other.envelope_fields
+            && self.strict == other.strict
+            && self.name == other.name
+            && self.log_config == other.log_config
+            && self.transport == other.transport
+    }
 }
 
 impl Default for BufferAppenderBuilder {
     fn default() -> BufferAppenderBuilder {
         BufferAppenderBuilder {
             level: Level::Info,
+            target_levels: TargetLevels::default(),
+            directives: None,
+            field_filter: FieldFilter::default(),
+            protocol: Protocol::default(),
             hostname: "127.0.0.1".to_string(),
             port: 12202,
             #[cfg(feature = "tls")]
             use_tls: true,
-            null_character: true,
-            buffer_size: Some(100),
-            additional_fields: {
-                let mut additional_fields = BTreeMap::new();
-                additional_fields.insert("pkg_name".into(), Value::String(env!("CARGO_PKG_NAME").into()));
-                additional_fields.insert("pkg_version".into(), Value::String(env!("CARGO_PKG_VERSION").into()));
-                additional_fields
-            },
+            #[cfg(feature = "tls")]
+            tls_pinned_cert_sha256: None,
+            #[cfg(feature = "tls")]
+            tls_verify_ca: true,
+            #[cfg(feature = "tls")]
+            tls_client_identity: None,
+            framing: Framing::default(),
+            http_path: DEFAULT_HTTP_PATH.to_string(),
+            http_authorization: None,
+            http_idle_timeout: None,
+            resolver: None,
+            compression_threshold: None,
+            batch: BatchPolicy::default(),
+            additional_fields: BTreeMap::new(),
+            secret_fields: BTreeMap::new(),
+            default_fields: true,
+            field_error: None,
+            dynamic_fields: DynamicFields::default(),
+            target_fields: TargetFields::default(),
+            enrichers: Vec::new(),
+            tracing_spans: false,
+            field_schema: FieldSchema::default(),
             connect_timeout: None,
             write_timeout: None,
+            max_memory_bytes: DEFAULT_MAX_MEMORY_BYTES,
+            queue_capacity: None,
+            overflow_policy: OverflowPolicy::default(),
+            delivery: Delivery::default(),
+            fold_window: None,
+            drop_stats_targets: DEFAULT_DROP_STATS_TARGETS,
+            error_report_interval: DEFAULT_ERROR_REPORT_INTERVAL,
+            capture_to_file: None,
+            capture_max_bytes: DEFAULT_CAPTURE_MAX_BYTES,
+            synchronous: false,
+            deferred_serialization: true,
+            connections: 1,
+            ordering: OrderingPolicy::default(),
+            record_ttl: None,
+            flush_timeout: None,
+            stats_interval: None,
+            priority_queue_level: LevelFilter::Error,
+            hooks: ConnectionHooks::default(),
+            fallback: Fallback::default(),
+            fallback_level: Level::Trace,
+            windows_fields: false,
+            host_tags: Vec::new(),
+            priority_field: false,
+            severity_field: None,
+            level_field: LevelField::default(),
+            level_range: (0, 7),
+            clock_offset: 0.0,
+            clock_offset_from_http: false,
+            encoder: None,
+            encoder_field: DEFAULT_ENCODER_FIELD.to_string(),
+            short_message_template: None,
+            sanitize: true,
+            empty_fields: EmptyFieldPolicy::default(),
+            max_field_bytes: None,
+            full_message_chunk_bytes: None,
+            correlation_id: false,
+            redactions: Vec::new(),
+            format: OutputFormat::default(),
+            protocol_version: GELF_VERSION.to_string(),
+            envelope_fields: BTreeMap::new(),
+            strict: true,
+            name: None,
+            log_config: false,
+            transport: None,
         }
     }
 }
@@ -111,6 +490,82 @@ impl BufferAppenderBuilder {
         self.level = level;
         self
     }
+    /// Sets the threshold for records whose target is `prefix` or one of its submodules,
+    /// overriding the appender level. The most specific prefix wins.
+    ///
+    /// This only applies to records log4rs hands over to the appender: a threshold more verbose
+    /// than the log4rs loggers has no effect.
+    pub fn set_target_level(mut self, prefix: &str, level: LevelFilter) -> BufferAppenderBuilder {
+        self.target_levels.insert(prefix, level);
+        self
+    }
+    /// Sets multiple target prefix thresholds at once, see
+    /// [`set_target_level`](#method.set_target_level).
+    pub fn extend_target_levels(mut self, target_levels: BTreeMap<String, LevelFilter>) -> BufferAppenderBuilder {
+        for (prefix, level) in target_levels {
+            self.target_levels.insert(&prefix, level);
+        }
+        self
+    }
+    /// Sets the thresholds from `env_logger` directives, as in `RUST_LOG`: `warn,my_app=debug`
+    /// lets the warnings through, and the debug records of `my_app` and its submodules. A bare
+    /// target enables all its records and a bare level applies to every target, overriding the
+    /// appender level. Directives take precedence over the
+    /// [`set_target_level`](#method.set_target_level) thresholds of the same prefix; message
+    /// filters (`/regex`) aren't supported.
+    pub fn set_directives(mut self, directives: &str) -> BufferAppenderBuilder {
+        self.directives = Some(directives.to_string());
+        self
+    }
+    /// Drops the records whose additional fields, from the record context or static, have all the
+    /// given values.
+    pub fn set_drop_if(mut self, fields: BTreeMap<String, Value>) -> BufferAppenderBuilder {
+        self.field_filter.set_drop_if(fields);
+        self
+    }
+    /// Only keeps the records whose additional fields, from the record context or static, have
+    /// all the given values.
+    pub fn set_only_if(mut self, fields: BTreeMap<String, Value>) -> BufferAppenderBuilder {
+        self.field_filter.set_only_if(fields);
+        self
+    }
+    /// Sets the protocol spoken with the remote server, TCP by default.
+    pub fn set_protocol(mut self, protocol: Protocol) -> BufferAppenderBuilder {
+        self.protocol = protocol;
+        self
+    }
+    /// Sets the path messages are posted to with the HTTP protocol, `/gelf` by default.
+    pub fn set_http_path(mut self, http_path: &str) -> BufferAppenderBuilder {
+        self.http_path = http_path.to_string();
+        self
+    }
+    /// Sends `authorization` as the `Authorization` header of the requests made with the HTTP
+    /// protocol, e.g. `Bearer <token>` for an input behind an authenticating proxy. It's left out
+    /// of [`describe`](#method.describe).
+    pub fn set_http_authorization(mut self, authorization: &str) -> BufferAppenderBuilder {
+        self.http_authorization = Some(Secret(authorization.to_string()));
+        self
+    }
+    /// Gzips the messages of at least `compression_threshold` bytes, `0` compressing them all. Only
+    /// used with the HTTP protocol; the statistics count the compressed messages and the bytes
+    /// saved.
+    pub fn set_compression_threshold(mut self, compression_threshold: Option<usize>) -> BufferAppenderBuilder {
+        self.compression_threshold = compression_threshold;
+        self
+    }
+    /// Resolves the hostname with `resolver` rather than the system resolver, e.g. to use another
+    /// DNS client or static mappings. The addresses it returns are tried in order, on the
+    /// configured port.
+    pub fn set_resolver<F>(mut self, resolver: F) -> BufferAppenderBuilder where F: Fn(&str) -> io::Result<Vec<SocketAddr>> + Send + Sync + 'static {
+        self.resolver = Some(Resolver(Arc::new(resolver)));
+        self
+    }
+    /// Sets how long an HTTP connection may stay unused before a new one is opened for the next
+    /// message. It should be below the idle timeout of the server and of the proxies in between.
+    pub fn set_http_idle_timeout(mut self, http_idle_timeout: Option<Duration>) -> BufferAppenderBuilder {
+        self.http_idle_timeout = http_idle_timeout;
+        self
+    }
     /// Sets the hostname of the remote server.
     pub fn set_hostname(mut self, hostname: &str) -> BufferAppenderBuilder {
         self.hostname = hostname.to_string();
@@ -127,20 +582,76 @@ impl BufferAppenderBuilder {
         self.use_tls = use_tls;
         self
     }
-    /// Adds a NUL byte (`\0`) after each entry.
-    pub fn set_null_character(mut self, null_character: bool) -> BufferAppenderBuilder {
-        self.null_character = null_character;
+    /// Pins the certificate of the remote server: the TLS handshake fails unless its SHA-256
+    /// fingerprint, in hexadecimal, is `fingerprint`. Bytes may be separated by colons, as
+    /// `openssl x509 -fingerprint -sha256` prints them.
+    #[cfg(feature = "tls")]
+    pub fn set_tls_pinned_cert_sha256(mut self, fingerprint: &str) -> BufferAppenderBuilder {
+        self.tls_pinned_cert_sha256 = Some(fingerprint.to_string());
+        self
+    }
+    /// Whether the certificate of the remote server must also be signed by a trusted CA and
+    /// match the hostname, the default. It can only be disabled along with a pinned certificate,
+    /// e.g. for a self-signed Graylog certificate.
+    #[cfg(feature = "tls")]
+    pub fn set_tls_verify_ca(mut self, tls_verify_ca: bool) -> BufferAppenderBuilder {
+        self.tls_verify_ca = tls_verify_ca;
+        self
+    }
+    /// Presents a client certificate to the remote server, for inputs requiring mutual TLS:
+    /// `certificate` is the PEM certificate chain, `key` the PEM PKCS #8 private key.
+    #[cfg(feature = "tls")]
+    pub fn set_tls_client_identity(mut self, certificate: &str, key: &str) -> BufferAppenderBuilder {
+        self.tls_client_identity = Some(Secret((certificate.to_string(), key.to_string())));
+        self
+    }
+    /// Adds a NUL byte (`\0`) after each entry, a newline is used otherwise. Shorthand for
+    /// [`set_framing`](#method.set_framing).
+    pub fn set_null_character(self, null_character: bool) -> BufferAppenderBuilder {
+        self.set_framing(if null_character { Framing::Null } else { Framing::Newline })
+    }
+    /// Sets how entries are delimited, by a NUL byte by default. Only used with the TCP protocol.
+    pub fn set_framing(mut self, framing: Framing) -> BufferAppenderBuilder {
+        self.framing = framing;
+        self
+    }
+    /// Former name of [`set_max_batch_records`](#method.set_max_batch_records), which only sets
+    /// the batch size: the number of records which may wait is set by
+    /// [`set_queue_capacity`](#method.set_queue_capacity).
+    #[deprecated(note = "use `set_max_batch_records` instead")]
+    pub fn set_buffer_size(self, buffer_size: Option<usize>) -> BufferAppenderBuilder {
+        self.set_max_batch_records(buffer_size.unwrap_or(DEFAULT_MAX_BATCH_RECORDS))
+    }
+    /// Sets the number of queued records which triggers a write to the remote server. It's also
+    /// the most records written at once.
+    pub fn set_max_batch_records(mut self, max_batch_records: usize) -> BufferAppenderBuilder {
+        self.batch.max_records = max_batch_records;
+        self
+    }
+    /// Sets the size in bytes of queued records which triggers a write to the remote server. It's
+    /// also the largest write, unless a single record is bigger.
+    pub fn set_max_batch_bytes(mut self, max_batch_bytes: usize) -> BufferAppenderBuilder {
+        self.batch.max_bytes = max_batch_bytes;
         self
     }
-    /// Sets the upperbound limit on the number of records that can be placed in the buffer, once
-    /// this size has been reached, the buffer will be sent to the remote server.
-    pub fn set_buffer_size(mut self, buffer_size: Option<usize>) -> BufferAppenderBuilder {
-        self.buffer_size = buffer_size;
+    /// Sets how long a record may wait for its batch to fill up before it's written anyway.
+    pub fn set_max_batch_delay(mut self, max_batch_delay: Duration) -> BufferAppenderBuilder {
+        self.batch.max_delay = max_batch_delay;
         self
     }
-    /// Adds an additional data which will be appended to each log entry.
-    pub fn put_additional_field(mut self, key: &str, value: Value) -> BufferAppenderBuilder {
-        self.additional_fields.insert(key.to_string(), value);
+    /// Adds an additional data which will be appended to each log entry. Besides a
+    /// [`Value`](enum.Value.html), it can be anything serializable, e.g. a `serde_json::Value`.
+    ///
+    /// A value which fails to serialize makes [`build`](#method.build) fail.
+    pub fn put_additional_field<V: Serialize>(mut self, key: &str, value: V) -> BufferAppenderBuilder {
+        match serde_value::to_value(value) {
+            Ok(value) => {
+                self.additional_fields.insert(key.to_string(), value);
+            }
+            Err(err) => {
+                self.field_error.get_or_insert_with(|| format!("{}: {}", key, err));
+            }
+        }
         self
     }
     /// Adds multiple additional data which will be appended to each log entry.
@@ -148,6 +659,82 @@ impl BufferAppenderBuilder {
         self.additional_fields.extend(additional_fields);
         self
     }
+    /// Adds an additional field holding a secret, e.g. the ingestion token of a hosted Graylog.
+    /// Its value is left out of [`describe`](#method.describe) and [`diff`](#method.diff).
+    pub fn put_secret_field(mut self, key: &str, value: &str) -> BufferAppenderBuilder {
+        self.secret_fields.insert(key.to_string(), Secret(value.to_string()));
+        self
+    }
+    /// Whether to add the `pkg_name` and `pkg_version` fields, enabled by default. They hold the
+    /// `LOG4RS_GELF_PKG_NAME` and `LOG4RS_GELF_PKG_VERSION` environment variables when set, the
+    /// name and version of this crate otherwise. Additional fields of the same names take
+    /// precedence.
+    pub fn set_default_fields(mut self, default_fields: bool) -> BufferAppenderBuilder {
+        self.default_fields = default_fields;
+        self
+    }
+    /// Adds an additional field whose value is computed by `provider` for each log entry, such as
+    /// the current memory usage. Fields of the record take precedence.
+    ///
+    /// Providers only run for the records which pass the level and target thresholds and the
+    /// [`set_drop_if`](#method.set_drop_if) and [`set_only_if`](#method.set_only_if) filters,
+    /// unless a filter reads their field or enrichers are set, which need it first.
+    pub fn put_dynamic_field<F>(mut self, key: &str, provider: F) -> BufferAppenderBuilder where F: Fn() -> Value + Send + Sync + 'static {
+        self.dynamic_fields.insert(key, Arc::new(provider));
+        self
+    }
+    /// Appends a step to the enrichment pipeline, run in order on each message once its record,
+    /// dynamic and target fields are known:
+    ///
+    /// * `hostname` adds the name of the machine as `_hostname`, even when the `host` of the
+    ///   message is overridden;
+    /// * `thread` adds the name of the logging thread, or its id, as `_thread`;
+    /// * `k8s` adds `_k8s_pod`, `_k8s_namespace` and `_k8s_node`, from the `POD_NAME`,
+    ///   `POD_NAMESPACE` and `NODE_NAME` environment variables of the Kubernetes downward API;
+    /// * `env:VARIABLE` adds an environment variable, named in lowercase, e.g. `_aws_region`;
+    /// * `redact:detector,...` redacts the fields added so far with the given
+    ///   [`Redaction`](enum.Redaction.html) detectors, e.g. `redact:bearer_tokens`.
+    ///
+    /// Fields already set are left alone. Environment variables are read when the appender is
+    /// built, and an unknown step makes [`build`](#method.build) fail. Applications can add their
+    /// own with [`register_enricher`](fn.register_enricher.html).
+    pub fn add_enricher(mut self, enricher: &str) -> BufferAppenderBuilder {
+        self.enrichers.push(enricher.to_string());
+        self
+    }
+    /// Recovers the span context of the records `tracing` logs through its `log` feature: the
+    /// records of the lifecycle of its spans are followed instead of being sent, and the events
+    /// logged in a span get its `_span_name`, `_span_id` and fields as `_span_<field>`, ahead of
+    /// the enrichers. The lifecycle records are logged with the `tracing::span` and
+    /// `tracing::span::active` targets, at the trace level, and with the span target for spans
+    /// with fields: the log4rs loggers must let them through. Disabled by default.
+    pub fn set_tracing_spans(mut self, tracing_spans: bool) -> BufferAppenderBuilder {
+        self.tracing_spans = tracing_spans;
+        self
+    }
+    /// Sets the names of the metadata fields, those of this crate by default. Field filters see
+    /// the renamed fields.
+    pub fn set_field_schema(mut self, field_schema: FieldSchema) -> BufferAppenderBuilder {
+        self.field_schema = field_schema;
+        self
+    }
+    /// Adds an additional field to the records whose target is `pattern` or one of its submodules,
+    /// such as the team owning a module. `pattern` may end with `::*`, and `*` matches every
+    /// target. The most specific pattern wins, and fields of the record take precedence.
+    pub fn put_target_field(mut self, pattern: &str, key: &str, value: Value) -> BufferAppenderBuilder {
+        self.target_fields.insert(pattern, key, value);
+        self
+    }
+    /// Adds multiple per-target additional fields at once, see
+    /// [`put_target_field`](#method.put_target_field).
+    pub fn extend_target_fields(mut self, target_fields: BTreeMap<String, BTreeMap<String, Value>>) -> BufferAppenderBuilder {
+        for (pattern, fields) in target_fields {
+            for (key, value) in fields {
+                self.target_fields.insert(&pattern, &key, value);
+            }
+        }
+        self
+    }
     /// set the connection timeout
     pub fn set_connect_timeout(mut self, connect_timeout: Option<Duration>) -> BufferAppenderBuilder {
         self.connect_timeout = connect_timeout;
@@ -158,36 +745,935 @@ impl BufferAppenderBuilder {
         self.write_timeout = write_timeout;
         self
     }
+    /// Sets a hard cap on the memory held by records waiting to be sent, including the batch
+    /// being written. Once reached, the overflow policy decides what happens to new records.
+    pub fn set_max_memory_bytes(mut self, max_memory_bytes: usize) -> BufferAppenderBuilder {
+        self.max_memory_bytes = max_memory_bytes;
+        self
+    }
+    /// Caps the number of records waiting to be sent, the batch being written aside, whatever
+    /// their size. Once reached, the overflow policy decides what happens to new records. It's
+    /// independent of the [batch size](#method.set_max_batch_records), which only decides how
+    /// many records are written at once. Only the memory budget applies by default.
+    pub fn set_queue_capacity(mut self, queue_capacity: Option<usize>) -> BufferAppenderBuilder {
+        self.queue_capacity = queue_capacity;
+        self
+    }
+    /// Sets what to do with records which don't fit in the memory budget or the queue capacity.
+    pub fn set_overflow_policy(mut self, overflow_policy: OverflowPolicy) -> BufferAppenderBuilder {
+        self.overflow_policy = overflow_policy;
+        self
+    }
+    /// Sets whether logging returns once the record is queued, the default, or blocks until it's
+    /// written to the transport. Guaranteed deliveries wait for at most the
+    /// [flush timeout](#method.set_flush_timeout) if any, and fail if the record is dropped.
+    pub fn set_delivery(mut self, delivery: Delivery) -> BufferAppenderBuilder {
+        self.delivery = delivery;
+        self
+    }
+    /// Folds the lines of multi-line stack traces, such as Java or Python ones logged line by line
+    /// from the output of a subprocess, into the `full_message` of the record they follow. Lines
+    /// which are indented, or start with `at ` or `Caused by:`, continue the previous record of
+    /// the same target when logged within `fold_window` of it. Each message is then held back
+    /// for up to this window. Disabled by default.
+    pub fn set_fold_window(mut self, fold_window: Option<Duration>) -> BufferAppenderBuilder {
+        self.fold_window = fold_window;
+        self
+    }
+    /// Sets how many of the targets shedding the most records are reported in
+    /// [`Stats::dropped_by_target`](struct.Stats.html#structfield.dropped_by_target).
+    pub fn set_drop_stats_targets(mut self, drop_stats_targets: usize) -> BufferAppenderBuilder {
+        self.drop_stats_targets = drop_stats_targets;
+        self
+    }
+    /// Sets how long transport errors identical to the last one reported are held back, so that
+    /// a long outage doesn't flood the standard error or the error channel: the first error is
+    /// reported at once, the next one after the interval along with the number held back. A
+    /// minute by default, zero reports every error.
+    pub fn set_error_report_interval(mut self, interval: Duration) -> BufferAppenderBuilder {
+        self.error_report_interval = interval;
+        self
+    }
+    /// Writes a copy of every payload handed over to the transport to a local file, one JSON line
+    /// each, to check what the server is sent when it fails to parse messages. The file is
+    /// truncated when the appender is built. Meant for debugging only: disabled by default.
+    pub fn set_capture_to_file(mut self, path: Option<PathBuf>) -> BufferAppenderBuilder {
+        self.capture_to_file = path;
+        self
+    }
+    /// Sets the size limit of the [capture file](#method.set_capture_to_file), 16 MiB by
+    /// default. The capture stops once it's reached, the file keeping the first payloads.
+    pub fn set_capture_max_bytes(mut self, capture_max_bytes: u64) -> BufferAppenderBuilder {
+        self.capture_max_bytes = capture_max_bytes;
+        self
+    }
+    /// Writes each record to the transport from the logging thread, instead of a background
+    /// thread, for environments where creating threads isn't allowed. Logging then blocks for
+    /// the time of the write, bounded by the write timeout, and a record which can't be written
+    /// is dropped rather than retried. The batch settings don't apply. Disabled by default.
+    pub fn set_synchronous(mut self, synchronous: bool) -> BufferAppenderBuilder {
+        self.synchronous = synchronous;
+        self
+    }
+    /// Whether the messages are serialized to JSON by the background thread, as it takes them off
+    /// the queue, rather than by the logging thread. The logging thread still builds the message,
+    /// and formats its arguments; the size of a queued message is then estimated, for the memory
+    /// budget and the batches. Messages held back by the [fold window](#method.set_fold_window),
+    /// copied to the [fallback](#method.set_fallback), or sent in synchronous mode are serialized
+    /// when logged. Enabled by default.
+    pub fn set_deferred_serialization(mut self, deferred_serialization: bool) -> BufferAppenderBuilder {
+        self.deferred_serialization = deferred_serialization;
+        self
+    }
+    /// Opens a pool of `connections` to the server when the appender is built, each written to
+    /// by a thread of its own, so that a slow or failing connection doesn't hold the batches
+    /// back. Batches are then no longer sent in order. A single connection, opened with the
+    /// first batch, by default. With the HTTP protocol, this is the size of the pool of
+    /// kept-alive connections the messages are posted on.
+    pub fn set_connections(mut self, connections: usize) -> BufferAppenderBuilder {
+        self.connections = connections;
+        self
+    }
+    /// Sets which records the [pool of connections](#method.set_connections) sends in the order
+    /// they were logged: those of each logging thread, all of them, one batch at a time, or none
+    /// by default. Ordered records don't skip ahead of the others either, whatever their
+    /// priority.
+    pub fn set_ordering(mut self, ordering: OrderingPolicy) -> BufferAppenderBuilder {
+        self.ordering = ordering;
+        self
+    }
+    /// Sets how long a record may wait to be sent, e.g. during an outage. Older records are dropped
+    /// and counted as such, rather than sent long after the fact. Unlimited by default.
+    pub fn set_record_ttl(mut self, record_ttl: Option<Duration>) -> BufferAppenderBuilder {
+        self.record_ttl = record_ttl;
+        self
+    }
+    /// Makes [`flush`](struct.BufferAppender.html#method.flush) block until the records queued
+    /// so far were delivered, or dropped, for at most `flush_timeout`. By default, flushing only
+    /// wakes up the background sender.
+    pub fn set_flush_timeout(mut self, flush_timeout: Option<Duration>) -> BufferAppenderBuilder {
+        self.flush_timeout = flush_timeout;
+        self
+    }
+    /// Sets a callback invoked the first time a connection to the remote server is established.
+    ///
+    /// Connection callbacks run on the background sender thread and should return quickly.
+    pub fn on_connect<F>(mut self, callback: F) -> BufferAppenderBuilder where F: Fn() + Send + Sync + 'static {
+        self.hooks.on_connect = Some(Arc::new(callback));
+        self
+    }
+    /// Sets a callback invoked with the cause when an established connection is lost.
+    pub fn on_disconnect<F>(mut self, callback: F) -> BufferAppenderBuilder where F: Fn(&Error) + Send + Sync + 'static {
+        self.hooks.on_disconnect = Some(Arc::new(callback));
+        self
+    }
+    /// Sets a callback invoked when the connection is established again after being lost.
+    pub fn on_reconnect<F>(mut self, callback: F) -> BufferAppenderBuilder where F: Fn() + Send + Sync + 'static {
+        self.hooks.on_reconnect = Some(Arc::new(callback));
+        self
+    }
+    /// Sets a callback invoked after each batch was delivered, with its number of records, its
+    /// size in bytes and how long writing it took.
+    pub fn on_batch_sent<F>(mut self, callback: F) -> BufferAppenderBuilder where F: Fn(usize, usize, Duration) + Send + Sync + 'static {
+        self.hooks.on_batch_sent = Some(Arc::new(callback));
+        self
+    }
+    /// Writes the diagnostics of the appender, such as transport errors, to another appender,
+    /// e.g. a console or file one, rather than straight to the standard error.
+    pub fn set_diagnostics(mut self, appender: Box<dyn Append>) -> BufferAppenderBuilder {
+        self.hooks.diagnostics = Diagnostics(Some(Arc::from(appender)));
+        self
+    }
+    /// Sends the errors of the transport to `channel`, for the application to alert on them,
+    /// instead of writing them to the diagnostics. Errors sent after the receiver is gone are
+    /// lost.
+    pub fn set_error_channel(mut self, channel: mpsc::Sender<Arc<Error>>) -> BufferAppenderBuilder {
+        let channel = Mutex::new(channel);
+        self.hooks.on_error = Some(Arc::new(move |err: &Arc<Error>| {
+            let _ = channel.lock().unwrap_or_else(PoisonError::into_inner).send(err.clone());
+        }));
+        self
+    }
+    /// Same as [`set_error_channel`](#method.set_error_channel), with a channel whose errors can
+    /// be awaited, created by [`error_channel`](fn.error_channel.html).
+    #[cfg(feature = "async")]
+    pub fn set_async_error_channel(mut self, channel: ErrorSender) -> BufferAppenderBuilder {
+        self.hooks.on_error = Some(Arc::new(move |err: &Arc<Error>| channel.send(err.clone())));
+        self
+    }
+    /// Sets a local stream records are also written to while the remote server is unreachable.
+    /// Nothing is written there once the connection is back.
+    pub fn set_fallback(mut self, fallback: Fallback) -> BufferAppenderBuilder {
+        self.fallback = fallback;
+        self
+    }
+    /// Sets the least severe level written to the fallback stream.
+    pub fn set_fallback_level(mut self, fallback_level: Level) -> BufferAppenderBuilder {
+        self.fallback_level = fallback_level;
+        self
+    }
+    /// Adds the `windows_session_id` and `windows_user` fields of the process to every message.
+    /// Ignored on other platforms.
+    pub fn set_windows_fields(mut self, windows_fields: bool) -> BufferAppenderBuilder {
+        self.windows_fields = windows_fields;
+        self
+    }
+    /// Adds the given tags to every message, as an array in the `_tags` field, for Graylog
+    /// streams routing on tags rather than hostnames. A `tags` field of the record or an
+    /// additional field takes precedence.
+    pub fn set_host_tags(mut self, host_tags: Vec<String>) -> BufferAppenderBuilder {
+        self.host_tags = host_tags;
+        self
+    }
+    /// Also sends the syslog severity of each message as a `_priority` field, as journald does.
+    pub fn set_priority_field(mut self, priority_field: bool) -> BufferAppenderBuilder {
+        self.priority_field = priority_field;
+        self
+    }
+    /// Also sends the syslog severity of each message as the given additional field, such as
+    /// `_severity`.
+    pub fn set_severity_field(mut self, severity_field: Option<&str>) -> BufferAppenderBuilder {
+        self.severity_field = severity_field.map(field_name);
+        self
+    }
+    /// With [`LevelField::Omit`](enum.LevelField.html), leaves the `level` out of the messages,
+    /// for pipelines rejecting some level values; the severity can still be sent with
+    /// [`set_severity_field`](#method.set_severity_field).
+    pub fn set_level_field(mut self, level_field: LevelField) -> BufferAppenderBuilder {
+        self.level_field = level_field;
+        self
+    }
+    /// Brings the syslog severity of each message within `lowest` and `highest`, 0 (emergency)
+    /// to 7 (debug) by default: with `2` to `6`, debug messages are sent at 6 and alerts at 2.
+    pub fn set_level_range(mut self, lowest: u32, highest: u32) -> BufferAppenderBuilder {
+        self.level_range = (lowest, highest);
+        self
+    }
+    /// Adds `offset` to the time of the logging calls sent as `timestamp`, for a host whose clock
+    /// is known to run behind and can't be fixed. Timestamps given by the records are kept.
+    pub fn set_clock_offset(mut self, offset: Duration) -> BufferAppenderBuilder {
+        self.clock_offset = offset.as_secs_f64();
+        self
+    }
+    /// Takes `offset` from the time of the logging calls, for a host whose clock runs ahead.
+    pub fn set_clock_ahead(mut self, offset: Duration) -> BufferAppenderBuilder {
+        self.clock_offset = -offset.as_secs_f64();
+        self
+    }
+    /// Derives the clock offset from the `Date` header of each HTTP answer, the clock offset set
+    /// being used until the first one. Only used with the HTTP protocol; the header is to the
+    /// second, so that smaller skews aren't corrected.
+    pub fn set_clock_offset_from_http(mut self, clock_offset_from_http: bool) -> BufferAppenderBuilder {
+        self.clock_offset_from_http = clock_offset_from_http;
+        self
+    }
+    /// Sets a log4rs encoder, such as a `PatternEncoder`, whose rendition of each record is sent
+    /// along with the structured fields.
+    pub fn set_encoder(mut self, encoder: Box<dyn Encode>) -> BufferAppenderBuilder {
+        self.encoder = Some(Arc::from(encoder));
+        self
+    }
+    /// Sets the field holding the output of the encoder, `full_message` by default. Other names
+    /// are sent as additional fields.
+    pub fn set_encoder_field(mut self, encoder_field: &str) -> BufferAppenderBuilder {
+        self.encoder_field = encoder_field.to_string();
+        self
+    }
+    /// Sets a pattern such as `{target} - {message}` building the `short_message` of each record.
+    ///
+    /// Placeholders are `message`, `level`, `target`, `module_path`, `file` and `line`; `{{` and
+    /// `}}` stand for literal braces.
+    pub fn set_short_message_template(mut self, template: &str) -> BufferAppenderBuilder {
+        self.short_message_template = Some(template.to_string());
+        self
+    }
+    /// Escapes NUL characters, which frame the messages of the GELF TCP input, as `\u{0}`, and
+    /// replaces invalid UTF-8 in the messages and their fields. Tabs, carriage returns and other
+    /// control characters are kept. Enabled by default.
+    pub fn set_sanitize(mut self, sanitize: bool) -> BufferAppenderBuilder {
+        self.sanitize = sanitize;
+        self
+    }
+    /// Sets how additional fields holding a null value or an empty string are sent, as given by
+    /// default.
+    pub fn set_empty_field_policy(mut self, empty_fields: EmptyFieldPolicy) -> BufferAppenderBuilder {
+        self.empty_fields = empty_fields;
+        self
+    }
+    /// Sets the longest string value of an additional field, in bytes. Longer values are cut and
+    /// end with `…[+N bytes]`, `N` being the number of bytes left out, the mark counting in the
+    /// limit. Unlimited by default.
+    pub fn set_max_field_bytes(mut self, max_field_bytes: Option<usize>) -> BufferAppenderBuilder {
+        self.max_field_bytes = max_field_bytes;
+        self
+    }
+    /// Splits a `full_message` longer than `full_message_chunk_bytes` across several messages
+    /// rather than letting the server truncate or reject it. They share a `_correlation_id` and
+    /// carry `_chunk_index` and `_chunk_total`; `max_field_bytes` no longer cuts `full_message`.
+    /// Can't be combined with `fold_window`.
+    pub fn set_full_message_chunk_bytes(mut self, full_message_chunk_bytes: Option<usize>) -> BufferAppenderBuilder {
+        self.full_message_chunk_bytes = full_message_chunk_bytes;
+        self
+    }
+    /// Sends a `_correlation_id` drawn once per process with every message, unless the record or
+    /// a [`correlation_scope`](fn.correlation_scope.html) gives one.
+    pub fn set_correlation_id(mut self, correlation_id: bool) -> BufferAppenderBuilder {
+        self.correlation_id = correlation_id;
+        self
+    }
+    /// Replaces the secret-looking values found by the given detectors with `[REDACTED]`, in the
+    /// `short_message` and the string values of the additional fields. None by default.
+    pub fn set_redactions(mut self, redactions: &[Redaction]) -> BufferAppenderBuilder {
+        self.redactions = redactions.to_vec();
+        self
+    }
+    /// Sets the layout of the messages, for receivers other than Graylog: plain JSON lines or
+    /// Logstash events, over the same transports. GELF by default.
+    pub fn set_format(mut self, format: OutputFormat) -> BufferAppenderBuilder {
+        self.format = format;
+        self
+    }
+    /// Sets the level from which records skip ahead of the others, so that they get out promptly
+    /// even when a flood of less severe records backs the queue up. `Error` by default, `Off`
+    /// keeps records in order.
+    pub fn set_priority_queue_level(mut self, priority_queue_level: LevelFilter) -> BufferAppenderBuilder {
+        self.priority_queue_level = priority_queue_level;
+        self
+    }
+    /// Sends the statistics of the appender every `stats_interval` as a message of its own, so
+    /// that the health of the pipeline shows in Graylog: `_sent`, `_dropped`, `_queue_depth`,
+    /// `_reconnects` and the message sizes, flagged `_self_monitoring: true`. Disabled by default.
+    pub fn set_stats_interval(mut self, stats_interval: Option<Duration>) -> BufferAppenderBuilder {
+        self.stats_interval = stats_interval;
+        self
+    }
+    /// Sets the `version` of the GELF messages, for receivers implementing a superset of GELF.
+    /// `1.1` by default; other versions require disabling [strict validation](#method.set_strict).
+    pub fn set_protocol_version(mut self, version: &str) -> BufferAppenderBuilder {
+        self.protocol_version = version.to_string();
+        self
+    }
+    /// Adds a top-level extension field to every GELF message, named as given rather than with a
+    /// leading underscore, for receivers implementing a superset of GELF. Requires disabling
+    /// [strict validation](#method.set_strict).
+    ///
+    /// A value which fails to serialize makes [`build`](#method.build) fail.
+    pub fn put_envelope_field<V: Serialize>(mut self, key: &str, value: V) -> BufferAppenderBuilder {
+        match serde_value::to_value(value) {
+            Ok(value) => {
+                self.envelope_fields.insert(key.to_string(), value);
+            }
+            Err(err) => {
+                self.field_error.get_or_insert_with(|| format!("{}: {}", key, err));
+            }
+        }
+        self
+    }
+    /// Adds multiple top-level extension fields, as [`put_envelope_field`](#method.put_envelope_field).
+    pub fn extend_envelope_fields(mut self, envelope_fields: BTreeMap<String, Value>) -> BufferAppenderBuilder {
+        self.envelope_fields.extend(envelope_fields);
+        self
+    }
+    /// Whether the messages must follow the GELF 1.1 specification, rejecting another protocol
+    /// version and envelope fields. Enabled by default.
+    pub fn set_strict(mut self, strict: bool) -> BufferAppenderBuilder {
+        self.strict = strict;
+        self
+    }
+    /// Names the appender, so that its [`GelfHandle`](struct.GelfHandle.html) can be looked up
+    /// with [`handle`](fn.handle.html) while it's alive. Unnamed by default.
+    pub fn set_name(mut self, name: &str) -> BufferAppenderBuilder {
+        self.name = Some(name.to_string());
+        self
+    }
+    /// Writes the effective settings to the standard error when the appender is built, as given
+    /// by [`describe`](#method.describe). Disabled by default.
+    pub fn set_log_config(mut self, log_config: bool) -> BufferAppenderBuilder {
+        self.log_config = log_config;
+        self
+    }
+    /// Describes the effective settings on a single line, as `key=value` pairs named after the
+    /// YAML keys. Values of fields whose name looks like a secret (`password`, `token`, ...) and
+    /// the query string of the HTTP path are masked.
+    pub fn describe(&self) -> String {
+        let settings: Vec<String> = self.settings().into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        settings.join(" ")
+    }
+    /// Lists the settings which differ from `other`, as `key: ours -> theirs`, masked as in
+    /// [`describe`](#method.describe). Helps tracking down environment-specific
+    /// misconfigurations.
+    pub fn diff(&self, other: &BufferAppenderBuilder) -> Vec<String> {
+        self.settings().into_iter()
+            .zip(other.settings())
+            .filter(|(ours, theirs)| ours.1 != theirs.1)
+            .map(|(ours, theirs)| format!("{}: {} -> {}", ours.0, ours.1, theirs.1))
+            .collect()
+    }
+    fn settings(&self) -> Vec<(&'static str, String)> {
+        let settings = vec![
+            ("level", self.level.to_string()),
+            ("target_levels", list(self.target_levels.rules().iter()
+                .map(|(prefix, level)| format!("{}={}", prefix, level)))),
+            ("directives", optional(&self.directives)),
+            ("protocol", format!("{:?}", self.protocol).to_lowercase()),
+            ("hostname", self.hostname.clone()),
+            ("port", self.port.to_string()),
+            ("framing", format!("{:?}", self.framing).to_lowercase()),
+            ("http_path", match self.http_path.find('?') {
+                Some(query) => format!("{}?***", &self.http_path[..query]),
+                None => self.http_path.clone(),
+            }),
+            ("http_authorization", secret(&self.http_authorization)),
+            ("http_idle_timeout", optional(&self.http_idle_timeout)),
+            ("resolver", self.resolver.is_some().to_string()),
+            ("compression_threshold", optional(&self.compression_threshold)),
+            ("max_batch_records", self.batch.max_records.to_string()),
+            ("max_batch_bytes", self.batch.max_bytes.to_string()),
+            ("max_batch_delay", format!("{:?}", self.batch.max_delay)),
+            ("additional_fields", fields(self.additional_fields.iter())),
+            ("secret_fields", list(self.secret_fields.keys().map(|key| format!("{}=***", key)))),
+            ("default_fields", self.default_fields.to_string()),
+            ("dynamic_fields", list(self.dynamic_fields.keys().map(str::to_string))),
+            ("target_fields", list(self.target_fields.rules().iter()
+                .map(|(prefix, target_fields)| format!("{}={}", prefix, fields(target_fields.iter().map(|(key, value)| (key, value))))))),
+            ("enrichers", list(self.enrichers.iter().cloned())),
+            ("tracing_spans", self.tracing_spans.to_string()),
+            ("schema", format!("{:?}", self.field_schema).to_lowercase()),
+            ("drop_if", fields(self.field_filter.drop_if().iter().map(|(key, value)| (key, value)))),
+            ("only_if", fields(self.field_filter.only_if().iter().map(|(key, value)| (key, value)))),
+            ("connect_timeout", optional(&self.connect_timeout)),
+            ("write_timeout", optional(&self.write_timeout)),
+            ("max_memory_bytes", self.max_memory_bytes.to_string()),
+            ("queue_capacity", optional(&self.queue_capacity)),
+            ("overflow_policy", format!("{:?}", self.overflow_policy)),
+            ("delivery", format!("{:?}", self.delivery)),
+            ("fold_window", optional(&self.fold_window)),
+            ("drop_stats_targets", self.drop_stats_targets.to_string()),
+            ("error_report_interval", format!("{:?}", self.error_report_interval)),
+            ("capture_to_file", optional(&self.capture_to_file)),
+            ("capture_max_bytes", self.capture_max_bytes.to_string()),
+            ("synchronous", self.synchronous.to_string()),
+            ("deferred_serialization", self.deferred_serialization.to_string()),
+            ("connections", self.connections.to_string()),
+            ("ordering", format!("{:?}", self.ordering)),
+            ("record_ttl", optional(&self.record_ttl)),
+            ("flush_timeout", optional(&self.flush_timeout)),
+            ("stats_interval", optional(&self.stats_interval)),
+            ("priority_queue_level", self.priority_queue_level.to_string()),
+            ("fallback", format!("{:?}", self.fallback)),
+            ("fallback_level", self.fallback_level.to_string()),
+            ("windows_fields", self.windows_fields.to_string()),
+            ("host_tags", list(self.host_tags.iter().cloned())),
+            ("priority_field", self.priority_field.to_string()),
+            ("severity_field", optional(&self.severity_field)),
+            ("level_field", format!("{:?}", self.level_field)),
+            ("level_range", format!("{}-{}", self.level_range.0, self.level_range.1)),
+            ("clock_offset", self.clock_offset.to_string()),
+            ("clock_offset_from_http", self.clock_offset_from_http.to_string()),
+            ("encoder", self.encoder.is_some().to_string()),
+            ("encoder_field", self.encoder_field.clone()),
+            ("short_message_template", optional(&self.short_message_template)),
+            ("sanitize", self.sanitize.to_string()),
+            ("empty_field_policy", format!("{:?}", self.empty_fields)),
+            ("max_field_bytes", optional(&self.max_field_bytes)),
+            ("full_message_chunk_bytes", optional(&self.full_message_chunk_bytes)),
+            ("correlation_id", self.correlation_id.to_string()),
+            ("redact", list(self.redactions.iter().map(|redaction| format!("{:?}", redaction)))),
+            ("format", format!("{:?}", self.format)),
+            ("protocol_version", self.protocol_version.clone()),
+            ("envelope_fields", fields(self.envelope_fields.iter())),
+            ("strict", self.strict.to_string()),
+            ("name", optional(&self.name)),
+            ("custom_transport", self.transport.is_some().to_string()),
+        ];
+        #[cfg(feature = "tls")]
+        let settings = settings.into_iter().chain(vec![
+            ("use_tls", self.use_tls.to_string()),
+            ("tls_pinned_cert_sha256", optional(&self.tls_pinned_cert_sha256)),
+            ("tls_verify_ca", self.tls_verify_ca.to_string()),
+            ("tls_client_identity", secret(&self.tls_client_identity)),
+        ]).collect();
+        settings
+    }
+    /// Lists every invalid setting, rather than failing later at send time.
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        if self.transport.as_ref().is_some_and(TransportSlot::is_taken) {
+            problems.push(TRANSPORT_TAKEN.to_string());
+        }
+        if self.transport.is_none() {
+            if self.hostname.trim().is_empty() {
+                problems.push("hostname is empty".to_string());
+            }
+            if self.port == 0 {
+                problems.push("port is 0".to_string());
+            }
+            #[cfg(feature = "http")]
+            if self.protocol == Protocol::Http && !self.http_path.starts_with('/') {
+                problems.push(format!("http_path {:?} doesn't start with /", self.http_path));
+            }
+            if self.http_authorization.as_ref().is_some_and(|Secret(authorization)| authorization.contains(['\r', '\n'])) {
+                problems.push("http_authorization holds a line break".to_string());
+            }
+            if self.http_idle_timeout == Some(Duration::from_secs(0)) {
+                problems.push("http_idle_timeout is 0".to_string());
+            }
+            if self.connect_timeout == Some(Duration::from_secs(0)) {
+                problems.push("connect_timeout is 0".to_string());
+            }
+            if self.write_timeout == Some(Duration::from_secs(0)) {
+                problems.push("write_timeout is 0".to_string());
+            }
+            #[cfg(feature = "tls")]
+            {
+                if let Some(fingerprint) = &self.tls_pinned_cert_sha256 {
+                    if parse_fingerprint(fingerprint).is_none() {
+                        problems.push(format!("tls_pinned_cert_sha256 {:?} isn't a SHA-256 fingerprint", fingerprint));
+                    }
+                } else if !self.tls_verify_ca {
+                    problems.push("tls_verify_ca is disabled without tls_pinned_cert_sha256".to_string());
+                }
+                if let Some(Secret((certificate, key))) = &self.tls_client_identity {
+                    if native_tls::Identity::from_pkcs8(certificate.as_bytes(), key.as_bytes()).is_err() {
+                        problems.push("tls_client_cert and tls_client_key aren't a PEM certificate and PKCS #8 key".to_string());
+                    }
+                }
+            }
+        }
+        if self.batch.max_records == 0 {
+            problems.push("max_batch_records is 0".to_string());
+        }
+        if self.batch.max_bytes == 0 {
+            problems.push("max_batch_bytes is 0".to_string());
+        }
+        if self.queue_capacity == Some(0) {
+            problems.push("queue_capacity is 0".to_string());
+        }
+        if self.capture_to_file.is_some() && self.capture_max_bytes == 0 {
+            problems.push("capture_max_bytes is 0".to_string());
+        }
+        if let Some(Err(problem)) = self.directives.as_deref().map(filter::parse_directives) {
+            problems.push(problem);
+        }
+        for enricher in &self.enrichers {
+            if let Err(problem) = enrich::create(enricher, "") {
+                problems.push(problem);
+            }
+        }
+        if self.level_range.0 > self.level_range.1 || self.level_range.1 > 7 {
+            problems.push(format!("level_range {}-{} isn't within 0-7", self.level_range.0, self.level_range.1));
+        }
+        if !self.clock_offset.is_finite() {
+            problems.push("clock_offset isn't a number".to_string());
+        }
+        #[cfg(feature = "http")]
+        let http = self.transport.is_none() && self.protocol == Protocol::Http;
+        #[cfg(not(feature = "http"))]
+        let http = false;
+        if self.clock_offset_from_http && !http {
+            problems.push("clock_offset_from_http requires the HTTP protocol".to_string());
+        }
+        if self.severity_field.as_deref() == Some("_") {
+            problems.push("severity_field is empty".to_string());
+        }
+        if self.stats_interval == Some(Duration::from_secs(0)) {
+            problems.push("stats_interval is 0".to_string());
+        }
+        if self.connections == 0 {
+            problems.push("connections is 0".to_string());
+        } else if self.connections > 1 && self.synchronous {
+            problems.push("synchronous mode writes to a single connection".to_string());
+        } else if self.connections > 1 && self.transport.is_some() {
+            problems.push("a transport given by the application is a single connection".to_string());
+        }
+        if self.synchronous && self.stats_interval.is_some() {
+            problems.push("stats_interval requires a thread, which synchronous mode can't start".to_string());
+        }
+        if self.synchronous && self.fold_window.is_some() {
+            problems.push("fold_window requires a thread, which synchronous mode can't start".to_string());
+        }
+        if self.full_message_chunk_bytes == Some(0) {
+            problems.push("full_message_chunk_bytes is 0".to_string());
+        } else if self.full_message_chunk_bytes.is_some() && self.fold_window.is_some() {
+            problems.push("fold_window appends to full_message, which full_message_chunk_bytes splits beforehand".to_string());
+        }
+        if self.fold_window == Some(Duration::from_secs(0)) {
+            problems.push("fold_window is 0".to_string());
+        } else if self.fold_window.is_some() && self.delivery == Delivery::Guaranteed {
+            problems.push("fold_window holds messages back, which guaranteed delivery can't".to_string());
+        }
+        if self.protocol_version.trim().is_empty() {
+            problems.push("protocol_version is empty".to_string());
+        } else if self.strict && self.protocol_version != GELF_VERSION {
+            problems.push(format!("protocol_version {:?} requires strict: false", self.protocol_version));
+        }
+        if self.strict && !self.envelope_fields.is_empty() {
+            problems.push("envelope_fields require strict: false".to_string());
+        }
+        for key in self.envelope_fields.keys() {
+            if key.is_empty() || key.starts_with('_') {
+                problems.push(format!("envelope field {:?} is empty or starts with an underscore", key));
+            } else if GELF_FIELDS.contains(&key.as_str()) {
+                problems.push(format!("envelope field {:?} is a GELF field", key));
+            }
+        }
+        if self.encoder.is_some() && self.encoder_field.trim_start_matches('_').is_empty() {
+            problems.push("encoder_field is empty".to_string());
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::new(problems))
+        }
+    }
     /// Invoke the builder and return a [`BufferAppender`](struct.BufferAppender.html).
-    pub fn build(self) -> Result<BufferAppender, gelf_logger::Error> {
-        let builder = Builder::new()
-            .filter_level(self.level.to_level_filter())
-            .hostname(self.hostname)
-            .port(self.port)
-            .null_character(self.null_character)
-            .buffer_size(self.buffer_size.unwrap_or(100))
-            .extend_additional_fields(self.additional_fields)
-            .connect_timeout(self.connect_timeout)
-            .write_timeout(self.write_timeout)
-            .background_error_handler(Some(|err| {
-                eprintln!("{err:?}");
-            }));
+    ///
+    /// Fails with [`Error::Config`](enum.Error.html#variant.Config), listing every invalid
+    /// setting, before anything is spawned.
+    pub fn build(mut self) -> Result<BufferAppender, Error> {
+        self.validate()?;
+        if self.log_config {
+            let settings = format!("log4rs-gelf appender settings: {}", self.describe());
+            self.hooks.diagnostics.report(Level::Info, &settings);
+        }
+        if let Some(field_error) = self.field_error {
+            return Err(Error::Field(field_error));
+        }
+        let short_message = match &self.short_message_template {
+            Some(template) => Some(Template::parse(template).map_err(Error::Template)?),
+            None => None,
+        };
+        let clock = Arc::new(Clock::new(self.clock_offset));
+        let transport_config = TransportConfig {
+            protocol: self.protocol,
+            hostname: self.hostname,
+            port: self.port,
+            #[cfg(feature = "tls")]
+            use_tls: self.use_tls,
+            #[cfg(feature = "tls")]
+            pinned_cert_sha256: self.tls_pinned_cert_sha256.as_ref().and_then(|fingerprint| parse_fingerprint(fingerprint)),
+            #[cfg(feature = "tls")]
+            verify_ca: self.tls_verify_ca,
+            #[cfg(feature = "tls")]
+            client_identity: self.tls_client_identity,
+            connect_timeout: self.connect_timeout,
+            write_timeout: self.write_timeout,
+            framing: self.framing,
+            http_path: self.http_path,
+            http_authorization: self.http_authorization,
+            http_idle_timeout: self.http_idle_timeout,
+            resolver: self.resolver,
+            compression_threshold: self.compression_threshold,
+            server_clock: if self.clock_offset_from_http { Some(clock.clone()) } else { None },
+        };
+        for (key, Secret(value)) in mem::take(&mut self.secret_fields) {
+            self.additional_fields.insert(key, Value::String(value));
+        }
+        if self.default_fields {
+            for (key, value) in default_fields() {
+                self.additional_fields.entry(key).or_insert(value);
+            }
+        }
+        if self.windows_fields {
+            for (key, value) in windows::fields() {
+                self.additional_fields.entry(key).or_insert(value);
+            }
+        }
+        if !self.host_tags.is_empty() {
+            let tags = self.host_tags.iter().cloned().map(Value::String).collect();
+            self.additional_fields.entry("tags".to_string()).or_insert(Value::Seq(tags));
+        }
+        if self.field_schema != FieldSchema::Gelf {
+            let field_schema = self.field_schema;
+            self.additional_fields = self.additional_fields.into_iter()
+                .map(|(key, value)| (field_schema.rename(field_name(&key)), value))
+                .collect();
+        }
+        if self.sanitize {
+            self.additional_fields = self.additional_fields.into_iter()
+                .map(|(key, value)| (sanitize_string(key), sanitize_value(value)))
+                .collect();
+        }
+        let redactor = Redactor::new(&self.redactions);
+        if !redactor.is_empty() {
+            self.additional_fields = self.additional_fields.into_iter()
+                .map(|(key, value)| (key, redactor.redact_value(value)))
+                .collect();
+        }
+        let empty_fields = self.empty_fields;
+        self.additional_fields = self.additional_fields.into_iter()
+            .filter_map(|(key, value)| empty_fields.apply(value).map(|value| (key, value)))
+            .collect();
+        if let Some(max_field_bytes) = self.max_field_bytes {
+            for value in self.additional_fields.values_mut() {
+                truncate_field(value, max_field_bytes);
+            }
+        }
+        let encoder_field = self.encoder_field;
+        let encoded_field = self.encoder.map(|encoder| EncodedField::new(&encoder_field, encoder));
+        let host = local_hostname();
+        if let Some(directives) = &self.directives {
+            let directives = filter::parse_directives(directives).map_err(|problem| ConfigError::new(vec![problem]))?;
+            for (prefix, level) in directives {
+                self.target_levels.insert(&prefix, level);
+            }
+        }
+        let mut enrichers = self.enrichers.iter()
+            .map(|enricher| enrich::create(enricher, &host))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|problem| ConfigError::new(vec![problem]))?;
+        let stats = Arc::new(StatsCollector::new(self.drop_stats_targets));
+        let (transport, reopen) = match self.transport {
+            Some(slot) => (slot.take().ok_or_else(|| ConfigError::new(vec![TRANSPORT_TAKEN.to_string()]))?, None),
+            None => (new_transport(transport_config.clone(), stats.clone()), Some(transport_config)),
+        };
+        let capture = match &self.capture_to_file {
+            Some(path) => Some(Capture::create(path, self.capture_max_bytes)?),
+            None => None,
+        };
+        let sender = Sender::spawn(SenderConfig {
+            batch: self.batch,
+            max_memory_bytes: self.max_memory_bytes,
+            queue_capacity: self.queue_capacity,
+            overflow_policy: self.overflow_policy,
+            hooks: self.hooks,
+            record_ttl: self.record_ttl,
+            priority_queue_level: self.priority_queue_level,
+            error_report_interval: self.error_report_interval,
+            capture,
+            synchronous: self.synchronous,
+            reopen,
+            connections: self.connections,
+            ordering: self.ordering,
+        }, transport, stats.clone())?;
 
-        #[cfg(feature = "tls")]
-        let builder = match true {
-            _ => builder.tls(self.use_tls)
+        // Fields the filter reads, or enrichers may, must be known before it runs.
+        let field_filter = &self.field_filter;
+        let field_schema = self.field_schema;
+        let lazy_dynamic_fields = enrichers.is_empty()
+            && !self.dynamic_fields.keys().any(|key| field_filter.reads(&field_schema.rename(key.to_string())));
+        let lazy_encoded_field = !encoded_field.as_ref()
+            .is_some_and(|field| field_filter.reads(&field_schema.rename(field.name.clone())));
+        let spans = if self.tracing_spans { Some(Arc::new(SpanTracker::default())) } else { None };
+        if let Some(spans) = &spans {
+            enrichers.insert(0, spans.clone());
+        }
+        let message = MessageOptions {
+            host,
+            static_fields: Arc::new(StaticFields::new(&self.additional_fields)),
+            envelope: Arc::new(Envelope {
+                version: self.protocol_version,
+                extensions: self.envelope_fields.into_iter().collect(),
+            }),
+            dynamic_fields: self.dynamic_fields,
+            target_fields: self.target_fields,
+            enrichers,
+            field_schema: self.field_schema,
+            format: self.format,
+            priority_field: self.priority_field,
+            severity_field: self.severity_field,
+            level_field: self.level_field,
+            level_range: self.level_range,
+            encoded_field,
+            short_message,
+            sanitize: self.sanitize,
+            empty_fields: self.empty_fields,
+            max_field_bytes: self.max_field_bytes,
+            full_message_chunk_bytes: self.full_message_chunk_bytes,
+            correlation_id: self.correlation_id,
+            redactor,
+            clock,
+            lazy_dynamic_fields,
+            lazy_encoded_field,
+        };
+        let monitor = match self.stats_interval {
+            Some(interval) => Some(monitor::spawn(interval, message.clone(), sender.handle(), stats.clone())?),
+            None => None,
         };
 
-        Ok(BufferAppender { gelf_logger: builder.build()? })
+        let max_field_bytes = self.max_field_bytes;
+        let folder = self.fold_window.map(|window| Arc::new(Folder::new(window, max_field_bytes)));
+        let folding = match &folder {
+            Some(folder) => Some(fold::spawn(folder.clone(), sender.handle())?),
+            None => None,
+        };
+
+        if let Some(name) = &self.name {
+            handle::register(name, GelfHandle::new(sender.handle(), stats.clone(), self.flush_timeout));
+        }
+
+        Ok(BufferAppender {
+            level: self.level,
+            target_levels: self.target_levels,
+            field_filter: self.field_filter,
+            message,
+            fallback: self.fallback,
+            fallback_level: self.fallback_level,
+            flush_timeout: self.flush_timeout,
+            delivery: self.delivery,
+            deferred_serialization: self.deferred_serialization && !self.synchronous,
+            stats,
+            folder,
+            spans,
+            _monitor: monitor,
+            _folding: folding,
+            name: self.name,
+            sender,
+        })
     }
 }
 
+fn list<I: Iterator<Item = String>>(items: I) -> String {
+    format!("[{}]", items.collect::<Vec<String>>().join(","))
+}
+
+fn fields<'f, I: Iterator<Item = (&'f String, &'f Value)>>(entries: I) -> String {
+    list(entries.map(|(key, value)| {
+        let name = key.to_lowercase();
+        if SECRET_NAMES.iter().any(|secret| name.contains(secret)) {
+            format!("{}=***", key)
+        } else {
+            format!("{}={}", key, serde_json::to_string(value).unwrap_or_else(|_| "?".to_string()))
+        }
+    }))
+}
+
+fn optional<T: fmt::Debug>(value: &Option<T>) -> String {
+    value.as_ref().map_or_else(|| "none".to_string(), |value| format!("{:?}", value))
+}
+
+fn secret<T>(value: &Option<T>) -> String {
+    if value.is_some() { "***" } else { "none" }.to_string()
+}
+
+/// The `pkg_name` and `pkg_version` fields, naming the application when the environment does.
+fn default_fields() -> Vec<(String, Value)> {
+    let name = std::env::var(PKG_NAME_ENV).unwrap_or_else(|_| env!("CARGO_PKG_NAME").to_string());
+    let version = std::env::var(PKG_VERSION_ENV).unwrap_or_else(|_| env!("CARGO_PKG_VERSION").to_string());
+    vec![
+        ("pkg_name".to_string(), Value::String(name)),
+        ("pkg_version".to_string(), Value::String(version)),
+    ]
+}
+
+/// Name of this machine, reported in the `host` field of every message.
+fn local_hostname() -> String {
+    hostname::get()
+        .ok()
+        .and_then(|name| name.into_string().ok())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
 
 impl BufferAppender {
     /// Creates a new [`BufferAppenderBuilder`](struct.BufferAppenderBuilder.html).
     pub fn builder() -> BufferAppenderBuilder {
         BufferAppenderBuilder::default()
     }
+    /// Creates a [`BufferAppenderBuilder`](struct.BufferAppenderBuilder.html) whose appender
+    /// ships the messages through `transport`. The protocol, hostname, port, TLS, framing, HTTP
+    /// path and timeout settings of the builder are then ignored.
+    ///
+    /// Clones of the builder share the transport: only one of them can build an appender.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use log4rs_gelf::{BufferAppender, Error, GelfTransport};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// /// Keeps the messages in memory.
+    /// #[derive(Debug, Default)]
+    /// struct Recorder {
+    ///     messages: Arc<Mutex<Vec<Vec<u8>>>>,
+    /// }
+    ///
+    /// impl GelfTransport for Recorder {
+    ///     fn is_connected(&self) -> bool { true }
+    ///     fn connect(&mut self) -> Result<(), Error> { Ok(()) }
+    ///     fn disconnect(&mut self) {}
+    ///     fn send(&mut self, payload: &[u8]) -> Result<(), Error> {
+    ///         self.messages.lock().unwrap().push(payload.to_vec());
+    ///         Ok(())
+    ///     }
+    ///     fn flush(&mut self) -> Result<(), Error> { Ok(()) }
+    /// }
+    ///
+    /// let appender = BufferAppender::with_transport(Box::new(Recorder::default()))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn with_transport(transport: Box<dyn GelfTransport>) -> BufferAppenderBuilder {
+        BufferAppenderBuilder { transport: Some(TransportSlot::new(transport)), ..BufferAppenderBuilder::default() }
+    }
+    /// The messages of a record, none if the field filters discard it.
+    fn compose<'a>(&self, record: &Record<'a>) -> anyhow::Result<Vec<GelfMessage<'a>>> {
+        let message = match GelfMessage::filtered(record, &self.message, &self.field_filter)? {
+            Some(message) => message,
+            None => return Ok(Vec::new()),
+        };
+        Ok(match self.message.full_message_chunk_bytes {
+            Some(chunk_bytes) => message.split_full_message(chunk_bytes),
+            None => vec![message],
+        })
+    }
+    /// Queues a message serialized elsewhere. Returns `false` if it was dropped.
+    pub(crate) fn send_serialized(&self, payload: Vec<u8>, level: Level, target: &str) -> bool {
+        self.sender.send(Entry::new(payload, level, target))
+    }
+    /// Takes up to `max` records off the queue, oldest first, so that they can be handed to
+    /// another sink when the remote server can't be reached, e.g. on shutdown or failover.
+    ///
+    /// The batch being written stays with the background thread. The drained records don't count
+    /// as sent nor dropped in the [`stats`](#method.stats), but pending flushes treat them as
+    /// dropped.
+    pub fn drain_pending(&self, max: usize) -> Vec<PendingMessage> {
+        self.sender.drain(max)
+    }
+    /// Returns a snapshot of the records sent and dropped so far.
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+    /// Whether records of this level and target are sent, as far as the
+    /// [level](struct.BufferAppenderBuilder.html#method.set_level) and the
+    /// [target levels](struct.BufferAppenderBuilder.html#method.set_target_level) tell: the field
+    /// filters need the whole record. It's the first check of `append`, before anything is formatted.
+    ///
+    /// log4rs doesn't ask its appenders before building a record, so that a logger wrapping the
+    /// appender, or an application about to compute an expensive argument, can call it instead.
+    pub fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.threshold(metadata.target())
+    }
+    /// The most verbose level records of any target are sent at, e.g. to set the level of the
+    /// log4rs logger feeding the appender, so that `log` drops the other records right away.
+    pub fn max_level(&self) -> LevelFilter {
+        let rules = self.target_levels.rules();
+        // A directive without a target overrides the level for every target.
+        let level = if rules.iter().any(|rule| rule.0.is_empty()) {
+            LevelFilter::Off
+        } else {
+            self.level.to_level_filter()
+        };
+        rules.iter().map(|rule| rule.1).fold(level, cmp::max)
+    }
+    fn threshold(&self, target: &str) -> LevelFilter {
+        self.target_levels.level_for(target).unwrap_or(self.level.to_level_filter())
+    }
+    /// Returns the runtime controls of the appender, which can be handed to other threads.
+    pub fn handle(&self) -> GelfHandle {
+        GelfHandle::new(self.sender.handle(), self.stats.clone(), self.flush_timeout)
+    }
+    /// Ships the queued records without waiting for the batch to fill up. The future resolves once
+    /// they were all sent, or fails if some of them were dropped.
+    ///
+    /// Nothing blocks: it can be awaited from any executor, e.g. in the graceful shutdown of a
+    /// Tokio service.
+    #[cfg(feature = "async")]
+    pub fn flush_async(&self) -> impl Future<Output = Result<(), FlushError>> + Send + 'static {
+        self.sender.flush_async()
+    }
 }
 
 impl fmt::Debug for BufferAppender {
@@ -196,12 +1682,69 @@ impl fmt::Debug for BufferAppender {
     }
 }
 
+impl Drop for BufferAppender {
+    fn drop(&mut self) {
+        if let Some(name) = &self.name {
+            handle::unregister(name, &self.sender.handle());
+        }
+    }
+}
+
 
 impl Append for BufferAppender {
     fn append(&self, record: &Record) -> anyhow::Result<()> {
-        self.gelf_logger.append(record).context("")
+        if self.spans.as_ref().is_some_and(|spans| spans.observe(record)) {
+            return Ok(());
+        }
+        if !self.enabled(record.metadata()) {
+            return Ok(());
+        }
+        if let Some(folder) = &self.folder {
+            if folder.fold(record.target(), || record.args().to_string(), |line| self.message.clean(line)) {
+                return Ok(());
+            }
+        }
+        // Formatting runs user code, which mustn't take the logging thread down.
+        let messages = match panic::catch_unwind(AssertUnwindSafe(|| self.compose(record))) {
+            Ok(messages) => messages?,
+            Err(panic) => bail!("panicked while serializing GELF record: {}", panic_message(&*panic)),
+        };
+        for message in messages {
+            let fallback = self.fallback != Fallback::None && record.level() <= self.fallback_level && self.sender.is_link_down();
+            let entry = if self.deferred_serialization && self.folder.is_none() && !fallback {
+                Entry::deferred(message.into_owned(), record.level(), record.target())
+            } else {
+                let payload = message.to_json().context("failed to serialize GELF record")?;
+                if fallback {
+                    self.fallback.write(&payload);
+                }
+                if let Some(folder) = &self.folder {
+                    if let Some(previous) = folder.hold(payload, record.level(), record.target()) {
+                        self.sender.send(previous);
+                    }
+                    continue;
+                }
+                Entry::new(payload, record.level(), record.target())
+            };
+            if self.delivery == Delivery::Guaranteed {
+                if !self.sender.send_wait(entry, self.flush_timeout) {
+                    bail!("GELF record was not delivered");
+                }
+                continue;
+            }
+            self.sender.send(entry);
+        }
+        Ok(())
     }
     fn flush(&self) {
-        Log::flush(&self.gelf_logger)
+        if let Some(pending) = self.folder.as_ref().and_then(|folder| folder.take(true)) {
+            self.sender.send(pending);
+        }
+        match self.flush_timeout {
+            Some(timeout) => {
+                self.sender.flush_wait(timeout);
+            }
+            None => self.sender.flush(),
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,102 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long dropping an appender waits for its threads, before leaving them to finish on their own.
+pub(crate) const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Thread of an appender, stopped and joined when dropped: what it still sends is queued before
+/// the sender it feeds shuts down, e.g. when log4rs drops the appender on reconfiguration.
+#[derive(Debug)]
+pub(crate) struct Background {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<Thread>,
+}
+
+/// Starts a thread named `name` running `run`, whose receiver disconnects once it must stop.
+pub(crate) fn spawn<F>(name: &str, run: F) -> io::Result<Background>
+    where F: FnOnce(Receiver<()>) + Send + 'static
+{
+    let (stop, stopped) = mpsc::channel::<()>();
+    let thread = spawn_thread(name.to_string(), move || run(stopped))?;
+    Ok(Background { stop: Some(stop), thread: Some(thread) })
+}
+
+/// A thread whose end can be waited for until a deadline.
+#[derive(Debug)]
+pub(crate) struct Thread {
+    handle: JoinHandle<()>,
+    /// Disconnects once the thread is done running, even if it panicked.
+    ended: Mutex<Receiver<()>>,
+}
+
+/// Starts a thread named `name` running `run`.
+pub(crate) fn spawn_thread<F>(name: String, run: F) -> io::Result<Thread>
+    where F: FnOnce() + Send + 'static
+{
+    let (end, ended) = mpsc::channel::<()>();
+    let handle = thread::Builder::new().name(name).spawn(move || {
+        // Dropped once `run` returns or unwinds.
+        let _end = end;
+        run()
+    })?;
+    Ok(Thread { handle, ended: Mutex::new(ended) })
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            join_until(thread, Instant::now() + SHUTDOWN_TIMEOUT);
+        }
+    }
+}
+
+/// Joins `thread` if it ends before `deadline`, and detaches it otherwise: a write blocked on a
+/// server which stopped reading mustn't hold up `log4rs::Handle::set_config` or the exit.
+pub(crate) fn join_until(thread: Thread, deadline: Instant) {
+    let left = deadline.saturating_duration_since(Instant::now());
+    let ended = thread.ended.into_inner().unwrap_or_else(PoisonError::into_inner);
+    if let Err(RecvTimeoutError::Disconnected) = ended.recv_timeout(left) {
+        let _ = thread.handle.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threads_are_joined_as_soon_as_they_end() {
+        let thread = spawn_thread("test".to_string(), || thread::sleep(Duration::from_millis(50))).unwrap();
+        let started = Instant::now();
+        join_until(thread, started + Duration::from_secs(5));
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(50) && elapsed < Duration::from_secs(1), "{:?}", elapsed);
+
+        // Panics end the thread too.
+        let thread = spawn_thread("test".to_string(), || panic!("thread panicked")).unwrap();
+        let started = Instant::now();
+        join_until(thread, started + Duration::from_secs(5));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn threads_still_running_at_the_deadline_are_detached() {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = spawn_thread("test".to_string(), move || {
+            let _ = stopped.recv();
+        }).unwrap();
+        let started = Instant::now();
+        join_until(thread, started + Duration::from_millis(50));
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(50) && elapsed < Duration::from_secs(1), "{:?}", elapsed);
+        drop(stop);
+    }
+}
@@ -0,0 +1,49 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Default size limit of the capture file: 16 MiB.
+pub(crate) const DEFAULT_CAPTURE_MAX_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Local copy of the payloads handed over to the transport, one JSON line each, kept to check
+/// what the server is sent.
+#[derive(Debug)]
+pub(crate) struct Capture {
+    file: BufWriter<File>,
+    written: u64,
+    max_bytes: u64,
+}
+
+impl Capture {
+    /// Creates the capture file, truncating it if it exists.
+    ///
+    /// Payloads are appended at the end of the file: the appender a reconfiguration replaces
+    /// still writes its last batches while the new one truncates the file, and would otherwise
+    /// leave a hole of zeros.
+    pub(crate) fn create(path: &Path, max_bytes: u64) -> io::Result<Capture> {
+        File::create(path)?;
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Capture { file: BufWriter::new(file), written: 0, max_bytes })
+    }
+
+    /// Appends a payload, unless it would take the file over its size limit: the capture then
+    /// stops, the file holding the first payloads only.
+    pub(crate) fn write(&mut self, payload: &[u8]) {
+        let len = payload.len() as u64 + 1;
+        if self.written + len > self.max_bytes {
+            self.written = self.max_bytes;
+            return;
+        }
+        self.written += len;
+        let _ = self.file.write_all(payload).and_then(|_| self.file.write_all(b"\n"));
+    }
+
+    /// Writes the buffered payloads out, once a batch is taken.
+    pub(crate) fn flush(&mut self) {
+        let _ = self.file.flush();
+    }
+}
@@ -0,0 +1,86 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use appender::BufferAppenderBuilder;
+use log::LevelFilter;
+use log4rs::append::console::ConsoleAppender;
+use log4rs::config::{Appender, Config, Root};
+
+/// Name of the console appender added by [`set_console`](struct.ConfigBuilder.html#method.set_console).
+const CONSOLE_APPENDER: &str = "console";
+
+/// Builder of a complete log4rs [`Config`](https://docs.rs/log4rs/*/log4rs/config/struct.Config.html)
+/// whose root logger writes to a GELF appender.
+///
+/// ## Example
+///
+/// ```rust
+/// extern crate log;
+///
+/// use log::LevelFilter;
+/// use log4rs_gelf::{BufferAppender, ConfigBuilder};
+///
+/// fn main() {
+///     let config = ConfigBuilder::new(BufferAppender::builder().set_hostname("localhost"))
+///         .set_root_level(LevelFilter::Info)
+///         .set_console(true)
+///         .build()
+///         .unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ConfigBuilder {
+    appender: BufferAppenderBuilder,
+    appender_name: String,
+    root_level: LevelFilter,
+    console: bool,
+}
+
+impl ConfigBuilder {
+    /// Creates a builder around the GELF appender `appender`.
+    pub fn new(appender: BufferAppenderBuilder) -> ConfigBuilder {
+        ConfigBuilder {
+            appender,
+            appender_name: "gelf".to_string(),
+            root_level: LevelFilter::Info,
+            console: false,
+        }
+    }
+    /// Sets the name of the GELF appender in the configuration, `gelf` by default.
+    pub fn set_appender_name(mut self, appender_name: &str) -> ConfigBuilder {
+        self.appender_name = appender_name.to_string();
+        self
+    }
+    /// Sets the level of the root logger, `Info` by default.
+    pub fn set_root_level(mut self, root_level: LevelFilter) -> ConfigBuilder {
+        self.root_level = root_level;
+        self
+    }
+    /// Also writes every record to the standard output, through a log4rs `ConsoleAppender` named
+    /// `console`.
+    pub fn set_console(mut self, console: bool) -> ConfigBuilder {
+        self.console = console;
+        self
+    }
+    /// Builds the GELF appender and the configuration around it.
+    pub fn build(self) -> anyhow::Result<Config> {
+        let gelf = Appender::builder().build(self.appender_name.as_str(), Box::new(self.appender.build()?));
+        let mut config = Config::builder().appender(gelf);
+        let mut root = Root::builder().appender(self.appender_name.as_str());
+        if self.console {
+            let console = ConsoleAppender::builder().build();
+            config = config.appender(Appender::builder().build(CONSOLE_APPENDER, Box::new(console)));
+            root = root.appender(CONSOLE_APPENDER);
+        }
+        Ok(config.build(root.build(self.root_level))?)
+    }
+    /// Builds the configuration and initializes the global logger with it.
+    ///
+    /// ### Warning
+    ///
+    /// The logging system may only be initialized once.
+    pub fn init(self) -> anyhow::Result<log4rs::Handle> {
+        Ok(log4rs::init_config(self.build()?)?)
+    }
+}
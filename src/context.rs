@@ -0,0 +1,118 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use indexmap::IndexMap;
+use log::kv::{self, Key, VisitSource};
+use log::Record;
+use message::field_name;
+use serde_value::Value;
+use severity::parse_severity;
+use timestamp::{parse_timestamp, TIMESTAMP_KEYS};
+
+/// Reserved MDC / key-value key overriding the `host` field of a single message.
+///
+/// ```rust,ignore
+/// log_mdc::insert(log4rs_gelf::HOST_KEY, "device-42");
+/// log::info!("reading received");
+/// log_mdc::remove(log4rs_gelf::HOST_KEY);
+/// ```
+pub const HOST_KEY: &str = "gelf.host";
+
+/// Reserved key-value key setting the GELF `level` of a single message, either as a syslog
+/// severity number (`0` to `7`) or name (`emergency`, `alert`, `critical`, `notice`, ...).
+///
+/// See also the [`gelf_log!`](macro.gelf_log.html) macro.
+pub const LEVEL_KEY: &str = "gelf.level";
+
+/// Reserved MDC / key-value key removing static additional fields from a single message, as a
+/// comma-separated list of field names.
+///
+/// ```rust,ignore
+/// log_mdc::insert(log4rs_gelf::SUPPRESS_KEY, "datacenter,_rack");
+/// log::info!("reading relayed from another site");
+/// log_mdc::remove(log4rs_gelf::SUPPRESS_KEY);
+/// ```
+pub const SUPPRESS_KEY: &str = "gelf.suppress";
+
+/// Data attached to a record through the log4rs MDC and the record key-values.
+///
+/// Key-values take precedence over MDC entries of the same name. Reserved `gelf.*` keys drive
+/// the encoding of the message instead of being sent as additional fields, and so do `timestamp`
+/// and `event_time` key-values holding a valid time.
+#[derive(Debug, Default)]
+pub(crate) struct RecordContext {
+    pub(crate) host: Option<String>,
+    pub(crate) severity: Option<u32>,
+    pub(crate) timestamp: Option<f64>,
+    /// GELF names of the static fields left out of the message, sorted.
+    pub(crate) suppressed: Vec<String>,
+    pub(crate) fields: IndexMap<String, Value>,
+}
+
+impl RecordContext {
+    pub(crate) fn capture(record: &Record) -> RecordContext {
+        let mut context = RecordContext::default();
+        log_mdc::iter(|key, value| context.insert(key, Value::String(value.to_string())));
+        // The MDC is a hash map, its order must not leak into the message.
+        context.fields.sort_keys();
+        let _ = record.key_values().visit(&mut context);
+        context
+    }
+
+    fn insert(&mut self, key: &str, value: Value) {
+        match key {
+            HOST_KEY => {
+                if let Value::String(host) = value {
+                    self.host = Some(host);
+                }
+            }
+            LEVEL_KEY => {
+                if let Some(severity) = parse_severity(&value) {
+                    self.severity = Some(severity);
+                }
+            }
+            SUPPRESS_KEY => {
+                if let Value::String(names) = value {
+                    self.suppressed = names.split(',')
+                        .map(str::trim)
+                        .filter(|name| !name.is_empty())
+                        .map(field_name)
+                        .collect();
+                    self.suppressed.sort();
+                }
+            }
+            _ => {
+                self.fields.insert(key.to_string(), value);
+            }
+        }
+    }
+}
+
+impl<'kvs> VisitSource<'kvs> for RecordContext {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = to_value(&value);
+        if TIMESTAMP_KEYS.contains(&key.as_str()) {
+            if let Some(timestamp) = parse_timestamp(&value) {
+                self.timestamp = Some(timestamp);
+                return Ok(());
+            }
+        }
+        self.insert(key.as_str(), value);
+        Ok(())
+    }
+}
+
+fn to_value(value: &kv::Value) -> Value {
+    if let Some(value) = value.to_bool() {
+        Value::Bool(value)
+    } else if let Some(value) = value.to_u64() {
+        Value::U64(value)
+    } else if let Some(value) = value.to_i64() {
+        Value::I64(value)
+    } else if let Some(value) = value.to_f64() {
+        Value::F64(value)
+    } else {
+        Value::String(value.to_string())
+    }
+}
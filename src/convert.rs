@@ -0,0 +1,59 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use serde_value::Value;
+
+/// Conversion of common types into additional field values.
+///
+/// `Value` comes from `serde_value`, so this crate can't implement `From` for it. Dates become
+/// RFC 3339 strings in UTC with millisecond precision, which Graylog parses; UUIDs become their
+/// hyphenated form.
+///
+/// ## Example
+///
+/// ```rust,ignore
+/// use log4rs_gelf::IntoValue;
+///
+/// let started = chrono::Utc::now();
+/// let builder = log4rs_gelf::BufferAppender::builder()
+///     .put_additional_field("instance_id", uuid::Uuid::new_v4().into_value())
+///     .put_additional_field("started_at", started.into_value());
+/// ```
+pub trait IntoValue {
+    /// Converts `self` into a field value.
+    fn into_value(self) -> Value;
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> IntoValue for chrono::DateTime<Tz> {
+    fn into_value(self) -> Value {
+        Value::String(self.with_timezone(&chrono::Utc).to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+    }
+}
+
+#[cfg(feature = "time")]
+impl IntoValue for time::OffsetDateTime {
+    fn into_value(self) -> Value {
+        let utc = self.to_offset(time::UtcOffset::UTC);
+        let utc = utc.replace_nanosecond(utc.millisecond() as u32 * 1_000_000).unwrap_or(utc);
+        match utc.format(&time::format_description::well_known::Rfc3339) {
+            Ok(text) => Value::String(text),
+            // Years RFC 3339 can't express: seconds since the UNIX epoch instead.
+            Err(_) => Value::F64(utc.unix_timestamp() as f64 + f64::from(utc.millisecond()) / 1000.0),
+        }
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl IntoValue for uuid::Uuid {
+    fn into_value(self) -> Value {
+        Value::String(self.hyphenated().to_string())
+    }
+}
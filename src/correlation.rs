@@ -0,0 +1,99 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Field shared by the messages relating to one another.
+pub(crate) const CORRELATION_FIELD: &str = "_correlation_id";
+
+/// MDC key of the correlation id of a scope, sent as `_correlation_id`.
+const CORRELATION_KEY: &str = "correlation_id";
+
+/// Correlation id of the process, drawn when first needed.
+static PROCESS_ID: Mutex<Option<String>> = Mutex::new(None);
+
+/// Correlation id of the messages of this process outside of a
+/// [`correlation_scope`](fn.correlation_scope.html).
+pub(crate) fn process_id() -> String {
+    PROCESS_ID.lock().unwrap_or_else(PoisonError::into_inner).get_or_insert_with(random_uuid).clone()
+}
+
+/// Draws a new correlation id for the process, the child of a `fork()` being another one.
+pub(crate) fn renew_process_id() {
+    *PROCESS_ID.lock().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// Gives the messages logged by the current thread a new `_correlation_id` until the returned
+/// guard is dropped, e.g. for the handling of a request. The id is held by the MDC, so that it
+/// is sent whether or not `correlation_id` is enabled; scopes nest, the id of the enclosing one
+/// being restored.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// #[macro_use]
+/// extern crate log;
+///
+/// fn main() {
+///     let scope = log4rs_gelf::correlation_scope();
+///     info!("handling request {}", scope.id());
+/// }
+/// ```
+pub fn correlation_scope() -> CorrelationScope {
+    let id = random_uuid();
+    let previous = log_mdc::insert(CORRELATION_KEY, id.as_str());
+    CorrelationScope { id, previous }
+}
+
+/// Scope of a correlation id, ended when dropped.
+#[must_use = "the scope ends once dropped"]
+#[derive(Debug)]
+pub struct CorrelationScope {
+    id: String,
+    previous: Option<String>,
+}
+
+impl CorrelationScope {
+    /// The correlation id of the scope, e.g. to pass it on to other services.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl Drop for CorrelationScope {
+    fn drop(&mut self) {
+        match self.previous.take() {
+            Some(previous) => {
+                log_mdc::insert(CORRELATION_KEY, previous);
+            }
+            None => {
+                log_mdc::remove(CORRELATION_KEY);
+            }
+        }
+    }
+}
+
+/// A random (version 4) UUID, in its hyphenated form.
+///
+/// The randomness comes from the keys of the standard hasher, which are seeded by the operating
+/// system: enough to tell messages apart, not for cryptography.
+pub(crate) fn random_uuid() -> String {
+    let state = RandomState::new();
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let half = |salt: u64| {
+        let mut hasher = state.build_hasher();
+        hasher.write_u64(salt);
+        hasher.write_u128(nanos);
+        hasher.finish()
+    };
+    let high = (half(0) & 0xffff_ffff_ffff_0fff) | 0x4000;
+    let low = (half(1) & 0x3fff_ffff_ffff_ffff) | 0x8000_0000_0000_0000;
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        high >> 32, (high >> 16) & 0xffff, high & 0xffff, low >> 48, low & 0xffff_ffff_ffff
+    )
+}
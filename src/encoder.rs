@@ -0,0 +1,128 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use log::Record;
+use log4rs::encode::writer::simple::SimpleWriter;
+use log4rs::encode::Encode;
+use message::field_name;
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Default field holding the output of the encoder.
+pub(crate) const DEFAULT_ENCODER_FIELD: &str = "full_message";
+
+/// A log4rs encoder whose rendition of each record is sent in a message field.
+#[derive(Debug, Clone)]
+pub(crate) struct EncodedField {
+    /// GELF name of the field.
+    pub(crate) name: String,
+    pub(crate) encoder: Arc<dyn Encode>,
+}
+
+impl EncodedField {
+    pub(crate) fn new(name: &str, encoder: Arc<dyn Encode>) -> EncodedField {
+        EncodedField { name: gelf_field_name(name), encoder }
+    }
+
+    /// Renders `record`, without the trailing line break most patterns end with.
+    pub(crate) fn encode(&self, record: &Record) -> anyhow::Result<String> {
+        let mut writer = SimpleWriter(Vec::new());
+        self.encoder.encode(&mut writer, record)?;
+        let text = String::from_utf8_lossy(&writer.0);
+        Ok(text.trim_end_matches(['\n', '\r']).to_string())
+    }
+}
+
+/// `full_message` is a standard GELF field, any other name is an additional field.
+fn gelf_field_name(name: &str) -> String {
+    if name == DEFAULT_ENCODER_FIELD {
+        name.to_string()
+    } else {
+        field_name(name)
+    }
+}
+
+/// A pattern such as `{target} - {message}`, rendered for each record.
+///
+/// Placeholders are `message`, `level`, `target`, `module_path`, `file` and `line`; `{{` and
+/// `}}` stand for literal braces. Missing values render as an empty string.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Message,
+    Level,
+    Target,
+    ModulePath,
+    File,
+    Line,
+}
+
+impl Template {
+    pub(crate) fn parse(pattern: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or_else(|| format!("unclosed placeholder in `{}`", pattern))?;
+                    let part = match rest[..end].trim() {
+                        "message" => Part::Message,
+                        "level" => Part::Level,
+                        "target" => Part::Target,
+                        "module_path" => Part::ModulePath,
+                        "file" => Part::File,
+                        "line" => Part::Line,
+                        name => return Err(format!("unknown placeholder `{{{}}}`", name)),
+                    };
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(part);
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(format!("unmatched `}}` in `{}`", pattern)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    pub(crate) fn render(&self, record: &Record) -> String {
+        let mut text = String::new();
+        for part in &self.parts {
+            // Writing to a `String` can't fail.
+            let _ = match part {
+                Part::Literal(literal) => text.write_str(literal),
+                Part::Message => write!(text, "{}", record.args()),
+                Part::Level => write!(text, "{}", record.level()),
+                Part::Target => text.write_str(record.target()),
+                Part::ModulePath => text.write_str(record.module_path().unwrap_or_default()),
+                Part::File => text.write_str(record.file().unwrap_or_default()),
+                Part::Line => match record.line() {
+                    Some(line) => write!(text, "{}", line),
+                    None => Ok(()),
+                },
+            };
+        }
+        text
+    }
+}
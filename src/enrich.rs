@@ -0,0 +1,200 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use log::Record;
+use message::{field_name, Fields};
+use redact::{Redaction, Redactor};
+use serde_value::Value;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+
+/// File holding the namespace of a Kubernetes pod, mounted with its service account.
+const K8S_NAMESPACE_FILE: &str = "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+
+/// Creates an enricher from the argument following its name and a colon, if any.
+type Factory = dyn Fn(Option<&str>) -> anyhow::Result<Box<dyn Enricher>> + Send + Sync;
+
+/// Enrichers registered by the application.
+static REGISTRY: Mutex<Vec<(String, Arc<Factory>)>> = Mutex::new(Vec::new());
+
+/// A step of the enrichment pipeline, adding to or rewriting the additional fields of each
+/// message.
+///
+/// Enrichers are referenced by name from
+/// [`BufferAppenderBuilder::add_enricher`](struct.BufferAppenderBuilder.html#method.add_enricher)
+/// and the `enrichers` list of the configuration file, once registered with
+/// [`register_enricher`](fn.register_enricher.html).
+///
+/// ## Example
+///
+/// ```rust
+/// extern crate anyhow;
+/// extern crate log;
+///
+/// use log::Record;
+/// use log4rs_gelf::{Enricher, MessageFields, Value};
+///
+/// /// Adds the tenant given in the configuration, as in `tenant:acme`.
+/// #[derive(Debug)]
+/// struct Tenant(String);
+///
+/// impl Enricher for Tenant {
+///     fn enrich(&self, _record: &Record, fields: &mut MessageFields) {
+///         fields.insert_if_absent("tenant", Value::String(self.0.clone()));
+///     }
+/// }
+///
+/// fn main() {
+///     log4rs_gelf::register_enricher("tenant", |argument| {
+///         let tenant = argument.ok_or_else(|| anyhow::anyhow!("tenant:NAME expected"))?;
+///         Ok(Box::new(Tenant(tenant.to_string())))
+///     });
+///     let builder = log4rs_gelf::BufferAppender::builder().add_enricher("tenant:acme");
+/// }
+/// ```
+pub trait Enricher: fmt::Debug + Send + Sync {
+    /// Called for each record, in the logging thread.
+    fn enrich(&self, record: &Record, fields: &mut MessageFields);
+}
+
+/// The additional fields of a message being enriched. Keys are GELF names: an underscore is
+/// prepended when missing.
+pub struct MessageFields<'f> {
+    fields: &'f mut Fields,
+}
+
+impl<'f> MessageFields<'f> {
+    pub(crate) fn new(fields: &'f mut Fields) -> MessageFields<'f> {
+        MessageFields { fields }
+    }
+    /// Value of a field.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.fields.get(&field_name(key))
+    }
+    /// Sets a field, replacing its value if any.
+    pub fn insert(&mut self, key: &str, value: Value) {
+        self.fields.insert(field_name(key), value);
+    }
+    /// Sets a field, unless the record or a previous step set it already.
+    pub fn insert_if_absent(&mut self, key: &str, value: Value) {
+        self.fields.entry(field_name(key)).or_insert(value);
+    }
+    /// Removes a field, returning its value.
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.fields.shift_remove(&field_name(key))
+    }
+    /// The fields, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.fields.iter().map(|(key, value)| (key.as_str(), value))
+    }
+    /// The values of the fields, to be rewritten.
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Value> {
+        self.fields.values_mut()
+    }
+}
+
+/// Registers an enricher, so that it can be referenced by `name` in the enrichment pipeline of the
+/// appenders built afterwards. Its factory is given the text following the name and a colon, if
+/// any, and fails if it's invalid. A registered enricher replaces the built-in one of the same
+/// name.
+pub fn register_enricher<F>(name: &str, factory: F)
+    where F: Fn(Option<&str>) -> anyhow::Result<Box<dyn Enricher>> + Send + Sync + 'static
+{
+    let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    registry.retain(|(registered, _)| registered != name);
+    registry.push((name.to_string(), Arc::new(factory)));
+}
+
+/// Creates the enricher named by `spec`, registered or built-in: `hostname`, `thread`, `k8s`,
+/// `env:VARIABLE` or `redact:detector,...`.
+pub(crate) fn create(spec: &str, host: &str) -> Result<Arc<dyn Enricher>, String> {
+    let (name, argument) = match spec.split_once(':') {
+        Some((name, argument)) => (name, Some(argument)),
+        None => (spec, None),
+    };
+    let factory = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner).iter()
+        .find(|(registered, _)| registered == name)
+        .map(|(_, factory)| factory.clone());
+    if let Some(factory) = factory {
+        return factory(argument)
+            .map(Arc::from)
+            .map_err(|err| format!("invalid enricher {:?}: {}", spec, err));
+    }
+    match (name, argument) {
+        ("hostname", None) => Ok(Arc::new(StaticEnricher(vec![("_hostname".to_string(), Value::String(host.to_string()))]))),
+        ("thread", None) => Ok(Arc::new(ThreadEnricher)),
+        ("k8s", None) => Ok(Arc::new(StaticEnricher(kubernetes_fields()))),
+        ("env", Some(variable)) if !variable.is_empty() => {
+            let fields = env::var(variable).ok()
+                .map(|value| (field_name(&variable.to_lowercase()), Value::String(value)))
+                .into_iter()
+                .collect();
+            Ok(Arc::new(StaticEnricher(fields)))
+        }
+        ("redact", Some(detectors)) => {
+            let detectors = detectors.split(',')
+                .map(|detector| Redaction::from_name(detector.trim()).ok_or_else(|| format!("unknown redaction {:?}", detector)))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Arc::new(RedactEnricher(Redactor::new(&detectors))))
+        }
+        _ => Err(format!("unknown enricher {:?}", spec)),
+    }
+}
+
+/// Adds fields computed once, unless the record has them already.
+#[derive(Debug)]
+struct StaticEnricher(Vec<(String, Value)>);
+
+impl Enricher for StaticEnricher {
+    fn enrich(&self, _record: &Record, fields: &mut MessageFields) {
+        for (key, value) in &self.0 {
+            if fields.get(key).is_none() {
+                fields.insert(key, value.clone());
+            }
+        }
+    }
+}
+
+/// Adds the name of the logging thread, or its id when unnamed, as `_thread`.
+#[derive(Debug)]
+struct ThreadEnricher;
+
+impl Enricher for ThreadEnricher {
+    fn enrich(&self, _record: &Record, fields: &mut MessageFields) {
+        if fields.get("_thread").is_none() {
+            let thread = thread::current();
+            let name = thread.name().map_or_else(|| format!("{:?}", thread.id()), str::to_string);
+            fields.insert("_thread", Value::String(name));
+        }
+    }
+}
+
+/// Redacts the fields added so far.
+#[derive(Debug)]
+struct RedactEnricher(Redactor);
+
+impl Enricher for RedactEnricher {
+    fn enrich(&self, _record: &Record, fields: &mut MessageFields) {
+        for value in fields.values_mut() {
+            *value = self.0.redact_value(mem::replace(value, Value::Unit));
+        }
+    }
+}
+
+/// Pod, namespace and node, as exposed by the Kubernetes downward API through the `POD_NAME`,
+/// `POD_NAMESPACE` and `NODE_NAME` environment variables. The pod name defaults to the hostname
+/// and the namespace to the one of the service account.
+fn kubernetes_fields() -> Vec<(String, Value)> {
+    let pod = env::var("POD_NAME").or_else(|_| env::var("HOSTNAME")).ok();
+    let namespace = env::var("POD_NAMESPACE").ok()
+        .or_else(|| fs::read_to_string(K8S_NAMESPACE_FILE).ok().map(|namespace| namespace.trim().to_string()));
+    let node = env::var("NODE_NAME").ok();
+    [("_k8s_pod", pod), ("_k8s_namespace", namespace), ("_k8s_node", node)].iter()
+        .filter_map(|(key, value)| value.clone().map(|value| (key.to_string(), Value::String(value))))
+        .collect()
+}
@@ -0,0 +1,169 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use std::any::Any;
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Errors raised while building the appender or shipping records.
+#[derive(Debug)]
+pub enum Error {
+    /// I/O failure on the connection to the remote server.
+    Io(io::Error),
+    /// TLS setup or handshake failure.
+    #[cfg(feature = "tls")]
+    Tls(native_tls::Error),
+    /// A record could not be serialized to JSON.
+    Json(serde_json::Error),
+    /// The remote server answered an HTTP request with a non-`2xx` status.
+    Http(u16),
+    /// The `short_message` template is invalid.
+    Template(String),
+    /// The value of an additional field couldn't be serialized.
+    Field(String),
+    /// The settings of the appender are invalid.
+    Config(ConfigError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            #[cfg(feature = "tls")]
+            Error::Tls(err) => write!(f, "TLS error: {}", err),
+            Error::Json(err) => write!(f, "serialization error: {}", err),
+            Error::Http(status) => write!(f, "remote server answered with HTTP status {}", status),
+            Error::Template(reason) => write!(f, "invalid short_message template: {}", reason),
+            Error::Field(reason) => write!(f, "invalid additional field {}", reason),
+            Error::Config(err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            #[cfg(feature = "tls")]
+            Error::Tls(err) => Some(err),
+            Error::Json(err) => Some(err),
+            Error::Config(err) => Some(err),
+            Error::Http(_) | Error::Template(_) | Error::Field(_) => None,
+        }
+    }
+}
+
+impl Error {
+    /// Whether the server refused the message itself, e.g. as malformed, too large or
+    /// unauthorized: sending it again would fail the same way. Other HTTP statuses, such as `5xx`,
+    /// `408 Request Timeout` and `429 Too Many Requests`, may succeed later.
+    pub(crate) fn is_rejection(&self) -> bool {
+        match self {
+            Error::Http(status) => (400..500).contains(status) && *status != 408 && *status != 429,
+            _ => false,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Error {
+        Error::Config(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::Json(err)
+    }
+}
+
+/// The message of a caught panic.
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic.downcast_ref::<String>().map_or("unknown cause", String::as_str),
+    }
+}
+
+#[cfg(feature = "tls")]
+impl From<native_tls::Error> for Error {
+    fn from(err: native_tls::Error) -> Error {
+        Error::Tls(err)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl<S> From<native_tls::HandshakeError<S>> for Error {
+    fn from(err: native_tls::HandshakeError<S>) -> Error {
+        match err {
+            native_tls::HandshakeError::Failure(err) => Error::Tls(err),
+            native_tls::HandshakeError::WouldBlock(_) => {
+                Error::Io(io::Error::new(io::ErrorKind::WouldBlock, "TLS handshake interrupted"))
+            }
+        }
+    }
+}
+
+/// Every problem found in the settings of an appender by
+/// [`BufferAppenderBuilder::build`](struct.BufferAppenderBuilder.html#method.build).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    problems: Vec<String>,
+}
+
+impl ConfigError {
+    pub(crate) fn new(problems: Vec<String>) -> ConfigError {
+        ConfigError { problems }
+    }
+
+    /// Descriptions of the problems, one per invalid setting.
+    pub fn problems(&self) -> &[String] {
+        &self.problems
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid GELF appender settings: {}", self.problems.join("; "))
+    }
+}
+
+impl error::Error for ConfigError {}
+
+/// Returned by [`BufferAppender::flush_async`](struct.BufferAppender.html#method.flush_async)
+/// when records queued before the flush were dropped instead of sent.
+#[cfg(feature = "async")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushError {
+    dropped: u64,
+}
+
+#[cfg(feature = "async")]
+impl FlushError {
+    pub(crate) fn new(dropped: u64) -> FlushError {
+        FlushError { dropped }
+    }
+
+    /// Number of records dropped.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(feature = "async")]
+impl fmt::Display for FlushError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} records queued before the flush were dropped", self.dropped)
+    }
+}
+
+#[cfg(feature = "async")]
+impl error::Error for FlushError {}
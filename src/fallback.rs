@@ -0,0 +1,34 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use std::io::{self, Write};
+
+/// Local stream records are also written to while the remote server is unreachable.
+#[derive(serde_derive::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Fallback {
+    /// Records are only sent to the remote server.
+    #[default]
+    None,
+    /// Records are written as GELF JSON lines to the standard output.
+    Stdout,
+    /// Records are written as GELF JSON lines to the standard error.
+    Stderr,
+}
+
+impl Fallback {
+    /// Writes one serialized message followed by a newline.
+    pub(crate) fn write(self, json: &[u8]) {
+        let _ = match self {
+            Fallback::None => Ok(()),
+            Fallback::Stdout => write_line(&mut io::stdout().lock(), json),
+            Fallback::Stderr => write_line(&mut io::stderr().lock(), json),
+        };
+    }
+}
+
+fn write_line<W: Write>(out: &mut W, json: &[u8]) -> io::Result<()> {
+    out.write_all(json)?;
+    out.write_all(b"\n")
+}
@@ -2,61 +2,723 @@
 // license that can be found in the LICENSE file.
 // Copyright 2009 The log4rs-gelf Authors. All rights reserved.
 
+use anyhow::{bail, Context};
 use appender::BufferAppenderBuilder;
-use gelf_logger::Value;
-use log::Level;
+use capture::DEFAULT_CAPTURE_MAX_BYTES;
+use fallback::Fallback;
+use log::{Level, LevelFilter, Record};
 use log4rs::append::Append;
 use log4rs::config::{Deserialize, Deserializers};
+use log4rs::encode::EncoderConfig;
+use message::{EmptyFieldPolicy, LevelField, OutputFormat};
+use naming::FieldSchema;
+use mirror::MirrorAppenderBuilder;
+use redact::Redaction;
+use router::{Route, RouterAppenderBuilder};
+use sender::{Delivery, OrderingPolicy, OverflowPolicy, DEFAULT_MAX_BATCH_BYTES, DEFAULT_MAX_BATCH_DELAY, DEFAULT_MAX_BATCH_RECORDS};
+use sender::{DEFAULT_ERROR_REPORT_INTERVAL, DEFAULT_MAX_MEMORY_BYTES};
+use serde_value::Value;
+use stats::DEFAULT_DROP_STATS_TARGETS;
 use std::collections::BTreeMap;
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
+use transport::{Framing, Protocol, DEFAULT_HTTP_PATH};
+
+/// Kinds under which the GELF appender is registered. `buffer` is kept for backward compatibility,
+/// `gelf_tcp` only exists along with the `tcp` transport.
+#[cfg(feature = "tcp")]
+pub(crate) const KINDS: &[&str] = &["buffer", "gelf", "gelf_tcp", MIRROR_KIND, ROUTER_KIND];
+#[cfg(not(feature = "tcp"))]
+pub(crate) const KINDS: &[&str] = &["buffer", "gelf", MIRROR_KIND, ROUTER_KIND];
+
+/// Kind of the [`MirrorAppender`](../struct.MirrorAppender.html).
+pub(crate) const MIRROR_KIND: &str = "gelf_mirror";
+
+/// Kind of the [`RouterAppender`](../struct.RouterAppender.html).
+pub(crate) const ROUTER_KIND: &str = "gelf_router";
+
+/// Environment variable naming the profile of the GELF appenders, unless `profile_env` names
+/// another one.
+const PROFILE_ENV: &str = "LOG4RS_GELF_PROFILE";
+
+/// Key of the appender configurations holding their [`BuildErrorPolicy`].
+const BUILD_ERROR_KEY: &str = "on_build_error";
+
+/// What becomes of an appender whose configuration is invalid, so that a bad GELF block doesn't
+/// have to break all logging for the process.
+#[derive(serde_derive::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+enum BuildErrorPolicy {
+    /// [`init_file`](../fn.init_file.html) fails. log4rs alone leaves the appender out.
+    #[default]
+    Fail,
+    /// The appender is left out, log4rs reporting why.
+    Skip,
+    /// A no-op appender takes its place, once the reason was written to the standard error.
+    Stub,
+}
+
+/// Takes the `on_build_error` setting out of an appender configuration.
+fn take_build_error_policy(config: &mut Value) -> anyhow::Result<BuildErrorPolicy> {
+    let policy = match config {
+        Value::Map(config) => config.remove(&Value::String(BUILD_ERROR_KEY.to_string())),
+        _ => None,
+    };
+    match policy {
+        Some(policy) => policy.deserialize_into().map_err(|err| anyhow::anyhow!("invalid {}: {}", BUILD_ERROR_KEY, err)),
+        None => Ok(BuildErrorPolicy::default()),
+    }
+}
+
+/// Builds an appender with `build`, applying the `on_build_error` setting of its configuration
+/// when it fails.
+fn build_with_policy<F>(config: Value, build: F) -> anyhow::Result<Box<dyn Append>>
+    where F: FnOnce(Value) -> anyhow::Result<Box<dyn Append>>
+{
+    let mut config = apply_profile(config);
+    let policy = take_build_error_policy(&mut config)?;
+    match build(config) {
+        Err(err) if policy == BuildErrorPolicy::Stub => {
+            eprintln!("log4rs-gelf appender replaced by a no-op one: {:#}", err);
+            Ok(Box::new(StubAppender))
+        }
+        built => built,
+    }
+}
+
+/// Appender dropping every record, standing for one that failed to build.
+#[derive(Debug)]
+struct StubAppender;
+
+impl Append for StubAppender {
+    fn append(&self, _record: &Record) -> anyhow::Result<()> {
+        Ok(())
+    }
+    fn flush(&self) {}
+}
 
 struct BufferAppenderDeserializer;
 
+struct MirrorAppenderDeserializer;
+
+struct RouterAppenderDeserializer;
+
 impl Deserialize for BufferAppenderDeserializer {
     type Trait = dyn Append;
-    type Config = Config;
+    type Config = Value;
 
     fn deserialize(
         &self,
-        config: Config,
-        _deserializers: &Deserializers,
+        config: Value,
+        deserializers: &Deserializers,
     ) -> Result<Box<dyn Append>, anyhow::Error> {
-        let appender = BufferAppenderBuilder::default()
-            .set_level(config.level.clone())
-            .set_hostname(config.hostname.clone().as_str())
-            .set_port(config.port.clone())
-            .set_null_character(config.null_character.clone())
-            .set_buffer_size(config.buffer_size.clone())
-            .extend_additional_field(config.additional_fields.clone())
-            .set_connect_timeout(config.connect_timeout.map_or(None,|v| Some(Duration::from_secs(v)) ))
-            .set_write_timeout(config.write_timeout.map_or(None,|v| Some(Duration::from_secs(v)) ));
-
-        #[cfg(feature = "tls")]
-        let appender = match true {
-            _ => appender.set_use_tls(config.use_tls.clone())
-        };
+        build_with_policy(config, |config| {
+            let config: Config = serde_path_to_error::deserialize(config)
+                .map_err(|err| AppenderConfigError::new(None, err, None))?;
+            Ok(Box::new(appender_builder(config, deserializers)?.build()?))
+        })
+    }
+}
 
-        Ok(Box::new(appender.build()?))
+impl Deserialize for MirrorAppenderDeserializer {
+    type Trait = dyn Append;
+    type Config = Value;
+
+    fn deserialize(
+        &self,
+        config: Value,
+        deserializers: &Deserializers,
+    ) -> Result<Box<dyn Append>, anyhow::Error> {
+        build_with_policy(config, |config| {
+            let config: MirrorConfig = serde_path_to_error::deserialize(config)
+                .map_err(|err| AppenderConfigError::new(None, err, None))?;
+            let mut mirror = MirrorAppenderBuilder::default();
+            for destination in config.destinations {
+                mirror = mirror.add_destination(appender_builder(destination, deserializers)?);
+            }
+            Ok(Box::new(mirror.build()?))
+        })
     }
 }
 
+impl Deserialize for RouterAppenderDeserializer {
+    type Trait = dyn Append;
+    type Config = Value;
+
+    fn deserialize(
+        &self,
+        config: Value,
+        deserializers: &Deserializers,
+    ) -> Result<Box<dyn Append>, anyhow::Error> {
+        build_with_policy(config, |config| {
+            let config: RouterConfig = serde_path_to_error::deserialize(config)
+                .map_err(|err| AppenderConfigError::new(None, err, None))?;
+            let mut router = RouterAppenderBuilder::default();
+            for route in config.routes {
+                router = router.add_route(route.rule.route(), appender_builder(route.destination, deserializers)?);
+            }
+            Ok(Box::new(router.build()?))
+        })
+    }
+}
+
+/// Builds the appender described by the `diagnostics` setting, with the deserializers of the
+/// log4rs configuration: any appender kind they know of will do.
+fn diagnostics_appender(config: Value, deserializers: &Deserializers) -> anyhow::Result<Box<dyn Append>> {
+    let mut config = match config {
+        Value::Map(config) => config,
+        _ => bail!("diagnostics must be an appender configuration"),
+    };
+    let kind = match config.remove(&Value::String("kind".to_string())) {
+        Some(Value::String(kind)) => kind,
+        _ => bail!("diagnostics has no kind"),
+    };
+    // log4rs has a serde-value of its own.
+    deserializers.deserialize(&kind, serde::Deserialize::deserialize(Value::Map(config))?)
+}
+
+/// Resolves a secret setting given as `file:PATH`, to the content of the file without its
+/// trailing newline, or as `env:VARIABLE`, to the value of the variable, so that secrets can stay
+/// out of the configuration file. Other values are returned as they are.
+fn resolve_secret(setting: &str, value: &str) -> anyhow::Result<String> {
+    if let Some(path) = value.strip_prefix("file:") {
+        let secret = fs::read_to_string(path).with_context(|| format!("{}: can't read {}", setting, path))?;
+        Ok(secret.trim_end_matches(&['\n', '\r'][..]).to_string())
+    } else if let Some(variable) = value.strip_prefix("env:") {
+        env::var(variable).with_context(|| format!("{}: can't read the {} environment variable", setting, variable))
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+/// Builder of the appender described by `config`.
+fn appender_builder(config: Config, deserializers: &Deserializers) -> anyhow::Result<BufferAppenderBuilder> {
+    let appender = BufferAppenderBuilder::default()
+        .set_level(config.level)
+        .extend_target_levels(config.target_levels.clone().unwrap_or_default())
+        .set_protocol(config.protocol.unwrap_or_default())
+        .set_hostname(&config.hostname)
+        .set_port(config.port)
+        .set_http_path(config.http_path.as_deref().unwrap_or(DEFAULT_HTTP_PATH))
+        .set_max_batch_records(config.max_batch_records.or(config.buffer_size).unwrap_or(DEFAULT_MAX_BATCH_RECORDS))
+        .set_max_batch_bytes(config.max_batch_bytes.unwrap_or(DEFAULT_MAX_BATCH_BYTES))
+        .set_max_batch_delay(config.max_batch_delay.map_or(DEFAULT_MAX_BATCH_DELAY, Duration::from_millis))
+        .extend_additional_field(config.additional_fields.clone())
+        .set_default_fields(config.default_fields.unwrap_or(true))
+        .extend_target_fields(config.target_fields.clone().unwrap_or_default())
+        .set_compression_threshold(config.compression_threshold)
+        .set_http_idle_timeout(config.http_idle_timeout.map(Duration::from_secs))
+        .set_connect_timeout(config.connect_timeout.map(Duration::from_secs))
+        .set_write_timeout(config.write_timeout.map(Duration::from_secs))
+        .set_max_memory_bytes(config.max_memory_bytes.unwrap_or(DEFAULT_MAX_MEMORY_BYTES))
+        .set_queue_capacity(config.queue_capacity)
+        .set_overflow_policy(config.overflow_policy.unwrap_or_default())
+        .set_delivery(config.delivery.unwrap_or_default())
+        .set_drop_stats_targets(config.drop_stats_targets.unwrap_or(DEFAULT_DROP_STATS_TARGETS))
+        .set_error_report_interval(config.error_report_interval.map_or(DEFAULT_ERROR_REPORT_INTERVAL, Duration::from_secs))
+        .set_capture_to_file(config.capture_to_file.clone())
+        .set_capture_max_bytes(config.capture_max_bytes.unwrap_or(DEFAULT_CAPTURE_MAX_BYTES))
+        .set_synchronous(config.synchronous.unwrap_or(false))
+        .set_tracing_spans(config.tracing_spans.unwrap_or(false))
+        .set_deferred_serialization(config.deferred_serialization.unwrap_or(true))
+        .set_connections(config.connections.unwrap_or(1))
+        .set_ordering(config.ordering.unwrap_or_default())
+        .set_fallback(config.fallback.unwrap_or_default())
+        .set_fallback_level(config.fallback_level.unwrap_or(Level::Trace))
+        .set_windows_fields(config.windows_fields.unwrap_or(false))
+        .set_host_tags(config.host_tags.clone().unwrap_or_default())
+        .set_priority_field(config.priority_field.unwrap_or(false))
+        .set_severity_field(config.severity_field.as_deref())
+        .set_level_field(config.level_field.unwrap_or_default())
+        .set_clock_offset_from_http(config.clock_offset_from_http.unwrap_or(false))
+        .set_field_schema(config.schema.unwrap_or_default())
+        .set_empty_field_policy(config.empty_field_policy.unwrap_or_default())
+        .set_max_field_bytes(config.max_field_bytes)
+        .set_full_message_chunk_bytes(config.full_message_chunk_bytes)
+        .set_correlation_id(config.correlation_id.unwrap_or(false))
+        .set_redactions(config.redact.as_deref().unwrap_or_default())
+        .set_format(config.format.unwrap_or_default())
+        .extend_envelope_fields(config.envelope_fields.clone().unwrap_or_default())
+        .set_strict(config.strict.unwrap_or(true))
+        .set_log_config(config.log_config.unwrap_or(false))
+        .set_drop_if(config.drop_if.clone().unwrap_or_default())
+        .set_only_if(config.only_if.clone().unwrap_or_default())
+        .set_record_ttl(config.record_ttl.map(Duration::from_secs))
+        .set_flush_timeout(config.flush_timeout.map(Duration::from_secs))
+        .set_stats_interval(config.stats_interval.map(Duration::from_secs))
+        .set_fold_window(config.fold_window.map(Duration::from_millis))
+        .set_priority_queue_level(config.priority_queue_level.unwrap_or(LevelFilter::Error));
+
+    let appender = match config.encoder {
+        Some(encoder) => appender.set_encoder(deserializers.deserialize(&encoder.kind, encoder.config)?),
+        None => appender,
+    };
+    let appender = match &config.encoder_field {
+        Some(encoder_field) => appender.set_encoder_field(encoder_field),
+        None => appender,
+    };
+    let appender = match (config.framing, config.null_character) {
+        (Some(framing), _) => appender.set_framing(framing),
+        (None, Some(null_character)) => appender.set_null_character(null_character),
+        (None, None) => appender,
+    };
+    let appender = match config.sanitize {
+        Some(sanitize) => appender.set_sanitize(sanitize),
+        None => appender,
+    };
+    let appender = config.enrichers.iter().flatten()
+        .fold(appender, |appender, enricher| appender.add_enricher(enricher));
+    let appender = match &config.short_message_template {
+        Some(template) => appender.set_short_message_template(template),
+        None => appender,
+    };
+    let appender = match &config.directives {
+        Some(directives) => appender.set_directives(directives),
+        None => appender,
+    };
+    let appender = match config.level_range {
+        Some((lowest, highest)) => appender.set_level_range(lowest, highest),
+        None => appender,
+    };
+    let appender = match config.clock_offset {
+        Some(offset) => {
+            let duration = Duration::try_from_secs_f64(offset.abs()).context("clock_offset isn't a number of seconds")?;
+            if offset < 0.0 { appender.set_clock_ahead(duration) } else { appender.set_clock_offset(duration) }
+        }
+        None => appender,
+    };
+    let appender = match &config.protocol_version {
+        Some(version) => appender.set_protocol_version(version),
+        None => appender,
+    };
+    let appender = match config.diagnostics {
+        Some(diagnostics) => appender.set_diagnostics(diagnostics_appender(diagnostics, deserializers)?),
+        None => appender,
+    };
+    let appender = match &config.name {
+        Some(name) => appender.set_name(name),
+        None => appender,
+    };
+    let mut appender = match &config.http_authorization {
+        Some(authorization) => appender.set_http_authorization(&resolve_secret("http_authorization", authorization)?),
+        None => appender,
+    };
+    for (key, value) in config.secret_fields.iter().flatten() {
+        appender = appender.put_secret_field(key, &resolve_secret(&format!("secret_fields.{}", key), value)?);
+    }
+
+    #[cfg(feature = "tls")]
+    let appender = appender.set_use_tls(config.use_tls);
+    #[cfg(feature = "tls")]
+    let appender = match &config.tls_pinned_cert_sha256 {
+        Some(fingerprint) => appender.set_tls_pinned_cert_sha256(fingerprint),
+        None => appender,
+    };
+    #[cfg(feature = "tls")]
+    let appender = appender.set_tls_verify_ca(config.tls_verify_ca.unwrap_or(true));
+    #[cfg(feature = "tls")]
+    let appender = match (&config.tls_client_cert, &config.tls_client_key) {
+        (Some(certificate), Some(key)) => appender.set_tls_client_identity(
+            &resolve_secret("tls_client_cert", certificate)?,
+            &resolve_secret("tls_client_key", key)?,
+        ),
+        (None, None) => appender,
+        _ => bail!("tls_client_cert and tls_client_key go together"),
+    };
+
+    Ok(appender)
+}
+
 pub fn deserializers() -> Deserializers {
     let mut d = Deserializers::default();
-    d.insert("buffer", BufferAppenderDeserializer);
+    for kind in KINDS {
+        if *kind == MIRROR_KIND {
+            d.insert(kind, MirrorAppenderDeserializer);
+        } else if *kind == ROUTER_KIND {
+            d.insert(kind, RouterAppenderDeserializer);
+        } else {
+            d.insert(kind, BufferAppenderDeserializer);
+        }
+    }
     d
 }
 
+/// Checks the GELF appenders of a YAML configuration file, so mistakes are reported with their
+/// location. Other formats, and files which aren't valid YAML, are left to log4rs.
+pub(crate) fn check_file(path: &Path) -> Result<(), AppenderConfigError> {
+    let is_yaml = path.extension().is_some_and(|ext| ext == "yml" || ext == "yaml");
+    let source = match fs::read_to_string(path) {
+        Ok(source) if is_yaml => source,
+        _ => return Ok(()),
+    };
+    let document: serde_yaml::Value = match serde_yaml::from_str(&source) {
+        Ok(document) => document,
+        Err(_) => return Ok(()),
+    };
+    let appenders = match document.get("appenders").and_then(serde_yaml::Value::as_mapping) {
+        Some(appenders) => appenders,
+        None => return Ok(()),
+    };
+
+    for (name, appender) in appenders {
+        let kind = appender.get("kind").and_then(serde_yaml::Value::as_str);
+        match kind {
+            Some(kind) if KINDS.contains(&kind) => {}
+            _ => continue,
+        }
+        let name = name.as_str().unwrap_or_default();
+        let mut appender = match serde_value::to_value(appender) {
+            Ok(appender) => apply_profile(appender),
+            Err(_) => continue,
+        };
+        // The deserializer reports invalid policies.
+        if take_build_error_policy(&mut appender).unwrap_or_default() != BuildErrorPolicy::Fail {
+            continue;
+        }
+        let checked = if kind == Some(MIRROR_KIND) {
+            serde_path_to_error::deserialize::<_, MirrorConfig>(appender).map(drop)
+        } else if kind == Some(ROUTER_KIND) {
+            serde_path_to_error::deserialize::<_, RouterConfig>(appender).map(drop)
+        } else {
+            serde_path_to_error::deserialize::<_, Config>(appender).map(drop)
+        };
+        if let Err(err) = checked {
+            let mut keys = vec!["appenders", name];
+            let path: Vec<String> = err.path().iter().map(|segment| segment.to_string()).collect();
+            keys.extend(path.iter().map(String::as_str));
+            let location = locate(&source, &keys);
+            return Err(AppenderConfigError::new(Some(name), err, location));
+        }
+    }
+    Ok(())
+}
+
+/// Overrides the base configuration of an appender with its profile selected by the environment,
+/// if any. Maps, such as `additional_fields`, are merged key by key.
+///
+/// ```yaml
+/// kind: gelf
+/// hostname: graylog.dev
+/// profiles:
+///   prod:
+///     hostname: graylog.prod
+///     level: Warn
+/// ```
+fn apply_profile(config: Value) -> Value {
+    let mut base = match config {
+        Value::Map(base) => base,
+        config => return config,
+    };
+    let profiles = base.remove(&Value::String("profiles".into()));
+    let profile_env = match base.remove(&Value::String("profile_env".into())) {
+        Some(Value::String(profile_env)) => profile_env,
+        _ => PROFILE_ENV.to_string(),
+    };
+    let profile = match (env::var(&profile_env), profiles) {
+        (Ok(name), Some(Value::Map(mut profiles))) => profiles.remove(&Value::String(name)),
+        _ => None,
+    };
+    if let Some(Value::Map(overrides)) = profile {
+        merge(&mut base, overrides);
+    }
+    Value::Map(base)
+}
+
+fn merge(base: &mut BTreeMap<Value, Value>, overrides: BTreeMap<Value, Value>) {
+    for (key, value) in overrides {
+        let value = match (base.get_mut(&key), value) {
+            (Some(Value::Map(nested)), Value::Map(value)) => {
+                merge(nested, value);
+                continue;
+            }
+            (_, value) => value,
+        };
+        base.insert(key, value);
+    }
+}
+
+/// Best-effort lookup of the line and column of a key in a block-style YAML document. Falls back
+/// to the deepest parent found.
+fn locate(source: &str, keys: &[&str]) -> Option<(usize, usize)> {
+    let mut keys = keys.iter();
+    let mut wanted = keys.next()?;
+    let mut parent_indent = None;
+    let mut found = None;
+
+    for (number, line) in source.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        if parent_indent.is_some_and(|parent| indent <= parent) {
+            break;
+        }
+        let is_key = [format!("{}:", wanted), format!("\"{}\":", wanted), format!("'{}':", wanted)]
+            .iter()
+            .any(|key| trimmed.starts_with(key.as_str()));
+        if is_key {
+            found = Some((number + 1, indent + 1));
+            parent_indent = Some(indent);
+            match keys.next() {
+                Some(next) => wanted = next,
+                None => break,
+            }
+        }
+    }
+    found
+}
+
+/// Invalid GELF appender configuration.
+#[derive(Debug)]
+pub struct AppenderConfigError {
+    appender: Option<String>,
+    key: Option<String>,
+    location: Option<(usize, usize)>,
+    message: String,
+}
+
+impl AppenderConfigError {
+    fn new<E: fmt::Display>(appender: Option<&str>, err: serde_path_to_error::Error<E>,
+                            location: Option<(usize, usize)>) -> AppenderConfigError {
+        let key = err.path().to_string();
+        AppenderConfigError {
+            appender: appender.map(str::to_string),
+            key: if key == "." { None } else { Some(key) },
+            location,
+            message: err.inner().to_string(),
+        }
+    }
+
+    /// Name of the appender, when known.
+    pub fn appender(&self) -> Option<&str> {
+        self.appender.as_deref()
+    }
+
+    /// Dotted path of the offending key, relative to the appender.
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    /// Line and column (starting at 1) of the offending key, or of its closest parent.
+    pub fn location(&self) -> Option<(usize, usize)> {
+        self.location
+    }
+}
+
+impl fmt::Display for AppenderConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid GELF appender")?;
+        if let Some(appender) = &self.appender {
+            write!(f, " `{}`", appender)?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, ", key `{}`", key)?;
+        }
+        if let Some((line, column)) = self.location {
+            write!(f, " at line {} column {}", line, column)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl error::Error for AppenderConfigError {}
+
 /// Struct to manipulate configuration.
 #[derive(serde_derive::Deserialize, Debug, Clone)]
 pub struct Config {
     level: Level,
+    target_levels: Option<BTreeMap<String, LevelFilter>>,
+    directives: Option<String>,
+    protocol: Option<Protocol>,
     hostname: String,
     port: u16,
-    null_character: bool,
+    name: Option<String>,
+    /// Legacy shorthand for `framing`.
+    null_character: Option<bool>,
+    framing: Option<Framing>,
+    http_path: Option<String>,
+    http_authorization: Option<String>,
+    /// Legacy name of `max_batch_records`.
     buffer_size: Option<usize>,
+    max_batch_records: Option<usize>,
+    max_batch_bytes: Option<usize>,
+    /// In milliseconds.
+    max_batch_delay: Option<u64>,
     additional_fields: BTreeMap<String, Value>,
+    /// Additional fields holding secrets, which are left out of the description of the settings.
+    secret_fields: Option<BTreeMap<String, String>>,
+    default_fields: Option<bool>,
+    target_fields: Option<BTreeMap<String, BTreeMap<String, Value>>>,
+    /// In seconds.
+    http_idle_timeout: Option<u64>,
+    compression_threshold: Option<usize>,
     connect_timeout: Option<u64>,
     write_timeout: Option<u64>,
+    max_memory_bytes: Option<usize>,
+    queue_capacity: Option<usize>,
+    overflow_policy: Option<OverflowPolicy>,
+    delivery: Option<Delivery>,
+    drop_stats_targets: Option<usize>,
+    /// In seconds.
+    error_report_interval: Option<u64>,
+    /// Configuration of the appender diagnostics are written to.
+    diagnostics: Option<Value>,
+    fallback: Option<Fallback>,
+    fallback_level: Option<Level>,
+    windows_fields: Option<bool>,
+    host_tags: Option<Vec<String>>,
+    priority_field: Option<bool>,
+    severity_field: Option<String>,
+    level_field: Option<LevelField>,
+    level_range: Option<(u32, u32)>,
+    /// In seconds, negative for a clock running ahead.
+    clock_offset: Option<f64>,
+    clock_offset_from_http: Option<bool>,
+    encoder: Option<EncoderConfig>,
+    encoder_field: Option<String>,
+    short_message_template: Option<String>,
+    sanitize: Option<bool>,
+    empty_field_policy: Option<EmptyFieldPolicy>,
+    max_field_bytes: Option<usize>,
+    full_message_chunk_bytes: Option<usize>,
+    correlation_id: Option<bool>,
+    redact: Option<Vec<Redaction>>,
+    enrichers: Option<Vec<String>>,
+    schema: Option<FieldSchema>,
+    format: Option<OutputFormat>,
+    protocol_version: Option<String>,
+    envelope_fields: Option<BTreeMap<String, Value>>,
+    strict: Option<bool>,
+    log_config: Option<bool>,
+    drop_if: Option<BTreeMap<String, Value>>,
+    only_if: Option<BTreeMap<String, Value>>,
+    /// In seconds.
+    record_ttl: Option<u64>,
+    /// In seconds.
+    flush_timeout: Option<u64>,
+    /// In seconds.
+    stats_interval: Option<u64>,
+    /// In milliseconds.
+    fold_window: Option<u64>,
+    capture_to_file: Option<PathBuf>,
+    capture_max_bytes: Option<u64>,
+    synchronous: Option<bool>,
+    tracing_spans: Option<bool>,
+    deferred_serialization: Option<bool>,
+    connections: Option<usize>,
+    ordering: Option<OrderingPolicy>,
+    priority_queue_level: Option<LevelFilter>,
     #[cfg(feature = "tls")]
     use_tls: bool,
-}
\ No newline at end of file
+    #[cfg(feature = "tls")]
+    tls_pinned_cert_sha256: Option<String>,
+    #[cfg(feature = "tls")]
+    tls_verify_ca: Option<bool>,
+    /// PEM certificate chain presented to the server.
+    #[cfg(feature = "tls")]
+    tls_client_cert: Option<String>,
+    /// PEM PKCS #8 key of `tls_client_cert`.
+    #[cfg(feature = "tls")]
+    tls_client_key: Option<String>,
+}
+/// Configuration of a [`MirrorAppender`](../struct.MirrorAppender.html).
+#[derive(serde_derive::Deserialize, Debug, Clone)]
+pub(crate) struct MirrorConfig {
+    destinations: Vec<Config>,
+}
+/// Configuration of a [`RouterAppender`](../struct.RouterAppender.html).
+#[derive(serde_derive::Deserialize, Debug, Clone)]
+pub(crate) struct RouterConfig {
+    routes: Vec<RouteConfig>,
+}
+#[derive(serde_derive::Deserialize, Debug, Clone)]
+pub(crate) struct RouteConfig {
+    #[serde(rename = "match", default)]
+    rule: RouteRule,
+    destination: Config,
+}
+/// Conditions of a [`Route`](../struct.Route.html).
+#[derive(serde_derive::Deserialize, Debug, Clone, Default)]
+pub(crate) struct RouteRule {
+    min_level: Option<Level>,
+    max_level: Option<Level>,
+    targets: Option<Vec<String>>,
+    fields: Option<BTreeMap<String, Value>>,
+}
+
+impl RouteRule {
+    fn route(self) -> Route {
+        let mut route = Route::default();
+        if let Some(level) = self.min_level {
+            route = route.set_min_level(level);
+        }
+        if let Some(level) = self.max_level {
+            route = route.set_max_level(level);
+        }
+        for target in self.targets.unwrap_or_default() {
+            route = route.add_target(&target);
+        }
+        route.set_fields(self.fields.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A GELF appender configuration whose port is invalid, with the given `on_build_error`.
+    fn invalid(policy: Option<&str>) -> String {
+        let policy = policy.map(|policy| format!("\n    on_build_error: {}", policy)).unwrap_or_default();
+        format!("appenders:\n  graylog:\n    kind: gelf\n    hostname: localhost\n    port: not a port{}\n", policy)
+    }
+
+    /// Builds the `graylog` appender of `yaml` as log4rs does, without its `kind`.
+    fn build(yaml: &str) -> anyhow::Result<Box<dyn Append>> {
+        let document: serde_yaml::Value = serde_yaml::from_str(yaml).unwrap();
+        let mut config = document["appenders"]["graylog"].clone();
+        config.as_mapping_mut().unwrap().remove("kind");
+        BufferAppenderDeserializer.deserialize(serde_value::to_value(config).unwrap(), &deserializers())
+    }
+
+    /// Checks `yaml` as `init_file` does, from a file of its own.
+    fn check(name: &str, yaml: &str) -> Result<(), AppenderConfigError> {
+        let path = env::temp_dir().join(format!("log4rs-gelf-{}-{}.yaml", name, std::process::id()));
+        fs::write(&path, yaml).unwrap();
+        let checked = check_file(&path);
+        fs::remove_file(&path).unwrap();
+        checked
+    }
+
+    #[test]
+    fn invalid_appenders_fail_by_default() {
+        for policy in &[None, Some("fail")] {
+            assert!(build(&invalid(*policy)).is_err());
+            let err = check("fail", &invalid(*policy)).unwrap_err();
+            assert!(err.to_string().contains("port"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn skipped_appenders_are_left_to_log4rs() {
+        assert!(build(&invalid(Some("skip"))).is_err());
+        assert!(check("skip", &invalid(Some("skip"))).is_ok());
+    }
+
+    #[test]
+    fn stubbed_appenders_drop_records() {
+        let appender = build(&invalid(Some("stub"))).unwrap();
+        assert!(appender.append(&Record::builder().args(format_args!("dropped")).level(Level::Error).build()).is_ok());
+        assert!(check("stub", &invalid(Some("stub"))).is_ok());
+    }
+
+    #[test]
+    fn unknown_policies_are_rejected() {
+        let err = build(&invalid(Some("retry"))).unwrap_err();
+        assert!(err.to_string().starts_with("invalid on_build_error"), "{}", err);
+    }
+}
@@ -0,0 +1,191 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use log::LevelFilter;
+use message::{field_name, GelfMessage};
+use serde_value::Value;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+/// Level thresholds per target prefix. The longest matching prefix wins.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct TargetLevels {
+    /// Sorted by decreasing prefix length.
+    rules: Vec<(String, LevelFilter)>,
+}
+
+impl TargetLevels {
+    pub(crate) fn insert(&mut self, prefix: &str, level: LevelFilter) {
+        self.rules.retain(|rule| rule.0 != prefix);
+        self.rules.push((prefix.to_string(), level));
+        self.rules.sort_by_key(|rule| Reverse(rule.0.len()));
+    }
+
+    /// Returns the threshold of the most specific rule matching `target`, if any.
+    pub(crate) fn level_for(&self, target: &str) -> Option<LevelFilter> {
+        self.rules.iter()
+            .find(|rule| matches_prefix(target, &rule.0))
+            .map(|rule| rule.1)
+    }
+
+    pub(crate) fn rules(&self) -> &[(String, LevelFilter)] {
+        &self.rules
+    }
+}
+
+/// Parses `env_logger` directives, as found in `RUST_LOG`: comma-separated `target=level`,
+/// `target` (enabling all its records) or `level` (for every target) entries. Returns the
+/// thresholds by target prefix, the empty prefix standing for every target.
+pub(crate) fn parse_directives(directives: &str) -> Result<Vec<(String, LevelFilter)>, String> {
+    if directives.contains('/') {
+        return Err(format!("directives {:?} filter messages, which isn't supported", directives));
+    }
+    directives.split(',')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(|directive| match directive.split_once('=') {
+            Some((target, level)) => match level.trim().parse() {
+                Ok(level) if !target.trim().is_empty() => Ok((target.trim().to_string(), level)),
+                _ => Err(format!("invalid directive {:?}", directive)),
+            },
+            None => match directive.parse() {
+                Ok(level) => Ok((String::new(), level)),
+                Err(_) => Ok((directive.to_string(), LevelFilter::Trace)),
+            },
+        })
+        .collect()
+}
+
+/// Whether `target` is the module `prefix` or one of its submodules.
+pub(crate) fn matches_prefix(target: &str, prefix: &str) -> bool {
+    target.starts_with(prefix) && {
+        let rest = &target[prefix.len()..];
+        rest.is_empty() || rest.starts_with("::") || prefix.is_empty()
+    }
+}
+
+/// Conditions on the additional fields of a message, record and static fields merged.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct FieldFilter {
+    /// Messages having all these field values are dropped.
+    drop_if: Vec<(String, Value)>,
+    /// Only messages having all these field values are kept.
+    only_if: Vec<(String, Value)>,
+}
+
+impl FieldFilter {
+    pub(crate) fn set_drop_if(&mut self, fields: BTreeMap<String, Value>) {
+        self.drop_if = conditions(fields);
+    }
+
+    pub(crate) fn set_only_if(&mut self, fields: BTreeMap<String, Value>) {
+        self.only_if = conditions(fields);
+    }
+
+    pub(crate) fn drop_if(&self) -> &[(String, Value)] {
+        &self.drop_if
+    }
+
+    pub(crate) fn only_if(&self) -> &[(String, Value)] {
+        &self.only_if
+    }
+
+    /// Whether a condition reads the field called `key`.
+    pub(crate) fn reads(&self, key: &str) -> bool {
+        self.drop_if.iter().chain(&self.only_if).any(|(name, _)| name == key)
+    }
+
+    pub(crate) fn accepts(&self, message: &GelfMessage) -> bool {
+        let all_match = |conditions: &[(String, Value)]| {
+            conditions.iter().all(|(key, expected)| {
+                message.field(key).is_some_and(|actual| field_matches(actual, expected))
+            })
+        };
+        (self.drop_if.is_empty() || !all_match(&self.drop_if))
+            && (self.only_if.is_empty() || all_match(&self.only_if))
+    }
+}
+
+fn conditions(fields: BTreeMap<String, Value>) -> Vec<(String, Value)> {
+    fields.into_iter().map(|(key, value)| (field_name(&key), value)).collect()
+}
+
+/// Values are equal, or have the same text: `5` in a configuration file matches a `5u8` field.
+pub(crate) fn field_matches(actual: &Value, expected: &Value) -> bool {
+    actual == expected || match (scalar_text(actual), scalar_text(expected)) {
+        (Some(actual), Some(expected)) => actual == expected,
+        _ => false,
+    }
+}
+
+fn scalar_text(value: &Value) -> Option<String> {
+    Some(match value {
+        Value::Bool(value) => value.to_string(),
+        Value::U8(value) => value.to_string(),
+        Value::U16(value) => value.to_string(),
+        Value::U32(value) => value.to_string(),
+        Value::U64(value) => value.to_string(),
+        Value::I8(value) => value.to_string(),
+        Value::I16(value) => value.to_string(),
+        Value::I32(value) => value.to_string(),
+        Value::I64(value) => value.to_string(),
+        Value::F32(value) => value.to_string(),
+        Value::F64(value) => value.to_string(),
+        Value::Char(value) => value.to_string(),
+        Value::String(value) => value.clone(),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(directives: &str) -> TargetLevels {
+        let mut levels = TargetLevels::default();
+        for (prefix, level) in parse_directives(directives).unwrap() {
+            levels.insert(&prefix, level);
+        }
+        levels
+    }
+
+    #[test]
+    fn directives_are_parsed() {
+        assert_eq!(parse_directives(" warn, hyper=info ,, my_app::db ").unwrap(), vec![
+            (String::new(), LevelFilter::Warn),
+            ("hyper".to_string(), LevelFilter::Info),
+            ("my_app::db".to_string(), LevelFilter::Trace),
+        ]);
+        assert_eq!(parse_directives("hyper=OFF").unwrap(), vec![("hyper".to_string(), LevelFilter::Off)]);
+        assert_eq!(parse_directives("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn the_most_specific_directive_wins() {
+        let levels = levels("info,my_app=debug,my_app::db=error");
+        assert_eq!(levels.level_for("hyper"), Some(LevelFilter::Info));
+        assert_eq!(levels.level_for("my_app"), Some(LevelFilter::Debug));
+        assert_eq!(levels.level_for("my_app::http"), Some(LevelFilter::Debug));
+        assert_eq!(levels.level_for("my_app::db::pool"), Some(LevelFilter::Error));
+        // A prefix only matches whole modules.
+        assert_eq!(levels.level_for("my_application"), Some(LevelFilter::Info));
+    }
+
+    #[test]
+    fn later_directives_replace_earlier_ones() {
+        assert_eq!(levels("debug,warn").level_for("any"), Some(LevelFilter::Warn));
+        let levels = levels("my_app=debug,warn,my_app=error");
+        assert_eq!(levels.level_for("my_app"), Some(LevelFilter::Error));
+        assert_eq!(levels.rules().len(), 2);
+    }
+
+    #[test]
+    fn malformed_directives_are_rejected() {
+        for directives in &["my_app=loud", "=info", "my_app=", "info,my_app=debug=trace"] {
+            assert!(parse_directives(directives).is_err(), "{:?} was accepted", directives);
+        }
+        let error = parse_directives("info,my_app/foo").unwrap_err();
+        assert!(error.contains("filter messages"), "{}", error);
+    }
+}
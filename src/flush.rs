@@ -0,0 +1,141 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use std::cmp;
+#[cfg(feature = "async")]
+use std::task::Poll;
+use std::task::Waker;
+
+/// Numbers the queued records and keeps track of the flushes waiting for them. A flush is
+/// complete once every record queued before it was sent or dropped.
+#[derive(Default)]
+pub(crate) struct FlushTracker {
+    next_seq: u64,
+    /// Lowest sequence number of each batch being written, one per connection.
+    in_flight: Vec<u64>,
+    next_id: u64,
+    waiters: Vec<Waiter>,
+}
+
+/// Registered by `flush_async` and guaranteed deliveries.
+struct Waiter {
+    id: u64,
+    /// Sequence number of the first record waited for.
+    from: u64,
+    /// Sequence number of the first record queued after the flush.
+    target: u64,
+    /// Records the flush waits for which were dropped.
+    dropped: u64,
+    waker: Option<Waker>,
+}
+
+impl FlushTracker {
+    /// Numbers a record being queued.
+    pub(crate) fn assign(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
+    }
+
+    pub(crate) fn start_batch(&mut self, seq: u64) {
+        self.in_flight.push(seq);
+    }
+
+    /// The batch starting at `seq` was sent or dropped.
+    pub(crate) fn end_batch(&mut self, seq: u64) {
+        if let Some(index) = self.in_flight.iter().position(|&in_flight| in_flight == seq) {
+            self.in_flight.swap_remove(index);
+        }
+        for waiter in &mut self.waiters {
+            wake(waiter);
+        }
+    }
+
+    /// Forgets the batches being written, by threads which no longer exist after a fork.
+    pub(crate) fn abandon_batches(&mut self) {
+        self.in_flight.clear();
+    }
+
+    /// A queued record was dropped instead of sent.
+    pub(crate) fn dropped(&mut self, seq: u64) {
+        for waiter in &mut self.waiters {
+            if waiter.from <= seq && seq < waiter.target {
+                waiter.dropped += 1;
+                wake(waiter);
+            }
+        }
+    }
+
+    /// Sequence number of the next record to be queued. A flush waits for the records before.
+    pub(crate) fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// Sequence number of the oldest record neither sent nor dropped, or of the next one if there
+    /// is none. `queue_front` is the sequence number of the oldest queued record, which priority
+    /// records may have overtaken.
+    pub(crate) fn oldest_pending(&self, queue_front: Option<u64>) -> u64 {
+        let in_flight = self.in_flight.iter().cloned().min();
+        match (in_flight, queue_front) {
+            (Some(in_flight), Some(queue_front)) => cmp::min(in_flight, queue_front),
+            (in_flight, queue_front) => in_flight.or(queue_front).unwrap_or(self.next_seq),
+        }
+    }
+
+    /// Starts waiting for the records queued so far. Returns the id of the flush.
+    #[cfg(feature = "async")]
+    pub(crate) fn register(&mut self) -> u64 {
+        let target = self.next_seq;
+        self.add_waiter(0, target)
+    }
+
+    /// Starts waiting for the record numbered `seq` alone, the records queued before aside,
+    /// although it only completes once they are done with too. Returns the id of the wait.
+    pub(crate) fn register_record(&mut self, seq: u64) -> u64 {
+        self.add_waiter(seq, seq + 1)
+    }
+
+    fn add_waiter(&mut self, from: u64, target: u64) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.waiters.push(Waiter { id, from, target, dropped: 0, waker: None });
+        id
+    }
+
+    /// Returns the number of records dropped once the flush is complete.
+    #[cfg(feature = "async")]
+    pub(crate) fn poll(&mut self, id: u64, queue_front: Option<u64>, waker: &Waker) -> Poll<u64> {
+        if let Some(dropped) = self.finish(id, queue_front) {
+            return Poll::Ready(dropped);
+        }
+        if let Some(waiter) = self.waiters.iter_mut().find(|waiter| waiter.id == id) {
+            waiter.waker = Some(waker.clone());
+        }
+        Poll::Pending
+    }
+
+    /// Returns the number of records dropped, and stops waiting, once the wait is complete.
+    pub(crate) fn finish(&mut self, id: u64, queue_front: Option<u64>) -> Option<u64> {
+        let oldest_pending = self.oldest_pending(queue_front);
+        let index = match self.waiters.iter().position(|waiter| waiter.id == id) {
+            Some(index) => index,
+            None => return Some(0),
+        };
+        if oldest_pending >= self.waiters[index].target {
+            return Some(self.waiters.swap_remove(index).dropped);
+        }
+        None
+    }
+
+    /// Stops waiting, the flush was abandoned.
+    pub(crate) fn cancel(&mut self, id: u64) {
+        self.waiters.retain(|waiter| waiter.id != id);
+    }
+}
+
+fn wake(waiter: &mut Waiter) {
+    if let Some(waker) = waiter.waker.take() {
+        waker.wake();
+    }
+}
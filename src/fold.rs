@@ -0,0 +1,187 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use background::{self, Background};
+use log::Level;
+use message::truncate_field;
+use sender::{Entry, SenderHandle};
+use serde::de::{self, Deserialize, Deserializer, MapAccess};
+use serde::ser::{Serialize, Serializer};
+use serde_json::Value;
+use std::fmt;
+use std::io;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Prefixes of the lines continuing a stack trace, besides indented ones.
+const CONTINUATIONS: [&str; 2] = ["at ", "Caused by:"];
+
+/// Folds the continuation lines of multi-line stack traces, logged as records of their own, e.g.
+/// when forwarding the output of a subprocess, into the `full_message` of the record they follow.
+///
+/// Each message is held back until a record which doesn't continue it comes, or no line was
+/// folded in for `window`.
+#[derive(Debug)]
+pub(crate) struct Folder {
+    window: Duration,
+    /// Longest `full_message`, in bytes.
+    max_field_bytes: Option<usize>,
+    pending: Mutex<Option<Pending>>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    payload: Vec<u8>,
+    level: Level,
+    target: String,
+    lines: Vec<String>,
+    /// When the message or its last line was logged.
+    updated: Instant,
+}
+
+impl Folder {
+    pub(crate) fn new(window: Duration, max_field_bytes: Option<usize>) -> Folder {
+        Folder { window, max_field_bytes, pending: Mutex::new(None) }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Pending>> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Folds the line logged on `target` into the message held back if it continues it. Returns
+    /// `false` otherwise. The line is only rendered when a message is held back, and only goes
+    /// through `clean` once it was recognized, so that the sanitizing and redactions can't hide
+    /// its indentation or its prefix.
+    pub(crate) fn fold<F, C>(&self, target: &str, line: F, clean: C) -> bool
+        where F: FnOnce() -> String, C: FnOnce(String) -> String
+    {
+        let mut pending = self.lock();
+        let pending = match pending.as_mut() {
+            Some(pending) if pending.target == target && pending.updated.elapsed() < self.window => pending,
+            _ => return false,
+        };
+        let line = line();
+        if !is_continuation(&line) {
+            return false;
+        }
+        pending.lines.push(clean(line));
+        pending.updated = Instant::now();
+        true
+    }
+
+    /// Holds a message back, in case the next records continue it. Returns the message held back
+    /// until then, with its lines folded in.
+    pub(crate) fn hold(&self, payload: Vec<u8>, level: Level, target: &str) -> Option<Entry> {
+        let held = Pending { payload, level, target: target.to_string(), lines: Vec::new(), updated: Instant::now() };
+        let previous = self.lock().replace(held);
+        previous.map(|previous| self.entry(previous))
+    }
+
+    /// Takes the message held back out, once no line was folded in for `window` unless `force`.
+    pub(crate) fn take(&self, force: bool) -> Option<Entry> {
+        let mut pending = self.lock();
+        if !force && pending.as_ref().is_some_and(|pending| pending.updated.elapsed() < self.window) {
+            return None;
+        }
+        pending.take().map(|pending| self.entry(pending))
+    }
+
+    fn entry(&self, pending: Pending) -> Entry {
+        let payload = if pending.lines.is_empty() {
+            None
+        } else {
+            fold_lines(&pending.payload, &pending.lines, self.max_field_bytes)
+        };
+        Entry::new(payload.unwrap_or(pending.payload), pending.level, &pending.target)
+    }
+}
+
+fn is_continuation(line: &str) -> bool {
+    line.starts_with(char::is_whitespace) || CONTINUATIONS.iter().any(|prefix| line.starts_with(prefix))
+}
+
+/// Appends `lines` to the `full_message` of a serialized message, which starts with the
+/// `short_message` if it had none. The other keys keep their order, `full_message` its place or
+/// comes last. `None` if the payload isn't a JSON object.
+fn fold_lines(payload: &[u8], lines: &[String], max_field_bytes: Option<usize>) -> Option<Vec<u8>> {
+    let mut message: Object = serde_json::from_slice(payload).ok()?;
+    let head = ["full_message", "short_message", "message"].iter()
+        .find_map(|key| message.get(key).and_then(Value::as_str))
+        .unwrap_or_default();
+    let mut full_message = head.to_string();
+    for line in lines {
+        full_message.push('\n');
+        full_message.push_str(line);
+    }
+    let mut full_message = serde_value::Value::String(full_message);
+    if let Some(max_field_bytes) = max_field_bytes {
+        truncate_field(&mut full_message, max_field_bytes);
+    }
+    if let serde_value::Value::String(full_message) = full_message {
+        message.set("full_message", Value::String(full_message));
+    }
+    serde_json::to_vec(&message).ok()
+}
+
+/// The keys and values of a JSON object, in the order they were serialized.
+struct Object(Vec<(String, Value)>);
+
+impl Object {
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.0.iter().find(|(name, _)| name == key).map(|(_, value)| value)
+    }
+
+    fn set(&mut self, key: &str, value: Value) {
+        match self.0.iter_mut().find(|(name, _)| name == key) {
+            Some((_, previous)) => *previous = value,
+            None => self.0.push((key.to_string(), value)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Object {
+    fn deserialize<D>(deserializer: D) -> Result<Object, D::Error> where D: Deserializer<'de> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Object;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a JSON object")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Object, A::Error> where A: MapAccess<'de> {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Object(entries))
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+impl Serialize for Object {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.collect_map(self.0.iter().map(|(key, value)| (key, value)))
+    }
+}
+
+/// Starts a thread sending the message held back by `folder` once its window elapsed. It stops
+/// once the returned handle is dropped, sending the message held back if any.
+pub(crate) fn spawn(folder: Arc<Folder>, sender: SenderHandle) -> io::Result<Background> {
+    background::spawn("gelf-fold", move |stopped| {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(folder.window) {
+            if let Some(entry) = folder.take(false) {
+                sender.send(entry);
+            }
+        }
+        if let Some(entry) = folder.take(true) {
+            sender.send(entry);
+        }
+    })
+}
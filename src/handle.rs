@@ -0,0 +1,103 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+#[cfg(feature = "async")]
+use error::FlushError;
+use sender::{PendingMessage, SenderHandle};
+use stats::{Stats, StatsCollector};
+use std::fmt;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// Handles of the appenders built with a name, the latest one winning.
+static HANDLES: Mutex<Vec<(String, GelfHandle)>> = Mutex::new(Vec::new());
+
+/// Runtime controls of a [`BufferAppender`](struct.BufferAppender.html), for code that can't
+/// reach the appender itself, such as the one only holding the `log4rs::Handle` once the
+/// configuration is installed.
+///
+/// Handles are looked up with [`handle`](fn.handle.html), by the name given to the appender with
+/// [`set_name`](struct.BufferAppenderBuilder.html#method.set_name) or the `name` setting of the
+/// configuration file. Once the appender is dropped, its records are gone and flushes time out.
+///
+/// ## Example
+///
+/// ```rust
+/// let appender = log4rs_gelf::BufferAppender::builder()
+///     .set_name("graylog")
+///     .set_hostname("graylog.example.com")
+///     .set_port(12201)
+///     .build()
+///     .expect("Failed to create appender");
+///
+/// // Anywhere else, e.g. in a health check.
+/// if let Some(handle) = log4rs_gelf::handle("graylog") {
+///     println!("{} sent", handle.stats().sent);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct GelfHandle {
+    sender: SenderHandle,
+    stats: Arc<StatsCollector>,
+    flush_timeout: Option<Duration>,
+}
+
+impl GelfHandle {
+    pub(crate) fn new(sender: SenderHandle, stats: Arc<StatsCollector>, flush_timeout: Option<Duration>) -> GelfHandle {
+        GelfHandle { sender, stats, flush_timeout }
+    }
+    /// Ships the queued records without waiting for the batch to fill up, then waits for them like
+    /// the appender does, up to its flush timeout if any.
+    pub fn flush(&self) {
+        match self.flush_timeout {
+            Some(timeout) => {
+                self.sender.flush_wait(timeout);
+            }
+            None => self.sender.flush(),
+        }
+    }
+    /// Same as [`BufferAppender::flush_async`](struct.BufferAppender.html#method.flush_async).
+    #[cfg(feature = "async")]
+    pub fn flush_async(&self) -> impl Future<Output = Result<(), FlushError>> + Send + 'static {
+        self.sender.flush_async()
+    }
+    /// Same as [`BufferAppender::stats`](struct.BufferAppender.html#method.stats).
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+    /// Same as [`BufferAppender::drain_pending`](struct.BufferAppender.html#method.drain_pending).
+    pub fn drain_pending(&self, max: usize) -> Vec<PendingMessage> {
+        self.sender.drain(max)
+    }
+}
+
+impl fmt::Debug for GelfHandle {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("GelfHandle").finish()
+    }
+}
+
+/// Handle of the latest appender built with the given name, if any.
+pub fn handle(name: &str) -> Option<GelfHandle> {
+    HANDLES.lock().unwrap_or_else(PoisonError::into_inner).iter()
+        .find(|(registered, _)| registered == name)
+        .map(|(_, handle)| handle.clone())
+}
+
+/// Makes `handle` the one of `name`, replacing the handle of an appender built before, e.g. by an
+/// earlier configuration.
+pub(crate) fn register(name: &str, handle: GelfHandle) {
+    let mut handles = HANDLES.lock().unwrap_or_else(PoisonError::into_inner);
+    handles.retain(|(registered, _)| registered != name);
+    handles.push((name.to_string(), handle));
+}
+
+/// Forgets the handle of `name` if it's still the one of `sender`, whose appender is dropped, so
+/// that the registry doesn't keep its queue alive.
+pub(crate) fn unregister(name: &str, sender: &SenderHandle) {
+    let mut handles = HANDLES.lock().unwrap_or_else(PoisonError::into_inner);
+    handles.retain(|(registered, handle)| registered != name || !handle.sender.is_same(sender));
+}
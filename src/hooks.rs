@@ -0,0 +1,190 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use error::Error;
+use log::{Level, Record};
+use log4rs::append::Append;
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Called with the error which closed the connection.
+type ErrorHook = dyn Fn(&Error) + Send + Sync;
+/// Called with the number of records and bytes of a batch delivered, and how long it took.
+type BatchHook = dyn Fn(usize, usize, Duration) + Send + Sync;
+/// Called with each transport error, shared with the other handlers.
+type ErrorHandler = dyn Fn(&Arc<Error>) + Send + Sync;
+
+/// Callbacks invoked from the background sender when the state of the connection changes, when
+/// a batch was delivered, and when writing failed.
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionHooks {
+    pub(crate) on_connect: Option<Arc<dyn Fn() + Send + Sync>>,
+    pub(crate) on_disconnect: Option<Arc<ErrorHook>>,
+    pub(crate) on_reconnect: Option<Arc<dyn Fn() + Send + Sync>>,
+    pub(crate) on_batch_sent: Option<Arc<BatchHook>>,
+    /// Hands transport errors over to the application, instead of the diagnostics.
+    pub(crate) on_error: Option<Arc<ErrorHandler>>,
+    pub(crate) diagnostics: Diagnostics,
+}
+
+impl ConnectionHooks {
+    pub(crate) fn connected(&self, reconnection: bool) {
+        let hook = if reconnection { &self.on_reconnect } else { &self.on_connect };
+        if let Some(hook) = hook {
+            hook();
+        }
+    }
+
+    pub(crate) fn disconnected(&self, err: &Error) {
+        if let Some(hook) = &self.on_disconnect {
+            hook(err);
+        }
+    }
+
+    /// Returns `false` if no one takes the error.
+    pub(crate) fn error(&self, err: &Arc<Error>) -> bool {
+        match &self.on_error {
+            Some(hook) => {
+                hook(err);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn batch_sent(&self, records: usize, bytes: usize, duration: Duration) {
+        if let Some(hook) = &self.on_batch_sent {
+            hook(records, bytes, duration);
+        }
+    }
+}
+
+/// Target of the records written to the diagnostics appender.
+const DIAGNOSTICS_TARGET: &str = "log4rs_gelf";
+
+/// Where the appender writes its own diagnostics, such as transport errors: the standard error,
+/// or another log4rs appender.
+#[derive(Clone, Default)]
+pub(crate) struct Diagnostics(pub(crate) Option<Arc<dyn Append>>);
+
+impl Diagnostics {
+    /// Writes `message`, to the standard error if the diagnostics appender fails.
+    pub(crate) fn report(&self, level: Level, message: &str) {
+        let appender = match &self.0 {
+            Some(appender) => appender,
+            None => {
+                eprintln!("{}", message);
+                return;
+            }
+        };
+        let appended = appender.append(&Record::builder()
+            .level(level)
+            .target(DIAGNOSTICS_TARGET)
+            .args(format_args!("{}", message))
+            .build());
+        if let Err(err) = appended {
+            eprintln!("{} (diagnostics appender failed: {})", message, err);
+        }
+    }
+}
+
+/// Holds back transport errors identical to the last one reported, for `interval`, so that a long
+/// outage doesn't flood the diagnostics or the error handler. The next error reported after
+/// that tells how many were held back.
+#[derive(Debug)]
+pub(crate) struct ErrorThrottle {
+    interval: Duration,
+    /// Message of the last error reported, and when.
+    last: Option<(String, Instant)>,
+    held_back: u64,
+}
+
+impl ErrorThrottle {
+    pub(crate) fn new(interval: Duration) -> ErrorThrottle {
+        ErrorThrottle { interval, last: None, held_back: 0 }
+    }
+
+    /// Returns the number of errors held back since the last one reported if `err` is to be
+    /// reported, `None` otherwise.
+    pub(crate) fn admit(&mut self, err: &Error) -> Option<u64> {
+        let message = err.to_string();
+        let now = Instant::now();
+        if let Some((last, at)) = &self.last {
+            if *last == message && now.duration_since(*at) < self.interval {
+                self.held_back += 1;
+                return None;
+            }
+        }
+        self.last = Some((message, now));
+        Some(mem::replace(&mut self.held_back, 0))
+    }
+}
+
+/// Hooks are equal when they are the same closures.
+impl PartialEq for ConnectionHooks {
+    fn eq(&self, other: &ConnectionHooks) -> bool {
+        fn same<T: ?Sized>(a: &Option<Arc<T>>, b: &Option<Arc<T>>) -> bool {
+            match (a, b) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+        }
+        same(&self.on_connect, &other.on_connect)
+            && same(&self.on_disconnect, &other.on_disconnect)
+            && same(&self.on_reconnect, &other.on_reconnect)
+            && same(&self.on_batch_sent, &other.on_batch_sent)
+            && same(&self.on_error, &other.on_error)
+            && same(&self.diagnostics.0, &other.diagnostics.0)
+    }
+}
+
+impl fmt::Debug for ConnectionHooks {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ConnectionHooks")
+            .field("on_connect", &self.on_connect.is_some())
+            .field("on_disconnect", &self.on_disconnect.is_some())
+            .field("on_reconnect", &self.on_reconnect.is_some())
+            .field("on_batch_sent", &self.on_batch_sent.is_some())
+            .field("on_error", &self.on_error.is_some())
+            .field("diagnostics", &self.diagnostics.0.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn repeated_errors_are_held_back_within_the_interval() {
+        let mut throttle = ErrorThrottle::new(Duration::from_secs(60));
+        let refused = Error::Template("refused".to_string());
+        assert_eq!(throttle.admit(&refused), Some(0));
+        assert_eq!(throttle.admit(&refused), None);
+        assert_eq!(throttle.admit(&refused), None);
+        // Another error is reported at once, along with the count of those held back.
+        assert_eq!(throttle.admit(&Error::Http(503)), Some(2));
+        assert_eq!(throttle.admit(&Error::Http(503)), None);
+        assert_eq!(throttle.admit(&refused), Some(1));
+    }
+
+    #[test]
+    fn the_same_error_is_reported_again_once_the_interval_elapsed() {
+        let mut throttle = ErrorThrottle::new(Duration::from_millis(50));
+        let refused = Error::Template("refused".to_string());
+        assert_eq!(throttle.admit(&refused), Some(0));
+        assert_eq!(throttle.admit(&refused), None);
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(throttle.admit(&refused), Some(1));
+
+        // Without an interval, nothing is held back.
+        let mut throttle = ErrorThrottle::new(Duration::from_secs(0));
+        assert_eq!(throttle.admit(&refused), Some(0));
+        assert_eq!(throttle.admit(&refused), Some(0));
+    }
+}
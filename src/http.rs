@@ -0,0 +1,431 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use error::Error;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use stats::StatsCollector;
+use std::borrow::Cow;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use timestamp::parse_http_date;
+use transport::{not_connected, Connection, Connector, GelfTransport, Secret, TransportConfig};
+
+/// GELF HTTP transport: each message is posted on a kept-alive HTTP/1.1 connection, and only a
+/// `2xx` answer counts as delivered. A `4xx` answer other than `408` and `429` rejects the message
+/// for good, the sender drops it rather than retrying; after the others, it waits for the
+/// `Retry-After` of the answer, if any.
+///
+/// The sender posts one message at a time, so a single connection is used per transport, reopened
+/// once it was idle for longer than `http_idle_timeout`: servers and middleboxes silently drop idle
+/// connections, and the next message would fail on it. The pool of `connections` is made of one
+/// transport per sender thread.
+///
+/// Messages of at least `compression_threshold` bytes are sent gzipped: small ones would cost
+/// more CPU than they save bandwidth.
+#[derive(Debug)]
+pub(crate) struct HttpTransport {
+    config: TransportConfig,
+    stats: Arc<StatsCollector>,
+    connector: Connector,
+    connection: Option<BufReader<Connection>>,
+    /// When the last answer was read from `connection`.
+    last_used: Option<Instant>,
+    /// `Retry-After` of the last answer.
+    retry_after: Option<Duration>,
+}
+
+struct Response {
+    status: u16,
+    /// The server won't accept another request on this connection.
+    close: bool,
+    /// Time of the server, from the `Date` header.
+    date: Option<f64>,
+    retry_after: Option<Duration>,
+}
+
+impl HttpTransport {
+    pub(crate) fn new(config: TransportConfig, stats: Arc<StatsCollector>) -> HttpTransport {
+        HttpTransport {
+            config,
+            stats,
+            connector: Connector::default(),
+            connection: None,
+            last_used: None,
+            retry_after: None,
+        }
+    }
+
+    fn post(&mut self, payload: &[u8]) -> Result<Response, Error> {
+        let idle = match (self.config.http_idle_timeout, self.last_used) {
+            (Some(timeout), Some(last_used)) => last_used.elapsed() >= timeout,
+            _ => false,
+        };
+        if idle {
+            self.connection = None;
+        }
+        if self.connection.is_none() {
+            // The server closed the previous kept-alive connection, this isn't a link failure.
+            self.connect()?;
+        }
+        let connection = match self.connection.as_mut() {
+            Some(connection) => connection,
+            None => return Err(not_connected()),
+        };
+
+        let body = match self.config.compression_threshold {
+            Some(threshold) if payload.len() >= threshold => {
+                let mut encoder = GzEncoder::new(Vec::with_capacity(payload.len() / 2), Compression::fast());
+                encoder.write_all(payload)?;
+                let compressed = encoder.finish()?;
+                self.stats.record_compressed(payload.len(), compressed.len());
+                Cow::Owned(compressed)
+            }
+            _ => Cow::Borrowed(payload),
+        };
+        let encoding = if let Cow::Owned(_) = body { "Content-Encoding: gzip\r\n" } else { "" };
+
+        let mut request = Vec::with_capacity(body.len() + 160);
+        write!(
+            request,
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\n{}",
+            self.config.http_path, self.config.hostname, self.config.port, encoding
+        )?;
+        if let Some(Secret(authorization)) = &self.config.http_authorization {
+            write!(request, "Authorization: {}\r\n", authorization)?;
+        }
+        write!(request, "Content-Length: {}\r\n\r\n", body.len())?;
+        request.extend_from_slice(&body);
+        connection.get_mut().write_all(&request)?;
+        connection.get_mut().flush()?;
+        let response = read_response(connection)?;
+        self.last_used = Some(Instant::now());
+        if let (Some(clock), Some(date)) = (&self.config.server_clock, response.date) {
+            clock.observe_http_date(date);
+        }
+        Ok(response)
+    }
+}
+
+impl GelfTransport for HttpTransport {
+    fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    fn connect(&mut self) -> Result<(), Error> {
+        self.connection = Some(BufReader::new(self.connector.open(&self.config)?));
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.connection = None;
+    }
+
+    fn send(&mut self, payload: &[u8]) -> Result<(), Error> {
+        self.retry_after = None;
+        let response = self.post(payload)?;
+        self.retry_after = response.retry_after;
+        if response.close {
+            self.connection = None;
+        }
+        if (200..300).contains(&response.status) {
+            Ok(())
+        } else {
+            Err(Error::Http(response.status))
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn acknowledges(&self) -> bool {
+        true
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+}
+
+fn read_response<R: BufRead>(connection: &mut R) -> Result<Response, Error> {
+    let mut line = String::new();
+    if connection.read_line(&mut line)? == 0 {
+        return Err(unexpected_eof());
+    }
+    let status = line.split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid_response("malformed status line"))?;
+
+    let mut content_length = 0;
+    let mut chunked = false;
+    let mut close = false;
+    let mut date = None;
+    let mut retry_after = None;
+    loop {
+        line.clear();
+        if connection.read_line(&mut line)? == 0 {
+            return Err(unexpected_eof());
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().map_err(|_| invalid_response("malformed Content-Length"))?;
+            } else if name.eq_ignore_ascii_case("connection") && value.eq_ignore_ascii_case("close") {
+                close = true;
+            } else if name.eq_ignore_ascii_case("date") {
+                date = parse_http_date(value);
+            } else if name.eq_ignore_ascii_case("retry-after") {
+                retry_after = parse_retry_after(value);
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                // Without chunked last, the body lasts until the server closes the connection.
+                chunked = value.rsplit(',').next().unwrap_or_default().trim().eq_ignore_ascii_case("chunked");
+                close |= !chunked;
+            }
+        }
+    }
+    if chunked {
+        skip_chunks(connection)?;
+    } else if !close {
+        io::copy(&mut connection.by_ref().take(content_length), &mut io::sink())?;
+    }
+    Ok(Response { status, close, date, retry_after })
+}
+
+/// Skips a chunked body and its trailers, leaving the connection at the next answer.
+fn skip_chunks<R: BufRead>(connection: &mut R) -> Result<(), Error> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if connection.read_line(&mut line)? == 0 {
+            return Err(unexpected_eof());
+        }
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = u64::from_str_radix(size, 16).map_err(|_| invalid_response("malformed chunk size"))?;
+        if size == 0 {
+            break;
+        }
+        // The chunk and the line break ending it.
+        let skipped = io::copy(&mut connection.by_ref().take(size + 2), &mut io::sink())?;
+        if skipped < size + 2 {
+            return Err(unexpected_eof());
+        }
+    }
+    loop {
+        line.clear();
+        if connection.read_line(&mut line)? == 0 {
+            return Err(unexpected_eof());
+        }
+        if line.trim_end().is_empty() {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads a `Retry-After` header, a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+    parse_http_date(value).map(|date| Duration::from_secs_f64((date - now).max(0.0)))
+}
+
+fn unexpected_eof() -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed by the remote server"))
+}
+
+fn invalid_response(reason: &str) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, format!("invalid HTTP response: {}", reason)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use appender::{BufferAppender, BufferAppenderBuilder};
+    use flate2::read::GzDecoder;
+    use log::{Level, Record};
+    use log4rs::append::Append;
+    use std::mem;
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+    use std::thread;
+    use transport::Protocol;
+
+    /// An HTTP input answering its first requests with `answers`, then `202 Accepted`. Returns its
+    /// port and the bodies of the requests, rejected ones included, gunzipped.
+    fn stub(answers: &[&'static str]) -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let received = bodies.clone();
+        let mut answers = answers.to_vec();
+        answers.reverse();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut writer = stream.unwrap();
+                let mut reader = BufReader::new(writer.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                    let (mut length, mut gzip) = (0, false);
+                    loop {
+                        line.clear();
+                        reader.read_line(&mut line).unwrap();
+                        match line.trim_end().split_once(':') {
+                            Some((name, value)) if name.eq_ignore_ascii_case("content-length") => length = value.trim().parse().unwrap(),
+                            Some((name, value)) if name.eq_ignore_ascii_case("content-encoding") => gzip = value.trim() == "gzip",
+                            Some(_) => {}
+                            None => break,
+                        }
+                    }
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+                    if gzip {
+                        let compressed = mem::take(&mut body);
+                        GzDecoder::new(&compressed[..]).read_to_end(&mut body).unwrap();
+                    }
+                    received.lock().unwrap().push(String::from_utf8(body).unwrap());
+                    let answer = answers.pop().unwrap_or("202 Accepted");
+                    write!(writer, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", answer).unwrap();
+                    line.clear();
+                }
+            }
+        });
+        (port, bodies)
+    }
+
+    fn builder(port: u16) -> BufferAppenderBuilder {
+        let builder = BufferAppender::builder()
+            .set_hostname("127.0.0.1")
+            .set_port(port)
+            .set_protocol(Protocol::Http)
+            .set_compression_threshold(None)
+            .set_flush_timeout(Some(Duration::from_secs(5)));
+        #[cfg(feature = "tls")]
+        let builder = builder.set_use_tls(false);
+        builder
+    }
+
+    fn appender(port: u16) -> BufferAppender {
+        builder(port).build().unwrap()
+    }
+
+    fn log(appender: &BufferAppender, message: &str) {
+        appender.append(&Record::builder().args(format_args!("{}", message)).level(Level::Info).build()).unwrap();
+    }
+
+    fn short_messages(bodies: &Mutex<Vec<String>>) -> Vec<String> {
+        bodies.lock().unwrap().iter()
+            .map(|body| serde_json::from_str::<serde_json::Value>(body).unwrap()["short_message"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn rejected_messages_are_dropped() {
+        let (port, bodies) = stub(&["400 Bad Request"]);
+        let appender = appender(port);
+        for message in &["rejected", "first", "second"] {
+            log(&appender, message);
+        }
+        appender.flush();
+        assert_eq!(short_messages(&bodies), vec!["rejected", "first", "second"]);
+        let stats = appender.stats();
+        assert_eq!((stats.sent, stats.dropped), (2, 1));
+        assert!(matches!(*stats.last_error.unwrap().1, Error::Http(400)));
+
+        log(&appender, "later");
+        appender.flush();
+        assert_eq!(short_messages(&bodies).last().unwrap(), "later");
+        assert_eq!(appender.stats().sent, 3);
+    }
+
+    #[test]
+    fn unavailable_servers_are_retried() {
+        let (port, bodies) = stub(&["503 Service Unavailable\r\nRetry-After: 0", "429 Too Many Requests"]);
+        let appender = appender(port);
+        for message in &["first", "second"] {
+            log(&appender, message);
+        }
+        appender.flush();
+        assert_eq!(short_messages(&bodies), vec!["first", "first", "first", "second"]);
+        let stats = appender.stats();
+        assert_eq!((stats.sent, stats.dropped), (2, 0));
+    }
+
+    #[test]
+    fn retry_after_is_read_as_seconds_or_a_date() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"), Some(Duration::from_secs(0)));
+        let later = parse_retry_after("Fri, 31 Dec 9999 23:59:59 GMT").unwrap();
+        assert!(later > Duration::from_secs(86400 * 365 * 7000));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn messages_past_the_threshold_are_gzipped() {
+        let (port, bodies) = stub(&[]);
+        let appender = builder(port).set_compression_threshold(Some(1024)).build().unwrap();
+        let large = "x".repeat(2000);
+        for message in &["small", &large] {
+            log(&appender, message);
+        }
+        appender.flush();
+        assert_eq!(short_messages(&bodies), vec!["small", large.as_str()]);
+        let stats = appender.stats();
+        assert_eq!((stats.sent, stats.compressed), (2, 1));
+        assert!(stats.compression_saved_bytes > 1500, "{}", stats.compression_saved_bytes);
+    }
+
+    #[test]
+    fn nothing_is_gzipped_without_a_threshold() {
+        let (port, bodies) = stub(&[]);
+        let appender = appender(port);
+        log(&appender, &"x".repeat(2000));
+        appender.flush();
+        assert_eq!(short_messages(&bodies).len(), 1);
+        let stats = appender.stats();
+        assert_eq!((stats.sent, stats.compressed, stats.compression_saved_bytes), (1, 0, 0));
+    }
+
+    #[test]
+    fn only_client_errors_are_rejections() {
+        for status in &[400, 401, 403, 404, 413] {
+            assert!(Error::Http(*status).is_rejection(), "{}", status);
+        }
+        for status in &[301, 408, 429, 500, 503] {
+            assert!(!Error::Http(*status).is_rejection(), "{}", status);
+        }
+    }
+
+    #[test]
+    fn chunked_answers_keep_the_connection() {
+        let mut answers = &b"HTTP/1.1 202 Accepted\r\nTransfer-Encoding: gzip, Chunked\r\n\r\n\
+            4;name=value\r\nWiki\r\n10\r\n0123456789abcdef\r\n0\r\nExpires: never\r\n\r\n\
+            HTTP/1.1 503 Service Unavailable\r\nContent-Length: 2\r\n\r\nno"[..];
+        let response = read_response(&mut answers).unwrap();
+        assert_eq!((response.status, response.close), (202, false));
+        let response = read_response(&mut answers).unwrap();
+        assert_eq!((response.status, response.close), (503, false));
+        assert!(answers.is_empty());
+    }
+
+    #[test]
+    fn bodies_without_length_close_the_connection() {
+        let mut answers = &b"HTTP/1.1 202 Accepted\r\nTransfer-Encoding: gzip\r\n\r\nbody"[..];
+        assert!(read_response(&mut answers).unwrap().close);
+
+        for answer in &[
+            &b"HTTP/1.1 202 Accepted\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n"[..],
+            &b"HTTP/1.1 202 Accepted\r\nTransfer-Encoding: chunked\r\n\r\n10\r\nshort"[..],
+        ] {
+            assert!(read_response(&mut &answer[..]).is_err());
+        }
+    }
+}
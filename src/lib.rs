@@ -21,14 +21,20 @@
 //! ```yaml
 //! appenders:
 //!   ldp:
-//!     kind: buffer
+//!     kind: gelf
 //!     additional_fields:
 //!       component: rust-cs
-//!     buffer_size: 5
 //!     hostname: 127.0.0.1
 //!     level: Info
+//!     max_batch_delay: 500
+//!     max_batch_records: 5
+//!     max_memory_bytes: 67108864
 //!     null_character: true
+//!     overflow_policy: drop_newest
 //!     port: 12202
+//!     target_levels:
+//!       hyper: warn
+//!       my_app::db: debug
 //!     use_tls: false
 //! root:
 //!   appenders:
@@ -39,26 +45,32 @@
 //! ```rust,ignore
 //! log4rs_gelf::init_file("/tmp/log4rs.yml", None).unwrap();
 //! ```
+//! With [`ConfigBuilder`](struct.ConfigBuilder.html), which sets up the root logger:
+//! ```rust,no_run
+//! log4rs_gelf::ConfigBuilder::new(log4rs_gelf::BufferAppender::builder().set_hostname("localhost"))
+//!     .set_console(true)
+//!     .init()
+//!     .unwrap();
+//! ```
 //! Programmatically constructing a configuration:
 //! ```rust
-//! extern crate serde_gelf;
-//! extern crate gelf_logger;
 //! extern crate log4rs;
 //! extern crate log;
 //!
-//! use serde_gelf::GelfLevel;
-//! use gelf_logger::Value;
+//! use log4rs_gelf::Value;
 //! use log4rs::config::{Config, Appender, Root};
 //! use log::{Level,LevelFilter};
 //! use std::time::Duration;
 //! fn main() {
-//!    let buffer = log4rs_gelf::BufferAppender::builder()
+//!    let builder = log4rs_gelf::BufferAppender::builder()
 //!        .set_level(Level::Info)
 //!        .set_hostname("localhost")
-//!        .set_port(12202)
-//!        .set_use_tls(false)
+//!        .set_port(12202);
+//!    # #[cfg(feature = "tls")]
+//!    let builder = builder.set_use_tls(false);
+//!    let buffer = builder
 //!        .set_null_character(true)
-//!        .set_buffer_size(Some(5))
+//!        .set_max_batch_records(5)
 //!        .put_additional_field("component", Value::String("rust-cs".to_string()))
 //!        .build()
 //!        .unwrap();
@@ -78,19 +90,100 @@
 html_logo_url = "https://eu.api.ovh.com/images/com-square-bichro.png",
 html_favicon_url = "https://www.ovh.com/favicon.ico",
 )]
-extern crate gelf_logger;
 extern crate log;
 extern crate log4rs;
+extern crate log_mdc;
 extern crate serde_gelf;
 extern crate serde_value;
+extern crate serde_json;
+extern crate serde_path_to_error;
+extern crate serde_yaml;
+extern crate serde;
+extern crate hostname;
+extern crate indexmap;
+#[cfg(feature = "http")]
+extern crate flate2;
+#[cfg(feature = "tls")]
+extern crate native_tls;
+#[cfg(feature = "tls")]
+extern crate sha2;
 extern crate anyhow;
+#[cfg(windows)]
+extern crate windows_sys;
+#[cfg(feature = "chrono")]
+extern crate chrono;
+#[cfg(feature = "time")]
+extern crate time;
+#[cfg(feature = "uuid")]
+extern crate uuid;
 
 use log4rs::config::Deserializers;
 use log::SetLoggerError;
 pub use appender::{BufferAppender, BufferAppenderBuilder};
+pub use config::ConfigBuilder;
+pub use context::{HOST_KEY, LEVEL_KEY, SUPPRESS_KEY};
+pub use correlation::{correlation_scope, CorrelationScope};
+pub use enrich::{register_enricher, Enricher, MessageFields};
+pub use convert::IntoValue;
+pub use error::{ConfigError, Error};
+#[cfg(feature = "async")]
+pub use error::FlushError;
+pub use fallback::Fallback;
+pub use file::AppenderConfigError;
+pub use handle::{handle, GelfHandle};
+pub use message::{EmptyFieldPolicy, LevelField, OutputFormat};
+pub use naming::FieldSchema;
+pub use mirror::{MirrorAppender, MirrorAppenderBuilder};
+pub use redact::Redaction;
+#[cfg(feature = "async")]
+pub use report::{error_channel, ErrorReceiver, ErrorSender};
+pub use router::{Route, RouterAppender, RouterAppenderBuilder};
+pub use sender::{post_fork, Delivery, OrderingPolicy, OverflowPolicy, PendingMessage};
+pub use serde_gelf::GelfLevel;
+pub use serde_value::Value;
+#[doc(hidden)]
+pub use severity::__log_with_severity;
+pub use stats::{MessageSizes, SendLatency, Stats};
+pub use transport::{Framing, GelfTransport, Protocol};
 
 mod file;
 mod appender;
+mod background;
+mod capture;
+mod config;
+mod context;
+mod correlation;
+mod convert;
+mod encoder;
+mod enrich;
+mod error;
+mod fallback;
+mod filter;
+mod flush;
+mod fold;
+mod handle;
+mod hooks;
+#[cfg(feature = "http")]
+mod http;
+mod message;
+mod mirror;
+mod monitor;
+mod naming;
+pub mod pipe;
+mod redact;
+#[cfg(feature = "async")]
+mod report;
+pub mod replay;
+mod router;
+mod sanitize;
+mod schema;
+mod sender;
+mod severity;
+mod spans;
+mod stats;
+mod timestamp;
+mod transport;
+mod windows;
 
 /// Initializes the global logger as a log4rs logger configured via a file.
 ///
@@ -100,6 +193,10 @@ mod appender;
 /// Any nonfatal errors encountered when processing the configuration are
 /// reported to stderr.
 ///
+/// GELF appenders of YAML files are checked first: a mistake in their configuration is returned
+/// as an [`AppenderConfigError`](struct.AppenderConfigError.html) giving the appender name, the
+/// offending key and its location in the file.
+///
 /// ### Warning
 ///
 /// The logging system may only be initialized once.
@@ -118,9 +215,46 @@ mod appender;
 /// ```
 ///
 pub fn init_file<P>(path: P, deserializers: Option<log4rs::config::Deserializers>) -> anyhow::Result<()> where P: AsRef<std::path::Path> {
+    file::check_file(path.as_ref())?;
     log4rs::init_file(path, deserializers.unwrap_or(file::deserializers()))
 }
 
+/// Returns the appender kinds registered by [`init_file`](fn.init_file.html) when no
+/// `Deserializers` are given.
+///
+/// `buffer` is the historical name, `gelf` and `gelf_tcp` (with the `tcp` feature) are aliases of
+/// the same appender.
+/// `gelf_mirror` is the [`MirrorAppender`](struct.MirrorAppender.html), whose `destinations` list
+/// holds one GELF appender configuration per remote server. `gelf_router` is the
+/// [`RouterAppender`](struct.RouterAppender.html), whose `routes` list the conditions a record
+/// must meet under `match` and the GELF appender configuration it's sent to under `destination`.
+///
+/// ## Example
+///
+/// ```rust
+/// assert!(log4rs_gelf::kinds().contains(&"gelf"));
+/// ```
+pub fn kinds() -> &'static [&'static str] {
+    file::KINDS
+}
+
+/// Returns the JSON Schema of the GELF appender configurations, whatever their kind, to validate
+/// configuration files in CI or get completion in editors.
+///
+/// ## Example
+///
+/// ```rust
+/// extern crate serde_json;
+///
+/// fn main() {
+///     let schema = log4rs_gelf::config_schema();
+///     println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+/// }
+/// ```
+pub fn config_schema() -> serde_json::Value {
+    schema::schema()
+}
+
 /// Initializes the global logger as a log4rs logger with the provided config.
 ///
 /// A `Handle` object is returned which can be used to adjust the logging
@@ -133,26 +267,25 @@ pub fn init_file<P>(path: P, deserializers: Option<log4rs::config::Deserializers
 /// ## Example
 ///
 /// ```rust
-/// extern crate serde_gelf;
-/// extern crate gelf_logger;
 /// extern crate log4rs;
 /// extern crate log;
 ///
-///use serde_gelf::GelfLevel;
-///use gelf_logger::Value;
+///use log4rs_gelf::Value;
 ///use log4rs::config::{Config, Appender, Root};
 ///use std::time::Duration;
 ///use log4rs::append::Append;
 ///use log::{Level, LevelFilter};
 ///
 /// fn main() {
-///    let buffer = log4rs_gelf::BufferAppender::builder()
+///    let builder = log4rs_gelf::BufferAppender::builder()
 ///        .set_level(Level::Info)
 ///        .set_hostname("localhost")
-///        .set_port(12202)
-///        .set_use_tls(false)
+///        .set_port(12202);
+///    # #[cfg(feature = "tls")]
+///    let builder = builder.set_use_tls(false);
+///    let buffer = builder
 ///        .set_null_character(true)
-///        .set_buffer_size(Some(5))
+///        .set_max_batch_records(5)
 ///        .put_additional_field("component", Value::String("rust-cs".to_string()))
 ///        .build()
 ///        .unwrap();
@@ -168,8 +301,9 @@ pub fn init_file<P>(path: P, deserializers: Option<log4rs::config::Deserializers
 /// }
 /// ```
 ///
+#[allow(clippy::needless_doctest_main)]
 pub fn init_config(config: log4rs::config::Config) -> Result<log4rs::Handle, SetLoggerError> {
-    Ok(log4rs::init_config(config)?)
+    log4rs::init_config(config)
 }
 
 pub fn deserializers() -> Deserializers {
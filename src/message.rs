@@ -0,0 +1,1025 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use context::RecordContext;
+use correlation::{process_id, random_uuid, CORRELATION_FIELD};
+use encoder::{EncodedField, Template};
+use enrich::{Enricher, MessageFields};
+use filter::{matches_prefix, FieldFilter};
+use indexmap::IndexMap;
+use log::{Level, Record};
+use naming::FieldSchema;
+use redact::Redactor;
+use sanitize::{sanitize_string, sanitize_value, Sanitized};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_value::Value;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::Arc;
+use timestamp::{format_rfc3339, Clock};
+
+/// GELF payload specification version emitted by this crate.
+pub(crate) const GELF_VERSION: &str = "1.1";
+
+/// Top-level fields defined by the GELF specification.
+pub(crate) const GELF_FIELDS: [&str; 6] = ["version", "host", "short_message", "full_message", "timestamp", "level"];
+
+/// Additional fields of a single record, keyed by their GELF name (with the leading underscore).
+pub(crate) type Fields = IndexMap<String, Value>;
+
+/// Additional fields shared by every message of an appender.
+///
+/// They are converted once to their GELF name and sorted, so building a message only costs a
+/// reference count increment. Their JSON is also kept, to be spliced into each message.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StaticFields {
+    fields: Vec<(String, Value)>,
+    /// The fields serialized as JSON object entries, without the braces. `None` if they can't be
+    /// serialized.
+    fragment: Option<Vec<u8>>,
+}
+
+impl StaticFields {
+    pub(crate) fn new(additional_fields: &BTreeMap<String, Value>) -> StaticFields {
+        let mut fields: Vec<(String, Value)> = additional_fields.iter()
+            .map(|(key, value)| (field_name(key), value.clone()))
+            .collect();
+        // A key given both with and without its underscore is only sent once.
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        fields.dedup_by(|a, b| a.0 == b.0);
+        let fragment = json_fragment(&fields);
+        StaticFields { fields, fragment }
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.fields.binary_search_by(|(name, _)| name.as_str().cmp(key)).is_ok()
+    }
+}
+
+fn json_fragment(fields: &[(String, Value)]) -> Option<Vec<u8>> {
+    let mut fragment = Vec::new();
+    for (key, value) in fields {
+        if !fragment.is_empty() {
+            fragment.push(b',');
+        }
+        serde_json::to_writer(&mut fragment, key).ok()?;
+        fragment.push(b':');
+        serde_json::to_writer(&mut fragment, value).ok()?;
+    }
+    Some(fragment)
+}
+
+/// Top-level fields of the GELF messages of an appender, besides those of the record.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Envelope {
+    pub(crate) version: String,
+    /// Extension fields of the GELF supersets, without the leading underscore.
+    pub(crate) extensions: Vec<(String, Value)>,
+}
+
+impl Default for Envelope {
+    fn default() -> Envelope {
+        Envelope { version: GELF_VERSION.to_string(), extensions: Vec::new() }
+    }
+}
+
+/// Additional fields whose value is computed for each message.
+#[derive(Clone, Default)]
+pub(crate) struct DynamicFields {
+    fields: Vec<(String, Arc<dyn Fn() -> Value + Send + Sync>)>,
+}
+
+impl DynamicFields {
+    pub(crate) fn insert(&mut self, key: &str, provider: Arc<dyn Fn() -> Value + Send + Sync>) {
+        let key = field_name(key);
+        self.fields.retain(|(name, _)| *name != key);
+        self.fields.push((key, provider));
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(|(key, _)| key.as_str())
+    }
+}
+
+/// Providers are equal when they are the same closure.
+impl PartialEq for DynamicFields {
+    fn eq(&self, other: &DynamicFields) -> bool {
+        self.fields.len() == other.fields.len()
+            && self.fields.iter().zip(&other.fields)
+                .all(|(a, b)| a.0 == b.0 && Arc::ptr_eq(&a.1, &b.1))
+    }
+}
+
+impl fmt::Debug for DynamicFields {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_list().entries(self.fields.iter().map(|(key, _)| key)).finish()
+    }
+}
+
+/// Additional fields of the records whose target is a given module or one of its submodules.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct TargetFields {
+    /// Sorted by decreasing prefix length, so the most specific value of a key wins.
+    rules: Vec<(String, Vec<(String, Value)>)>,
+}
+
+impl TargetFields {
+    /// `pattern` is a target prefix, optionally followed by `::*`. A lone `*` matches every target.
+    pub(crate) fn insert(&mut self, pattern: &str, key: &str, value: Value) {
+        let prefix = pattern.strip_suffix("::*").or_else(|| pattern.strip_suffix('*')).unwrap_or(pattern);
+        if !self.rules.iter().any(|rule| rule.0 == prefix) {
+            self.rules.push((prefix.to_string(), Vec::new()));
+            self.rules.sort_by_key(|rule| Reverse(rule.0.len()));
+        }
+        if let Some(rule) = self.rules.iter_mut().find(|rule| rule.0 == prefix) {
+            let key = field_name(key);
+            rule.1.retain(|(name, _)| *name != key);
+            rule.1.push((key, value));
+        }
+    }
+
+    pub(crate) fn rules(&self) -> &[(String, Vec<(String, Value)>)] {
+        &self.rules
+    }
+
+    /// Fields of the rules matching `target`, the most specific ones first.
+    fn matching<'s>(&'s self, target: &'s str) -> impl Iterator<Item = &'s (String, Value)> + 's {
+        self.rules.iter()
+            .filter(move |rule| matches_prefix(target, &rule.0))
+            .flat_map(|rule| rule.1.iter())
+    }
+}
+
+/// Whether the messages carry their `level`.
+#[derive(serde_derive::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LevelField {
+    /// Sent in every message.
+    #[default]
+    Keep,
+    /// Left out, for pipelines rejecting some level values which rely on a severity field of
+    /// their own instead.
+    Omit,
+}
+
+/// How additional fields holding a null value or an empty string are sent.
+#[derive(serde_derive::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyFieldPolicy {
+    /// Sent as given.
+    #[default]
+    Keep,
+    /// Left out of the message.
+    Drop,
+    /// Sent as `null`.
+    Null,
+    /// Sent as an empty string.
+    EmptyString,
+}
+
+impl EmptyFieldPolicy {
+    /// Applies the policy to a field value, `None` meaning the field is dropped.
+    pub(crate) fn apply(self, value: Value) -> Option<Value> {
+        let empty = match &value {
+            Value::Unit | Value::Option(None) => true,
+            Value::String(text) => text.is_empty(),
+            _ => false,
+        };
+        match self {
+            _ if !empty => Some(value),
+            EmptyFieldPolicy::Keep => Some(value),
+            EmptyFieldPolicy::Drop => None,
+            EmptyFieldPolicy::Null => Some(Value::Unit),
+            EmptyFieldPolicy::EmptyString => Some(Value::String(String::new())),
+        }
+    }
+}
+
+/// Layout of the serialized messages, for receivers other than Graylog.
+#[derive(serde_derive::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// GELF messages.
+    #[default]
+    Gelf,
+    /// Plain JSON objects: `timestamp`, `host`, `level` (`error`, `warn`, ...), `message` and the
+    /// additional fields, without their leading underscore.
+    JsonLines,
+    /// Logstash JSON events: `@timestamp` (RFC 3339), `@version`, `message`, `host`, `level`
+    /// (`ERROR`, `WARN`, ...) and the additional fields, without their leading underscore.
+    Logstash,
+}
+
+impl OutputFormat {
+    /// Names of the fields of the envelope, which additional fields can't take.
+    fn envelope(self) -> &'static [&'static str] {
+        match self {
+            OutputFormat::Gelf => &["version", "host", "short_message", "timestamp", "level"],
+            OutputFormat::JsonLines => &["timestamp", "host", "level", "message"],
+            OutputFormat::Logstash => &["@timestamp", "@version", "message", "host", "level"],
+        }
+    }
+
+    /// Name of an additional field in the messages. `None` if it's taken by the envelope.
+    fn field_name(self, key: &str) -> Option<&str> {
+        if self == OutputFormat::Gelf {
+            return Some(key);
+        }
+        let name = key.strip_prefix('_').unwrap_or(key);
+        if self.envelope().contains(&name) { None } else { Some(name) }
+    }
+}
+
+/// Settings of an appender shaping each of its messages.
+#[derive(Debug, Clone)]
+pub(crate) struct MessageOptions {
+    /// `host` of the messages whose record doesn't name one.
+    pub(crate) host: String,
+    pub(crate) static_fields: Arc<StaticFields>,
+    pub(crate) envelope: Arc<Envelope>,
+    pub(crate) dynamic_fields: DynamicFields,
+    pub(crate) target_fields: TargetFields,
+    /// Run in order once the record, dynamic and target fields are known.
+    pub(crate) enrichers: Vec<Arc<dyn Enricher>>,
+    /// Names of the metadata fields. Static fields are renamed once by the builder.
+    pub(crate) field_schema: FieldSchema,
+    pub(crate) format: OutputFormat,
+    /// Also sends the severity as `_priority`, the journald name of the syslog severity.
+    pub(crate) priority_field: bool,
+    /// Also sends the severity as this additional field.
+    pub(crate) severity_field: Option<String>,
+    pub(crate) level_field: LevelField,
+    /// Lowest and highest severity sent, the others being brought within.
+    pub(crate) level_range: (u32, u32),
+    /// Rendition of the record by a log4rs encoder, `full_message` by default.
+    pub(crate) encoded_field: Option<EncodedField>,
+    /// Builds the `short_message`, instead of the bare record arguments.
+    pub(crate) short_message: Option<Template>,
+    /// Escapes NUL characters and replaces invalid UTF-8 in record strings.
+    pub(crate) sanitize: bool,
+    pub(crate) empty_fields: EmptyFieldPolicy,
+    /// Longest string value of an additional field, in bytes.
+    pub(crate) max_field_bytes: Option<usize>,
+    /// Sends the correlation id of the process with the messages which have none.
+    pub(crate) correlation_id: bool,
+    /// Longest `full_message` of a message, in bytes, longer ones being split across several
+    /// messages rather than truncated.
+    pub(crate) full_message_chunk_bytes: Option<usize>,
+    pub(crate) redactor: Redactor,
+    /// Dates the records without a timestamp of their own.
+    pub(crate) clock: Arc<Clock>,
+    /// Leaves the dynamic fields out until the field filter accepted the message. Unset when the
+    /// filter or an enricher may read them.
+    pub(crate) lazy_dynamic_fields: bool,
+    /// Leaves the encoder field out until the field filter accepted the message. Unset when the
+    /// filter reads it.
+    pub(crate) lazy_encoded_field: bool,
+}
+
+impl MessageOptions {
+    /// Sanitizes and redacts a text of the record, as its `short_message` would be.
+    pub(crate) fn clean(&self, text: String) -> String {
+        let text = if self.sanitize { sanitize_string(text) } else { text };
+        if self.redactor.is_empty() { text } else { self.redactor.redact_string(text) }
+    }
+
+    /// Sanitizes, redacts and truncates the value of an additional field.
+    fn clean_value(&self, key: &str, value: Value) -> Value {
+        let value = if self.sanitize { sanitize_value(value) } else { value };
+        let mut value = if self.redactor.is_empty() { value } else { self.redactor.redact_value(value) };
+        if let Some(max_field_bytes) = self.max_field_bytes {
+            if key != "full_message" || self.full_message_chunk_bytes.is_none() {
+                truncate_field(&mut value, max_field_bytes);
+            }
+        }
+        value
+    }
+}
+
+/// The `short_message` of a message.
+#[derive(Debug, Clone)]
+pub(crate) enum ShortMessage<'a> {
+    /// The record arguments, formatted straight into the output buffer during serialization.
+    Args(fmt::Arguments<'a>),
+    /// The record arguments, sanitized while formatted.
+    SanitizedArgs(fmt::Arguments<'a>),
+    /// The rendition of a template.
+    Rendered(String),
+}
+
+impl<'a> Serialize for ShortMessage<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        match self {
+            ShortMessage::Args(args) => serializer.collect_str(args),
+            ShortMessage::SanitizedArgs(args) => serializer.collect_str(&Sanitized(args)),
+            ShortMessage::Rendered(text) => serializer.serialize_str(text),
+        }
+    }
+}
+
+/// A single GELF message, ready to be serialized.
+///
+/// Record fields take precedence over static fields of the same name.
+#[derive(Debug, Clone)]
+pub(crate) struct GelfMessage<'a, M = ShortMessage<'a>> {
+    host: String,
+    short_message: M,
+    timestamp: f64,
+    level: u32,
+    /// Level of the record, named by the formats other than GELF.
+    record_level: Level,
+    level_field: LevelField,
+    fields: Fields,
+    static_fields: Arc<StaticFields>,
+    envelope: Arc<Envelope>,
+    /// Static fields left out of this message, sorted.
+    suppressed: Vec<String>,
+    format: OutputFormat,
+    /// Dynamic fields, by index, and whether the encoder field, are yet to be computed.
+    pending: (Vec<usize>, bool),
+    record: PhantomData<&'a str>,
+}
+
+/// A message no longer borrowing its record, which the sender serializes.
+pub(crate) type OwnedMessage = GelfMessage<'static, String>;
+
+impl<'a> GelfMessage<'a> {
+    /// Builds the message for `record`, from the options of the appender and the fields found in
+    /// the record context.
+    ///
+    /// The message borrows the record arguments, it must be serialized before the record goes.
+    /// Fails if the encoder fails.
+    pub(crate) fn from_record(record: &Record<'a>, options: &MessageOptions) -> anyhow::Result<GelfMessage<'a>> {
+        let mut message = GelfMessage::compose(record, options, false)?;
+        message.complete(record, options)?;
+        Ok(message)
+    }
+
+    /// Builds the message for `record`, unless `filter` drops it. The dynamic fields and the
+    /// encoder field, which run user code and may be costly, are only computed for the messages
+    /// accepted when the options allow it.
+    pub(crate) fn filtered(record: &Record<'a>, options: &MessageOptions, filter: &FieldFilter) -> anyhow::Result<Option<GelfMessage<'a>>> {
+        let mut message = GelfMessage::compose(record, options, true)?;
+        if !filter.accepts(&message) {
+            return Ok(None);
+        }
+        message.complete(record, options)?;
+        Ok(Some(message))
+    }
+
+    /// Builds the message, the lazy fields being left as placeholders, in their place, if `lazy`.
+    fn compose(record: &Record<'a>, options: &MessageOptions, lazy: bool) -> anyhow::Result<GelfMessage<'a>> {
+        let mut pending = (Vec::new(), false);
+        let context = RecordContext::capture(record);
+        let mut fields = Fields::with_capacity(context.fields.len() + options.dynamic_fields.len() + 6);
+        for (key, value) in context.fields {
+            let key = if options.sanitize { sanitize_string(key) } else { key };
+            if let Some(value) = options.empty_fields.apply(value) {
+                fields.insert(field_name(&key), value);
+            }
+        }
+        fields.insert("_target".into(), Value::String(record.target().to_string()));
+        if let Some(module_path) = record.module_path() {
+            fields.insert("_module_path".into(), Value::String(module_path.to_string()));
+        }
+        if let Some(file) = record.file() {
+            fields.insert("_file".into(), Value::String(file.to_string()));
+        }
+        if let Some(line) = record.line() {
+            fields.insert("_line".into(), Value::U32(line));
+        }
+
+        for (index, (key, provider)) in options.dynamic_fields.fields.iter().enumerate() {
+            if !fields.contains_key(key) {
+                if lazy && options.lazy_dynamic_fields {
+                    fields.insert(key.clone(), Value::Unit);
+                    pending.0.push(index);
+                } else if let Some(value) = options.empty_fields.apply(provider()) {
+                    fields.insert(key.clone(), value);
+                }
+            }
+        }
+
+        if options.correlation_id && !fields.contains_key(CORRELATION_FIELD) {
+            fields.insert(CORRELATION_FIELD.to_string(), Value::String(process_id()));
+        }
+
+        for (key, value) in options.target_fields.matching(record.target()) {
+            if !fields.contains_key(key) {
+                if let Some(value) = options.empty_fields.apply(value.clone()) {
+                    fields.insert(key.clone(), value);
+                }
+            }
+        }
+
+        for enricher in &options.enrichers {
+            enricher.enrich(record, &mut MessageFields::new(&mut fields));
+        }
+
+        if let Some(encoded_field) = &options.encoded_field {
+            if lazy && options.lazy_encoded_field {
+                fields.insert(encoded_field.name.clone(), Value::Unit);
+                pending.1 = true;
+            } else {
+                fields.insert(encoded_field.name.clone(), Value::String(encoded_field.encode(record)?));
+            }
+        }
+        let (lowest, highest) = options.level_range;
+        let level = context.severity.unwrap_or_else(|| syslog_level(record.level())).clamp(lowest, highest);
+        if options.priority_field {
+            fields.entry("_priority".into()).or_insert(Value::U32(level));
+        }
+        if let Some(severity_field) = &options.severity_field {
+            fields.entry(severity_field.clone()).or_insert(Value::U32(level));
+        }
+
+        let mut host = context.host.unwrap_or_else(|| options.host.clone());
+        options.field_schema.apply(record, &host, &mut fields);
+        let mut short_message = match &options.short_message {
+            Some(template) if options.sanitize => ShortMessage::Rendered(sanitize_string(template.render(record))),
+            Some(template) => ShortMessage::Rendered(template.render(record)),
+            None if options.sanitize => ShortMessage::SanitizedArgs(*record.args()),
+            None => ShortMessage::Args(*record.args()),
+        };
+        if options.sanitize {
+            host = sanitize_string(host);
+        }
+        if !options.redactor.is_empty() {
+            let text = match short_message {
+                ShortMessage::Args(args) => args.to_string(),
+                ShortMessage::SanitizedArgs(args) => Sanitized(args).to_string(),
+                ShortMessage::Rendered(text) => text,
+            };
+            short_message = ShortMessage::Rendered(options.redactor.redact_string(text));
+        }
+        for (key, value) in fields.iter_mut() {
+            *value = options.clean_value(key, mem::replace(value, Value::Unit));
+        }
+
+        Ok(GelfMessage {
+            host,
+            short_message,
+            timestamp: context.timestamp.unwrap_or_else(|| options.clock.now()),
+            level,
+            record_level: record.level(),
+            level_field: options.level_field,
+            fields,
+            static_fields: options.static_fields.clone(),
+            envelope: options.envelope.clone(),
+            suppressed: context.suppressed,
+            format: options.format,
+            pending,
+            record: PhantomData,
+        })
+    }
+
+    /// Computes the fields `compose` left as placeholders, which keep their place.
+    fn complete(&mut self, record: &Record, options: &MessageOptions) -> anyhow::Result<()> {
+        let (dynamic_fields, encoded_field) = mem::take(&mut self.pending);
+        for index in dynamic_fields {
+            let (key, provider) = &options.dynamic_fields.fields[index];
+            let key = options.field_schema.rename(key.clone());
+            match options.empty_fields.apply(provider()) {
+                Some(value) => {
+                    let value = options.clean_value(&key, value);
+                    self.fields.insert(key, value);
+                }
+                None => {
+                    self.fields.shift_remove(&key);
+                }
+            }
+        }
+        if let (true, Some(encoded_field)) = (encoded_field, &options.encoded_field) {
+            let key = options.field_schema.rename(encoded_field.name.clone());
+            let value = options.clean_value(&key, Value::String(encoded_field.encode(record)?));
+            self.fields.insert(key, value);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> GelfMessage<'a> {
+    /// Value of an additional field, given by the record or static.
+    pub(crate) fn field(&self, key: &str) -> Option<&Value> {
+        self.fields.get(key).or_else(|| {
+            let static_fields = &self.static_fields.fields;
+            static_fields.binary_search_by(|(name, _)| name.as_str().cmp(key))
+                .ok()
+                .filter(|_| !self.is_suppressed(key))
+                .map(|index| &static_fields[index].1)
+        })
+    }
+
+    /// Splits a `full_message` longer than `chunk_bytes` across as many messages, on character
+    /// boundaries. Each one has `_chunk_index` (from 0) and `_chunk_total`, and the
+    /// `_correlation_id` of the record, or a new one, to be put back together.
+    pub(crate) fn split_full_message(mut self, chunk_bytes: usize) -> Vec<GelfMessage<'a>> {
+        let text = match self.fields.get("full_message") {
+            Some(Value::String(text)) if text.len() > chunk_bytes => text.clone(),
+            _ => return vec![self],
+        };
+        let mut chunks = Vec::with_capacity(text.len() / chunk_bytes + 1);
+        let mut rest = text.as_str();
+        while !rest.is_empty() {
+            let mut end = chunk_bytes.min(rest.len());
+            while !rest.is_char_boundary(end) {
+                end -= 1;
+            }
+            if end == 0 {
+                end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+            }
+            chunks.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+        if self.field(CORRELATION_FIELD).is_none() {
+            self.fields.insert(CORRELATION_FIELD.to_string(), Value::String(random_uuid()));
+        }
+        let total = chunks.len() as u32;
+        chunks.into_iter().enumerate()
+            .map(|(index, chunk)| {
+                let mut message = self.clone();
+                message.fields.insert("full_message".to_string(), Value::String(chunk.to_string()));
+                message.fields.insert("_chunk_index".to_string(), Value::U32(index as u32));
+                message.fields.insert("_chunk_total".to_string(), Value::U32(total));
+                message
+            })
+            .collect()
+    }
+
+    /// Formats the record arguments, so that the message no longer borrows the record and can be
+    /// serialized by the sender, off the logging thread.
+    pub(crate) fn into_owned(self) -> OwnedMessage {
+        let short_message = match self.short_message {
+            ShortMessage::Args(args) => args.to_string(),
+            ShortMessage::SanitizedArgs(args) => Sanitized(args).to_string(),
+            ShortMessage::Rendered(text) => text,
+        };
+        GelfMessage {
+            host: self.host,
+            short_message,
+            timestamp: self.timestamp,
+            level: self.level,
+            record_level: self.record_level,
+            level_field: self.level_field,
+            fields: self.fields,
+            static_fields: self.static_fields,
+            envelope: self.envelope,
+            suppressed: self.suppressed,
+            format: self.format,
+            pending: self.pending,
+            record: PhantomData,
+        }
+    }
+}
+
+impl OwnedMessage {
+    /// Length of the JSON of the message, escapes included, to account for it in the memory
+    /// budget and the batches before it's serialized. The dynamic fields and the encoder field
+    /// still to compute aren't known yet: the sender charges the serialized length once known.
+    pub(crate) fn estimated_len(&self) -> usize {
+        let fields: usize = self.fields.iter().map(|(key, value)| json_string_len(key) + estimated_value_len(value) + 2).sum();
+        let static_fields = self.static_fields.fragment.as_ref().map_or(0, Vec::len);
+        let extensions: usize = self.envelope.extensions.iter()
+            .map(|(key, value)| json_string_len(key) + estimated_value_len(value) + 3)
+            .sum();
+        // Timestamp, level and the punctuation of the envelope.
+        80 + json_string_len(&self.envelope.version) + json_string_len(&self.host)
+            + json_string_len(&self.short_message) + extensions + fields + static_fields
+    }
+}
+
+impl<'a, M: Serialize> GelfMessage<'a, M> {
+
+    /// Serializes the message to JSON, in the output format of the appender.
+    ///
+    /// The pre-serialized static fields of GELF messages are spliced in, unless a record field
+    /// overrides or suppresses one of them.
+    pub(crate) fn to_json(&self) -> serde_json::Result<Vec<u8>> {
+        let static_fields = &self.static_fields;
+        let fragment = match &static_fields.fragment {
+            Some(fragment) if self.format == OutputFormat::Gelf
+                && !self.fields.keys().any(|key| static_fields.contains(key))
+                && !self.suppressed.iter().any(|key| static_fields.contains(key)) => fragment,
+            _ => return serde_json::to_vec(self),
+        };
+        let mut json = Vec::with_capacity(256 + fragment.len());
+        serde_json::to_writer(&mut json, &RecordPart(self))?;
+        if !fragment.is_empty() {
+            // Reopen the object to append the static fields.
+            json.pop();
+            json.push(b',');
+            json.extend_from_slice(fragment);
+            json.push(b'}');
+        }
+        Ok(json)
+    }
+
+    fn is_suppressed(&self, key: &str) -> bool {
+        self.suppressed.binary_search_by(|name| name.as_str().cmp(key)).is_ok()
+    }
+
+    fn serialize_with<S>(&self, serializer: S, static_fields: bool) -> Result<S::Ok, S::Error> where S: Serializer {
+        let mut map = serializer.serialize_map(None)?;
+        match self.format {
+            OutputFormat::Gelf => {
+                map.serialize_entry("version", &self.envelope.version)?;
+                map.serialize_entry("host", &self.host)?;
+                map.serialize_entry("short_message", &self.short_message)?;
+                map.serialize_entry("timestamp", &self.timestamp)?;
+                if self.level_field == LevelField::Keep {
+                    map.serialize_entry("level", &self.level)?;
+                }
+                for (key, value) in &self.envelope.extensions {
+                    map.serialize_entry(key, value)?;
+                }
+            }
+            OutputFormat::JsonLines => {
+                map.serialize_entry("timestamp", &self.timestamp)?;
+                map.serialize_entry("host", &self.host)?;
+                if self.level_field == LevelField::Keep {
+                    map.serialize_entry("level", &self.record_level.as_str().to_lowercase())?;
+                }
+                map.serialize_entry("message", &self.short_message)?;
+            }
+            OutputFormat::Logstash => {
+                map.serialize_entry("@timestamp", &format_rfc3339(self.timestamp))?;
+                map.serialize_entry("@version", "1")?;
+                map.serialize_entry("message", &self.short_message)?;
+                map.serialize_entry("host", &self.host)?;
+                if self.level_field == LevelField::Keep {
+                    map.serialize_entry("level", self.record_level.as_str())?;
+                }
+            }
+        }
+        for (key, value) in &self.fields {
+            if let Some(name) = self.format.field_name(key) {
+                map.serialize_entry(name, value)?;
+            }
+        }
+        if static_fields {
+            for (key, value) in &self.static_fields.fields {
+                if !self.fields.contains_key(key) && !self.is_suppressed(key) {
+                    if let Some(name) = self.format.field_name(key) {
+                        map.serialize_entry(name, value)?;
+                    }
+                }
+            }
+        }
+        map.end()
+    }
+}
+
+impl<'a, M: Serialize> Serialize for GelfMessage<'a, M> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        self.serialize_with(serializer, true)
+    }
+}
+
+/// A message without its static fields.
+struct RecordPart<'m, 'a: 'm, M: 'm>(&'m GelfMessage<'a, M>);
+
+impl<'m, 'a, M: Serialize> Serialize for RecordPart<'m, 'a, M> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        self.0.serialize_with(serializer, false)
+    }
+}
+
+/// Truncates a string value longer than `max_bytes`, on a character boundary, and appends the
+/// number of bytes left out, within `max_bytes`. Values too short for that mark are only cut.
+pub(crate) fn truncate_field(value: &mut Value, max_bytes: usize) {
+    let text = match value {
+        Value::String(text) if text.len() > max_bytes => text,
+        _ => return,
+    };
+    // The length of the mark depends on how much is left out.
+    let mut end = max_bytes;
+    loop {
+        end = char_boundary(text, end);
+        let mark = format!("…[+{} bytes]", text.len() - end);
+        if end + mark.len() <= max_bytes {
+            text.truncate(end);
+            text.push_str(&mark);
+            return;
+        }
+        if mark.len() > max_bytes {
+            let end = char_boundary(text, max_bytes);
+            text.truncate(end);
+            return;
+        }
+        end = max_bytes - mark.len();
+    }
+}
+
+/// The character boundary of `text` at or before `index`.
+fn char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Upper bound on the length of the JSON of a value.
+fn estimated_value_len(value: &Value) -> usize {
+    match value {
+        Value::String(text) => json_string_len(text),
+        Value::Char(c) => json_string_len(c.encode_utf8(&mut [0; 4])),
+        Value::Bytes(bytes) => bytes.len() * 4 + 2,
+        Value::Seq(values) => values.iter().map(|value| estimated_value_len(value) + 1).sum::<usize>() + 2,
+        Value::Map(map) => map.iter()
+            .map(|(key, value)| estimated_value_len(key) + estimated_value_len(value) + 2)
+            .sum::<usize>() + 2,
+        Value::Option(Some(value)) | Value::Newtype(value) => estimated_value_len(value),
+        // Numbers, the longest being floats such as `-2.2250738585072014e-308`.
+        _ => 24,
+    }
+}
+
+/// Length of `text` as a JSON string, quotes and escapes included.
+fn json_string_len(text: &str) -> usize {
+    let escaped: usize = text.bytes()
+        .map(|byte| match byte {
+            b'"' | b'\\' | b'\n' | b'\r' | b'\t' | 0x08 | 0x0c => 2,
+            0..=0x1f => 6,
+            _ => 1,
+        })
+        .sum();
+    escaped + 2
+}
+
+/// GELF name of an additional field: its key, prefixed with an underscore when missing.
+pub(crate) fn field_name(key: &str) -> String {
+    if key.starts_with('_') {
+        key.to_string()
+    } else {
+        format!("_{}", key)
+    }
+}
+
+/// Maps a `log` level onto the syslog severity expected in the GELF `level` field.
+pub(crate) fn syslog_level(level: Level) -> u32 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use appender::{BufferAppender, BufferAppenderBuilder};
+    use error::Error;
+    use log4rs::append::Append;
+    use log::LevelFilter;
+    use log4rs::encode::pattern::PatternEncoder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use timestamp::parse_timestamp;
+    use transport::GelfTransport;
+
+    /// Keeps the payloads sent.
+    #[derive(Debug, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl GelfTransport for Recorder {
+        fn is_connected(&self) -> bool {
+            true
+        }
+        fn connect(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+        fn disconnect(&mut self) {}
+        fn send(&mut self, payload: &[u8]) -> Result<(), Error> {
+            self.0.lock().unwrap().push(String::from_utf8(payload.to_vec()).unwrap());
+            Ok(())
+        }
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    /// Logs `lines` on the same target, and returns the payloads sent once the appender is dropped.
+    fn send<F>(configure: F, lines: &[&str]) -> Vec<String> where F: FnOnce(BufferAppenderBuilder) -> BufferAppenderBuilder {
+        let recorder = Recorder::default();
+        let payloads = recorder.0.clone();
+        let builder = BufferAppender::with_transport(Box::new(recorder))
+            .set_default_fields(false)
+            .put_additional_field("zone", "b")
+            .put_additional_field("app", "a");
+        let appender = configure(builder).build().unwrap();
+        for line in lines {
+            appender.append(&Record::builder()
+                .args(format_args!("{}", line))
+                .level(Level::Error)
+                .target("golden")
+                .module_path_static(Some("golden::module"))
+                .file_static(Some("golden.rs"))
+                .line(Some(7))
+                .key_values(&[("request_id", 42)])
+                .build()).unwrap();
+        }
+        drop(appender);
+        let payloads = payloads.lock().unwrap().clone();
+        payloads
+    }
+
+    /// `payload`, its `host` and `timestamp` replaced by placeholders.
+    fn golden(payload: &str) -> String {
+        let message: serde_json::Value = serde_json::from_str(payload).unwrap();
+        payload
+            .replacen(&format!("\"host\":{}", message["host"]), "\"host\":\"HOST\"", 1)
+            .replacen(&format!("\"timestamp\":{}", message["timestamp"]), "\"timestamp\":0", 1)
+    }
+
+    #[test]
+    fn keys_come_in_the_documented_order() {
+        let payloads = send(|builder| builder.set_encoder(Box::new(PatternEncoder::new("{m}"))), &["Exception"]);
+        assert_eq!(payloads.iter().map(|payload| golden(payload)).collect::<Vec<_>>(), vec![concat!(
+            r#"{"version":"1.1","host":"HOST","short_message":"Exception","timestamp":0,"level":3,"#,
+            r#""_request_id":42,"_target":"golden","_module_path":"golden::module","_file":"golden.rs","_line":7,"#,
+            r#""full_message":"Exception","_app":"a","_zone":"b"}"#,
+        )]);
+    }
+
+    #[test]
+    fn folding_keeps_the_order_of_the_keys() {
+        let lines = ["Exception", "    at golden.rs:7"];
+        let fold = |builder: BufferAppenderBuilder| builder.set_fold_window(Some(Duration::from_secs(10)));
+        let payloads = send(|builder| fold(builder).set_encoder(Box::new(PatternEncoder::new("{m}"))), &lines);
+        assert_eq!(payloads.iter().map(|payload| golden(payload)).collect::<Vec<_>>(), vec![concat!(
+            r#"{"version":"1.1","host":"HOST","short_message":"Exception","timestamp":0,"level":3,"#,
+            r#""_request_id":42,"_target":"golden","_module_path":"golden::module","_file":"golden.rs","_line":7,"#,
+            r#""full_message":"Exception\n    at golden.rs:7","_app":"a","_zone":"b"}"#,
+        )]);
+
+        // Without an encoder, the folded lines make a `full_message` of their own, last.
+        let payloads = send(fold, &lines);
+        assert_eq!(payloads.iter().map(|payload| golden(payload)).collect::<Vec<_>>(), vec![concat!(
+            r#"{"version":"1.1","host":"HOST","short_message":"Exception","timestamp":0,"level":3,"#,
+            r#""_request_id":42,"_target":"golden","_module_path":"golden::module","_file":"golden.rs","_line":7,"#,
+            r#""_app":"a","_zone":"b","full_message":"Exception\n    at golden.rs:7"}"#,
+        )]);
+    }
+
+    #[test]
+    fn dynamic_fields_are_only_computed_for_records_kept() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let recorder = Recorder::default();
+        let payloads = recorder.0.clone();
+        let mut drop_if = BTreeMap::new();
+        drop_if.insert("health_check".to_string(), Value::Bool(true));
+        let appender = BufferAppender::with_transport(Box::new(recorder))
+            .set_level(Level::Info)
+            .set_target_level("golden::noisy", LevelFilter::Off)
+            .set_drop_if(drop_if)
+            .put_dynamic_field("expensive", move || {
+                counted.fetch_add(1, Ordering::SeqCst);
+                Value::Bool(true)
+            })
+            .build()
+            .unwrap();
+        let log = |level: Level, target: &str, health_check: bool| {
+            appender.append(&Record::builder()
+                .args(format_args!("filtered"))
+                .level(level)
+                .target(target)
+                .key_values(&[("health_check", health_check)])
+                .build()).unwrap();
+        };
+
+        log(Level::Debug, "golden", false);
+        log(Level::Error, "golden::noisy", false);
+        log(Level::Error, "golden", true);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        log(Level::Error, "golden", false);
+        drop(appender);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(payloads.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn json_lines_have_no_gelf_envelope() {
+        let payloads = send(|builder| builder.set_format(OutputFormat::JsonLines), &["Exception"]);
+        assert_eq!(payloads.iter().map(|payload| golden(payload)).collect::<Vec<_>>(), vec![concat!(
+            r#"{"timestamp":0,"host":"HOST","level":"error","message":"Exception","#,
+            r#""request_id":42,"target":"golden","module_path":"golden::module","file":"golden.rs","line":7,"#,
+            r#""app":"a","zone":"b"}"#,
+        )]);
+    }
+
+    #[test]
+    fn logstash_events_are_dated_in_rfc3339() {
+        let payloads = send(|builder| builder.set_format(OutputFormat::Logstash), &["Exception"]);
+        let message: serde_json::Value = serde_json::from_str(&payloads[0]).unwrap();
+        let timestamp = message["@timestamp"].as_str().unwrap();
+        assert!(parse_timestamp(&Value::String(timestamp.to_string())).is_some(), "{}", timestamp);
+        assert_eq!(golden(&payloads[0].replacen(timestamp, "TIMESTAMP", 1)), concat!(
+            r#"{"@timestamp":"TIMESTAMP","@version":"1","message":"Exception","host":"HOST","level":"ERROR","#,
+            r#""request_id":42,"target":"golden","module_path":"golden::module","file":"golden.rs","line":7,"#,
+            r#""app":"a","zone":"b"}"#,
+        ));
+    }
+
+    #[test]
+    fn fields_named_after_the_envelope_are_left_out() {
+        for format in &[OutputFormat::JsonLines, OutputFormat::Logstash] {
+            let payloads = send(|builder| builder.set_format(*format).put_additional_field("message", "shadowed"), &["Exception"]);
+            let message: serde_json::Value = serde_json::from_str(&payloads[0]).unwrap();
+            assert_eq!(message["message"], "Exception", "{:?}", format);
+            assert!(!payloads[0].contains("shadowed"), "{}", payloads[0]);
+        }
+    }
+
+    #[test]
+    fn levels_are_brought_within_the_range() {
+        let levels = |range: (u32, u32)| -> Vec<u64> {
+            [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace].iter()
+                .map(|level| {
+                    let recorder = Recorder::default();
+                    let payloads = recorder.0.clone();
+                    let appender = BufferAppender::with_transport(Box::new(recorder))
+                        .set_level(Level::Trace)
+                        .set_level_range(range.0, range.1)
+                        .build()
+                        .unwrap();
+                    appender.append(&Record::builder().args(format_args!("ranged")).level(*level).build()).unwrap();
+                    drop(appender);
+                    let message: serde_json::Value = serde_json::from_str(&payloads.lock().unwrap()[0]).unwrap();
+                    message["level"].as_u64().unwrap()
+                })
+                .collect()
+        };
+        assert_eq!(levels((0, 7)), vec![3, 4, 6, 7, 7]);
+        assert_eq!(levels((4, 6)), vec![4, 4, 6, 6, 6]);
+    }
+
+    #[test]
+    fn invalid_level_ranges_are_rejected() {
+        for range in &[(5, 2), (0, 8)] {
+            let err = BufferAppender::with_transport(Box::new(Recorder::default()))
+                .set_level_range(range.0, range.1)
+                .build()
+                .unwrap_err();
+            assert!(err.to_string().contains("isn't within 0-7"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn the_severity_can_replace_the_level() {
+        let payloads = send(|builder| builder.set_level_range(4, 6).set_severity_field(Some("severity")), &["Exception"]);
+        assert_eq!(payloads.iter().map(|payload| golden(payload)).collect::<Vec<_>>(), vec![concat!(
+            r#"{"version":"1.1","host":"HOST","short_message":"Exception","timestamp":0,"level":4,"#,
+            r#""_request_id":42,"_target":"golden","_module_path":"golden::module","_file":"golden.rs","_line":7,"#,
+            r#""_severity":4,"_app":"a","_zone":"b"}"#,
+        )]);
+
+        let omitted = |builder: BufferAppenderBuilder| builder.set_level_field(LevelField::Omit).set_severity_field(Some("_severity"));
+        let payloads = send(omitted, &["Exception"]);
+        assert_eq!(payloads.iter().map(|payload| golden(payload)).collect::<Vec<_>>(), vec![concat!(
+            r#"{"version":"1.1","host":"HOST","short_message":"Exception","timestamp":0,"#,
+            r#""_request_id":42,"_target":"golden","_module_path":"golden::module","_file":"golden.rs","_line":7,"#,
+            r#""_severity":3,"_app":"a","_zone":"b"}"#,
+        )]);
+    }
+
+    fn truncated(text: &str, max_bytes: usize) -> String {
+        let mut value = Value::String(text.to_string());
+        truncate_field(&mut value, max_bytes);
+        match value {
+            Value::String(text) => text,
+            value => panic!("not a string: {:?}", value),
+        }
+    }
+
+    #[test]
+    fn truncated_fields_stay_within_the_limit() {
+        let text = "é".repeat(100);
+        for max_bytes in 0..text.len() {
+            let truncated = truncated(&text, max_bytes);
+            assert!(truncated.len() <= max_bytes, "{} bytes out of {}: {}", truncated.len(), max_bytes, truncated);
+        }
+        assert_eq!(truncated(&text, 30), format!("{}…[+186 bytes]", "é".repeat(7)));
+        assert_eq!(truncated(&text, 5), "éé");
+        assert_eq!(truncated("short", 5), "short");
+    }
+
+    #[test]
+    fn the_mark_accounts_for_its_own_digits() {
+        // Making room for the mark of 88 bytes left out leaves out over 99, a digit more.
+        let text = "a".repeat(110);
+        assert_eq!(truncated(&text, 22), format!("{}…[+103 bytes]", "a".repeat(7)));
+        assert_eq!(truncated(&text, 10), "a".repeat(10));
+    }
+}
@@ -0,0 +1,111 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use appender::{BufferAppender, BufferAppenderBuilder};
+use error::Error;
+use log::{LevelFilter, Metadata, Record};
+use log4rs::append::Append;
+use stats::Stats;
+use std::fmt;
+
+/// Appender sending every record to several remote servers, e.g. a Graylog cluster and an
+/// archival collector.
+///
+/// Each destination is a [`BufferAppender`](struct.BufferAppender.html) of its own: it has its
+/// own level, fields, queue, connection and retries, so an unreachable destination doesn't hold
+/// the others back.
+///
+/// ## Example
+///
+/// ```rust
+/// extern crate log;
+///
+/// use log::Level;
+///
+/// fn main() {
+///     let mirror = log4rs_gelf::MirrorAppender::builder()
+///         .add_destination(log4rs_gelf::BufferAppender::builder()
+///             .set_hostname("graylog.example.com")
+///             .set_port(12201))
+///         .add_destination(log4rs_gelf::BufferAppender::builder()
+///             .set_level(Level::Warn)
+///             .set_hostname("archive.example.com")
+///             .set_port(12201))
+///         .build()
+///         .expect("Failed to create appender");
+/// }
+/// ```
+pub struct MirrorAppender {
+    destinations: Vec<BufferAppender>,
+}
+
+/// Builder for [`MirrorAppender`](struct.MirrorAppender.html).
+#[derive(Debug, Default)]
+pub struct MirrorAppenderBuilder {
+    destinations: Vec<BufferAppenderBuilder>,
+}
+
+impl MirrorAppenderBuilder {
+    /// Adds a remote server records are sent to.
+    pub fn add_destination(mut self, destination: BufferAppenderBuilder) -> MirrorAppenderBuilder {
+        self.destinations.push(destination);
+        self
+    }
+    /// Invoke the builder and return a [`MirrorAppender`](struct.MirrorAppender.html).
+    pub fn build(self) -> Result<MirrorAppender, Error> {
+        let destinations = self.destinations.into_iter()
+            .map(BufferAppenderBuilder::build)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(MirrorAppender { destinations })
+    }
+}
+
+impl MirrorAppender {
+    /// Creates a new [`MirrorAppenderBuilder`](struct.MirrorAppenderBuilder.html).
+    pub fn builder() -> MirrorAppenderBuilder {
+        MirrorAppenderBuilder::default()
+    }
+    /// Returns a snapshot of the records sent and dropped so far by each destination, in the
+    /// order they were added.
+    pub fn stats(&self) -> Vec<Stats> {
+        self.destinations.iter().map(BufferAppender::stats).collect()
+    }
+    /// Whether any destination sends records of this level and target, see
+    /// [`BufferAppender::enabled`](struct.BufferAppender.html#method.enabled).
+    pub fn enabled(&self, metadata: &Metadata) -> bool {
+        self.destinations.iter().any(|destination| destination.enabled(metadata))
+    }
+    /// The most verbose level any destination sends records at.
+    pub fn max_level(&self) -> LevelFilter {
+        self.destinations.iter().map(BufferAppender::max_level).max().unwrap_or(LevelFilter::Off)
+    }
+}
+
+impl fmt::Debug for MirrorAppender {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MirrorAppender")
+            .field("destinations", &self.destinations.len())
+            .finish()
+    }
+}
+
+impl Append for MirrorAppender {
+    /// Hands the record to every destination, even if one of them fails. The first error is
+    /// returned.
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        for destination in &self.destinations {
+            let appended = destination.append(record);
+            if result.is_ok() {
+                result = appended;
+            }
+        }
+        result
+    }
+    fn flush(&self) {
+        for destination in &self.destinations {
+            destination.flush();
+        }
+    }
+}
@@ -0,0 +1,57 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use background::{self, Background};
+use log::kv;
+use log::{Level, Record};
+use message::{GelfMessage, MessageOptions};
+use sender::{Entry, SenderHandle};
+use stats::StatsCollector;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Target of the self-monitoring messages.
+const MONITOR_TARGET: &str = "log4rs_gelf::stats";
+
+/// Starts a thread sending the statistics of an appender every `interval`, as a GELF message
+/// flagged `_self_monitoring`. It stops once the returned handle is dropped.
+pub(crate) fn spawn(interval: Duration, options: MessageOptions, sender: SenderHandle,
+                    stats: Arc<StatsCollector>) -> io::Result<Background> {
+    background::spawn("gelf-stats", move |stopped| {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            let payload = panic::catch_unwind(AssertUnwindSafe(|| render(&options, &stats, &sender)));
+            if let Ok(Some(payload)) = payload {
+                sender.send(Entry::new(payload, Level::Info, MONITOR_TARGET));
+            }
+        }
+    })
+}
+
+fn render(options: &MessageOptions, stats: &StatsCollector, sender: &SenderHandle) -> Option<Vec<u8>> {
+    let stats = stats.snapshot();
+    let fields: [(&str, kv::Value); 8] = [
+        ("self_monitoring", kv::Value::from(true)),
+        ("sent", kv::Value::from(stats.sent)),
+        ("dropped", kv::Value::from(stats.dropped)),
+        ("queue_depth", kv::Value::from(sender.queue_depth() as u64)),
+        ("reconnects", kv::Value::from(stats.reconnects)),
+        ("message_size_p95", kv::Value::from(stats.message_sizes.p95 as u64)),
+        ("message_size_max", kv::Value::from(stats.message_sizes.max as u64)),
+        ("send_latency_p99_ms", kv::Value::from(stats.send_latency.p99.as_secs_f64() * 1000.0)),
+    ];
+    let fields: &[(&str, kv::Value)] = &fields;
+    // Dynamic fields and encoders are user code: a panic only loses this message.
+    let payload = GelfMessage::from_record(&Record::builder()
+        .args(format_args!("log4rs-gelf statistics: {} sent, {} dropped", stats.sent, stats.dropped))
+        .level(Level::Info)
+        .target(MONITOR_TARGET)
+        .key_values(&fields)
+        .build(), options)
+        .ok()
+        .and_then(|message| message.to_json().ok());
+    payload
+}
@@ -0,0 +1,86 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use log::Record;
+use message::Fields;
+use serde_value::Value;
+use std::mem;
+use std::process;
+
+/// Names of the metadata fields of the messages.
+#[derive(serde_derive::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldSchema {
+    /// The names of this crate: `_target`, `_file`, `pkg_name`, ...
+    #[default]
+    Gelf,
+    /// The Graylog Information Model, as expected by the Illuminate content packs: `pkg_name` and
+    /// `pkg_version` become `event_source_product` and `vendor_version`, and the
+    /// `vendor_event_severity`, `host_hostname` and `process_id` fields are added.
+    Gim,
+    /// The Elastic Common Schema, for messages also indexed by Elasticsearch: `_target` becomes
+    /// `log.logger`, `_file` and `_line` `log.origin.file.name` and `log.origin.file.line`,
+    /// `pkg_name` and `pkg_version` `service.name` and `service.version`, and the `log.level`,
+    /// `host.name` and `process.pid` fields are added.
+    Ecs,
+}
+
+/// GIM names of the fields of this crate. The others have no GIM equivalent.
+const GIM_NAMES: [(&str, &str); 2] = [
+    ("_pkg_name", "_event_source_product"),
+    ("_pkg_version", "_vendor_version"),
+];
+
+/// ECS names of the fields of this crate.
+const ECS_NAMES: [(&str, &str); 6] = [
+    ("_pkg_name", "_service.name"),
+    ("_pkg_version", "_service.version"),
+    ("_target", "_log.logger"),
+    ("_file", "_log.origin.file.name"),
+    ("_line", "_log.origin.file.line"),
+    ("_thread", "_process.thread.name"),
+];
+
+impl FieldSchema {
+    /// Name of the field called `name` by this crate.
+    pub(crate) fn rename(self, name: String) -> String {
+        let names: &[(&str, &str)] = match self {
+            FieldSchema::Gelf => return name,
+            FieldSchema::Gim => &GIM_NAMES,
+            FieldSchema::Ecs => &ECS_NAMES,
+        };
+        match names.iter().find(|(gelf, _)| *gelf == name) {
+            Some((_, renamed)) => renamed.to_string(),
+            None => name,
+        }
+    }
+
+    /// Renames the metadata fields of a message, and adds those the schema expects besides.
+    /// Fields of the record take precedence.
+    pub(crate) fn apply(self, record: &Record, host: &str, fields: &mut Fields) {
+        if self == FieldSchema::Gelf {
+            return;
+        }
+        *fields = mem::take(fields).into_iter()
+            .map(|(key, value)| (self.rename(key), value))
+            .collect();
+        let level = Value::String(record.level().as_str().to_lowercase());
+        let extra = match self {
+            FieldSchema::Gelf => vec![],
+            FieldSchema::Gim => vec![
+                ("_vendor_event_severity", level),
+                ("_host_hostname", Value::String(host.to_string())),
+                ("_process_id", Value::U32(process::id())),
+            ],
+            FieldSchema::Ecs => vec![
+                ("_log.level", level),
+                ("_host.name", Value::String(host.to_string())),
+                ("_process.pid", Value::U32(process::id())),
+            ],
+        };
+        for (key, value) in extra {
+            fields.entry(key.to_string()).or_insert(value);
+        }
+    }
+}
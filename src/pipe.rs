@@ -0,0 +1,120 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+//! Logging the output of child processes.
+
+use log::kv::{self, VisitSource};
+use log::{Level, Record};
+use serde_value::Value;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read};
+use std::thread::{self, JoinHandle};
+
+/// Target of the records logged by [`capture`](fn.capture.html), for routes and filters to
+/// match.
+pub const TARGET: &str = "log4rs_gelf::pipe";
+
+/// Logs each line read from `output`, typically the stdout or stderr of a child process, at
+/// `level` and with `fields` as record key-values, from a thread of its own. The records go
+/// through the logger installed, and so through the GELF appenders configured for the
+/// [`TARGET`](constant.TARGET.html) target.
+///
+/// Line endings are stripped and empty lines skipped: indentation is kept, so that stack traces
+/// can be folded with `fold_window`. Invalid UTF-8 is replaced. The thread ends with `output`,
+/// returning the number of lines logged.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// extern crate log;
+///
+/// use log::Level;
+/// use log4rs_gelf::Value;
+/// use std::collections::BTreeMap;
+/// use std::process::{Command, Stdio};
+///
+/// fn main() {
+///     let mut child = Command::new("legacy-daemon")
+///         .stdout(Stdio::piped())
+///         .stderr(Stdio::piped())
+///         .spawn()
+///         .unwrap();
+///     let mut fields = BTreeMap::new();
+///     fields.insert("program".to_string(), Value::String("legacy-daemon".to_string()));
+///     let stdout = log4rs_gelf::pipe::capture(child.stdout.take().unwrap(), Level::Info, fields.clone()).unwrap();
+///     let stderr = log4rs_gelf::pipe::capture(child.stderr.take().unwrap(), Level::Warn, fields).unwrap();
+///     child.wait().unwrap();
+///     stdout.join().unwrap().unwrap();
+///     stderr.join().unwrap().unwrap();
+/// }
+/// ```
+pub fn capture<R>(output: R, level: Level, fields: BTreeMap<String, Value>) -> io::Result<JoinHandle<io::Result<u64>>>
+    where R: Read + Send + 'static
+{
+    let fields = LineFields(fields.into_iter().map(|(key, value)| (key, scalar(value))).collect());
+    thread::Builder::new()
+        .name("gelf-pipe".to_string())
+        .spawn(move || forward(BufReader::new(output), level, &fields))
+}
+
+fn forward<R: BufRead>(mut output: R, level: Level, fields: &LineFields) -> io::Result<u64> {
+    let mut logged = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if output.read_until(b'\n', &mut line)? == 0 {
+            return Ok(logged);
+        }
+        let end = line.iter().rposition(|byte| !matches!(byte, b'\n' | b'\r')).map_or(0, |end| end + 1);
+        if end == 0 || level > log::max_level() {
+            continue;
+        }
+        let text = String::from_utf8_lossy(&line[..end]);
+        log::logger().log(&Record::builder()
+            .args(format_args!("{}", text))
+            .level(level)
+            .target(TARGET)
+            .module_path_static(Some(TARGET))
+            .key_values(fields)
+            .build());
+        logged += 1;
+    }
+}
+
+/// Fields attached to the lines, as booleans, numbers or strings.
+struct LineFields(Vec<(String, Value)>);
+
+impl kv::Source for LineFields {
+    fn visit<'kvs>(&'kvs self, visitor: &mut dyn VisitSource<'kvs>) -> Result<(), kv::Error> {
+        for (key, value) in &self.0 {
+            let value = match value {
+                Value::Bool(value) => kv::Value::from(*value),
+                Value::U64(value) => kv::Value::from(*value),
+                Value::I64(value) => kv::Value::from(*value),
+                Value::F64(value) => kv::Value::from(*value),
+                Value::String(value) => kv::Value::from(value.as_str()),
+                _ => continue,
+            };
+            visitor.visit_pair(kv::Key::from_str(key), value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts a value to one a key-value can hold, serializing sequences and maps to JSON.
+fn scalar(value: Value) -> Value {
+    match value {
+        Value::Bool(_) | Value::U64(_) | Value::I64(_) | Value::F64(_) | Value::String(_) => value,
+        Value::U8(value) => Value::U64(value.into()),
+        Value::U16(value) => Value::U64(value.into()),
+        Value::U32(value) => Value::U64(value.into()),
+        Value::I8(value) => Value::I64(value.into()),
+        Value::I16(value) => Value::I64(value.into()),
+        Value::I32(value) => Value::I64(value.into()),
+        Value::F32(value) => Value::F64(value.into()),
+        Value::Char(value) => Value::String(value.to_string()),
+        Value::Option(Some(value)) | Value::Newtype(value) => scalar(*value),
+        value => Value::String(serde_json::to_string(&value).unwrap_or_default()),
+    }
+}
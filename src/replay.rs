@@ -0,0 +1,99 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+//! Replaying GELF messages spooled to a file.
+
+use anyhow::Context;
+use appender::BufferAppender;
+use log::Level;
+use log4rs::append::Append;
+use serde_json::{Map, Value};
+use severity::log_level;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Outcome of [`send_file`](fn.send_file.html).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Messages handed over to the appender.
+    pub queued: u64,
+    /// Messages the appender dropped instead, its memory limit being reached.
+    pub dropped: u64,
+    /// Lines which aren't a JSON object, skipped.
+    pub invalid_lines: u64,
+}
+
+/// Ships the GELF messages of a newline-delimited JSON file through `appender`, at most
+/// `max_per_second` of them per second when given. Such files are written by the `stdout` and
+/// `stderr` fallbacks, or by the file appenders of log4rs with a JSON encoder.
+///
+/// Messages are sent as they are, regardless of the level and filters of the appender: their
+/// `timestamp` is kept. Empty lines are ignored. The appender is flushed at the end, waiting for
+/// its flush timeout if it has one.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// let appender = log4rs_gelf::BufferAppender::builder()
+///     .set_hostname("graylog.example.com")
+///     .set_flush_timeout(Some(std::time::Duration::from_secs(30)))
+///     .build()
+///     .unwrap();
+/// let summary = log4rs_gelf::replay::send_file("/var/spool/gelf.ndjson", &appender, Some(500)).unwrap();
+/// println!("{} messages sent, {} invalid lines", summary.queued, summary.invalid_lines);
+/// ```
+pub fn send_file<P: AsRef<Path>>(path: P, appender: &BufferAppender, max_per_second: Option<u32>) -> anyhow::Result<Summary> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path).with_context(|| format!("failed to open {}", path.display()))?);
+    let interval = max_per_second.filter(|&rate| rate > 0).map(|rate| Duration::from_secs(1) / rate);
+    let started = Instant::now();
+    let mut summary = Summary::default();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).with_context(|| format!("failed to read {}", path.display()))? == 0 {
+            break;
+        }
+        let json = trim(&line);
+        if json.is_empty() {
+            continue;
+        }
+        let message = match serde_json::from_slice::<Map<String, Value>>(json) {
+            Ok(message) => message,
+            Err(_) => {
+                summary.invalid_lines += 1;
+                continue;
+            }
+        };
+
+        if let Some(interval) = interval {
+            // Paced from the start, so that slow sends don't lower the rate.
+            let due = started + interval * (summary.queued + summary.dropped) as u32;
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+        let level = message.get("level")
+            .and_then(Value::as_u64)
+            .map_or(Level::Info, |severity| log_level(severity as u32));
+        let target = message.get("_target").and_then(Value::as_str).unwrap_or("");
+        if appender.send_serialized(json.to_vec(), level, target) {
+            summary.queued += 1;
+        } else {
+            summary.dropped += 1;
+        }
+    }
+    appender.flush();
+    Ok(summary)
+}
+
+fn trim(line: &[u8]) -> &[u8] {
+    let start = line.iter().position(|byte| !byte.is_ascii_whitespace()).unwrap_or(line.len());
+    let end = line.iter().rposition(|byte| !byte.is_ascii_whitespace()).map_or(start, |end| end + 1);
+    &line[start..end]
+}
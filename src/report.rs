@@ -0,0 +1,132 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use error::Error;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+/// Creates a channel the transport errors of an appender can be awaited from, as given to
+/// [`set_async_error_channel`](struct.BufferAppenderBuilder.html#method.set_async_error_channel).
+///
+/// It holds up to `capacity` errors: while the receiver doesn't keep up, the oldest ones are
+/// dropped, so that a flapping connection can't exhaust the memory.
+///
+/// ## Example
+///
+/// ```rust
+/// let (sender, receiver) = log4rs_gelf::error_channel(64);
+/// let builder = log4rs_gelf::BufferAppender::builder().set_async_error_channel(sender);
+/// // A task then loops on `receiver.recv().await`, until it resolves to `None`.
+/// ```
+pub fn error_channel(capacity: usize) -> (ErrorSender, ErrorReceiver) {
+    let shared = Arc::new(Mutex::new(Queue {
+        errors: VecDeque::new(),
+        capacity: capacity.max(1),
+        waker: None,
+        senders: 1,
+    }));
+    (ErrorSender { shared: shared.clone() }, ErrorReceiver { shared })
+}
+
+struct Queue {
+    errors: VecDeque<Arc<Error>>,
+    capacity: usize,
+    waker: Option<Waker>,
+    /// The channel is closed once none is left.
+    senders: usize,
+}
+
+fn lock(shared: &Mutex<Queue>) -> MutexGuard<'_, Queue> {
+    shared.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Sending half of an [`error_channel`](fn.error_channel.html).
+pub struct ErrorSender {
+    shared: Arc<Mutex<Queue>>,
+}
+
+impl ErrorSender {
+    /// Queues an error, dropping the oldest one if the channel is full.
+    pub(crate) fn send(&self, err: Arc<Error>) {
+        let mut queue = lock(&self.shared);
+        if queue.errors.len() == queue.capacity {
+            queue.errors.pop_front();
+        }
+        queue.errors.push_back(err);
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Clone for ErrorSender {
+    fn clone(&self) -> ErrorSender {
+        lock(&self.shared).senders += 1;
+        ErrorSender { shared: self.shared.clone() }
+    }
+}
+
+impl Drop for ErrorSender {
+    fn drop(&mut self) {
+        let mut queue = lock(&self.shared);
+        queue.senders -= 1;
+        if queue.senders == 0 {
+            if let Some(waker) = queue.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ErrorSender {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ErrorSender").finish()
+    }
+}
+
+/// Receiving half of an [`error_channel`](fn.error_channel.html).
+pub struct ErrorReceiver {
+    shared: Arc<Mutex<Queue>>,
+}
+
+impl ErrorReceiver {
+    /// Waits for the next error. Resolves to `None` once the appenders holding the sender are
+    /// dropped and every error was received.
+    ///
+    /// Nothing blocks: it can be awaited from any executor.
+    pub fn recv(&mut self) -> impl Future<Output = Option<Arc<Error>>> + '_ {
+        Recv { shared: &self.shared }
+    }
+}
+
+impl fmt::Debug for ErrorReceiver {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ErrorReceiver").finish()
+    }
+}
+
+/// Future returned by [`ErrorReceiver::recv`](struct.ErrorReceiver.html#method.recv).
+struct Recv<'r> {
+    shared: &'r Mutex<Queue>,
+}
+
+impl<'r> Future for Recv<'r> {
+    type Output = Option<Arc<Error>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut queue = lock(self.shared);
+        if let Some(err) = queue.errors.pop_front() {
+            return Poll::Ready(Some(err));
+        }
+        if queue.senders == 0 {
+            return Poll::Ready(None);
+        }
+        queue.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
@@ -0,0 +1,205 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use appender::{BufferAppender, BufferAppenderBuilder};
+use context::RecordContext;
+use error::Error;
+use filter::{field_matches, matches_prefix};
+use log::{Level, LevelFilter, Metadata, Record};
+use log4rs::append::Append;
+use message::field_name;
+use serde_value::Value;
+use stats::Stats;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Which records a route of a [`RouterAppender`](struct.RouterAppender.html) takes. A record
+/// must meet every condition set; a route without any takes them all.
+///
+/// ## Example
+///
+/// ```rust
+/// extern crate log;
+///
+/// use log::Level;
+/// use log4rs_gelf::Route;
+///
+/// fn main() {
+///     // Warnings and errors of the payments::* modules.
+///     let route = Route::default()
+///         .set_min_level(Level::Warn)
+///         .add_target("payments");
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Route {
+    min_level: Option<Level>,
+    max_level: Option<Level>,
+    targets: Vec<String>,
+    fields: Vec<(String, Value)>,
+}
+
+impl Route {
+    /// Only takes records at least as severe as `level`.
+    pub fn set_min_level(mut self, level: Level) -> Route {
+        self.min_level = Some(level);
+        self
+    }
+    /// Only takes records at most as severe as `level`: `Info` to `Warn` takes the `Info` and
+    /// `Warn` records.
+    pub fn set_max_level(mut self, level: Level) -> Route {
+        self.max_level = Some(level);
+        self
+    }
+    /// Takes records whose target is `prefix` or one of its submodules. When several prefixes are
+    /// added, any of them will do.
+    pub fn add_target(mut self, prefix: &str) -> Route {
+        self.targets.push(prefix.to_string());
+        self
+    }
+    /// Only takes records whose context, MDC or key-values, has all the given field values.
+    pub fn set_fields(mut self, fields: BTreeMap<String, Value>) -> Route {
+        self.fields = fields.into_iter().map(|(key, value)| (field_name(&key), value)).collect();
+        self
+    }
+
+    /// Whether the route takes `record`. The context is only captured, once for all the routes,
+    /// when the level and target of the record didn't rule it out.
+    fn matches(&self, record: &Record, context: &mut Option<RecordContext>) -> bool {
+        if !self.admits(record.metadata()) {
+            return false;
+        }
+        if self.fields.is_empty() {
+            return true;
+        }
+        let context = context.get_or_insert_with(|| RecordContext::capture(record));
+        self.fields.iter().all(|(name, expected)| {
+            context.fields.iter()
+                .find(|(key, _)| field_name(key) == *name)
+                .is_some_and(|(_, actual)| field_matches(actual, expected))
+        })
+    }
+
+    /// Whether the level and the target of a record let the route take it.
+    fn admits(&self, metadata: &Metadata) -> bool {
+        if self.min_level.is_some_and(|level| metadata.level() > level)
+            || self.max_level.is_some_and(|level| metadata.level() < level)
+        {
+            return false;
+        }
+        self.targets.is_empty() || self.targets.iter().any(|prefix| matches_prefix(metadata.target(), prefix))
+    }
+}
+
+/// Appender dispatching each record to the destinations whose route takes it, e.g. the errors to
+/// an alerting collector and everything else to Graylog. Records no route takes are dropped.
+///
+/// Like with the [`MirrorAppender`](struct.MirrorAppender.html), each destination is a
+/// [`BufferAppender`](struct.BufferAppender.html) of its own.
+///
+/// ## Example
+///
+/// ```rust
+/// extern crate log;
+///
+/// use log::Level;
+/// use log4rs_gelf::Route;
+///
+/// fn main() {
+///     let router = log4rs_gelf::RouterAppender::builder()
+///         .add_route(Route::default().set_min_level(Level::Error), log4rs_gelf::BufferAppender::builder()
+///             .set_hostname("alerts.example.com")
+///             .set_port(12201))
+///         .add_route(Route::default().set_max_level(Level::Warn), log4rs_gelf::BufferAppender::builder()
+///             .set_hostname("graylog.example.com")
+///             .set_port(12201))
+///         .build()
+///         .expect("Failed to create appender");
+/// }
+/// ```
+pub struct RouterAppender {
+    routes: Vec<(Route, BufferAppender)>,
+}
+
+/// Builder for [`RouterAppender`](struct.RouterAppender.html).
+#[derive(Debug, Default)]
+pub struct RouterAppenderBuilder {
+    routes: Vec<(Route, BufferAppenderBuilder)>,
+}
+
+impl RouterAppenderBuilder {
+    /// Sends the records `route` takes to `destination`.
+    pub fn add_route(mut self, route: Route, destination: BufferAppenderBuilder) -> RouterAppenderBuilder {
+        self.routes.push((route, destination));
+        self
+    }
+    /// Invoke the builder and return a [`RouterAppender`](struct.RouterAppender.html).
+    pub fn build(self) -> Result<RouterAppender, Error> {
+        let routes = self.routes.into_iter()
+            .map(|(route, destination)| destination.build().map(|destination| (route, destination)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(RouterAppender { routes })
+    }
+}
+
+impl RouterAppender {
+    /// Creates a new [`RouterAppenderBuilder`](struct.RouterAppenderBuilder.html).
+    pub fn builder() -> RouterAppenderBuilder {
+        RouterAppenderBuilder::default()
+    }
+    /// Returns a snapshot of the records sent and dropped so far by each destination, in the
+    /// order the routes were added.
+    pub fn stats(&self) -> Vec<Stats> {
+        self.routes.iter().map(|(_, destination)| destination.stats()).collect()
+    }
+    /// Whether a route may take records of this level and target and its destination send them,
+    /// see [`BufferAppender::enabled`](struct.BufferAppender.html#method.enabled). Routes on
+    /// fields can only be told with the whole record.
+    pub fn enabled(&self, metadata: &Metadata) -> bool {
+        self.routes.iter().any(|(route, destination)| route.admits(metadata) && destination.enabled(metadata))
+    }
+    /// The most verbose level any route sends records at.
+    pub fn max_level(&self) -> LevelFilter {
+        self.routes.iter()
+            .map(|(route, destination)| match route.min_level {
+                Some(level) => cmp::min(level.to_level_filter(), destination.max_level()),
+                None => destination.max_level(),
+            })
+            .max()
+            .unwrap_or(LevelFilter::Off)
+    }
+}
+
+impl fmt::Debug for RouterAppender {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("RouterAppender")
+            .field("routes", &self.routes.len())
+            .finish()
+    }
+}
+
+impl Append for RouterAppender {
+    /// Hands the record to every destination whose route takes it, even if one of them fails.
+    /// The first error is returned.
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let mut context = None;
+        let mut result = Ok(());
+        for (route, destination) in &self.routes {
+            if !route.matches(record, &mut context) {
+                continue;
+            }
+            let appended = destination.append(record);
+            if result.is_ok() {
+                result = appended;
+            }
+        }
+        result
+    }
+    fn flush(&self) {
+        for (_, destination) in &self.routes {
+            destination.flush();
+        }
+    }
+}
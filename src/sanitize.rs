@@ -0,0 +1,73 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use serde_value::Value;
+use std::borrow::Cow;
+use std::fmt::{self, Write};
+
+/// Escapes the NUL characters of `text` as `\u{0}`.
+pub(crate) fn sanitize_str(text: &str) -> Cow<'_, str> {
+    if !text.chars().any(is_escaped) {
+        return Cow::Borrowed(text);
+    }
+    let mut sanitized = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        if is_escaped(c) {
+            sanitized.extend(c.escape_unicode());
+        } else {
+            sanitized.push(c);
+        }
+    }
+    Cow::Owned(sanitized)
+}
+
+/// Same as [`sanitize_str`](fn.sanitize_str.html), reusing `text` when it is clean.
+pub(crate) fn sanitize_string(text: String) -> String {
+    if text.chars().any(is_escaped) {
+        sanitize_str(&text).into_owned()
+    } else {
+        text
+    }
+}
+
+/// NUL frames the messages of the GELF TCP input, other control characters such as tabs and
+/// carriage returns are left as they are.
+fn is_escaped(c: char) -> bool {
+    c == '\0'
+}
+
+/// Sanitizes the strings found in `value`. Bytes become a string, invalid UTF-8 sequences being
+/// replaced by `U+FFFD`.
+pub(crate) fn sanitize_value(value: Value) -> Value {
+    match value {
+        Value::String(text) => Value::String(sanitize_string(text)),
+        Value::Char(c) if is_escaped(c) => Value::String(c.escape_unicode().to_string()),
+        Value::Bytes(bytes) => Value::String(sanitize_str(&String::from_utf8_lossy(&bytes)).into_owned()),
+        Value::Option(Some(value)) => Value::Option(Some(Box::new(sanitize_value(*value)))),
+        Value::Newtype(value) => Value::Newtype(Box::new(sanitize_value(*value))),
+        Value::Seq(values) => Value::Seq(values.into_iter().map(sanitize_value).collect()),
+        Value::Map(entries) => Value::Map(entries.into_iter()
+            .map(|(key, value)| (sanitize_value(key), sanitize_value(value)))
+            .collect()),
+        value => value,
+    }
+}
+
+/// Sanitizes a value while it is formatted.
+pub(crate) struct Sanitized<T>(pub(crate) T);
+
+impl<T: fmt::Display> fmt::Display for Sanitized<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut escaper = Escaper(f);
+        write!(escaper, "{}", self.0)
+    }
+}
+
+struct Escaper<'f, 'b: 'f>(&'f mut fmt::Formatter<'b>);
+
+impl<'f, 'b> Write for Escaper<'f, 'b> {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        self.0.write_str(&sanitize_str(text))
+    }
+}
@@ -0,0 +1,209 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use file::{Config, MirrorConfig, RouteConfig, RouteRule, RouterConfig, KINDS, MIRROR_KIND, ROUTER_KIND};
+use serde::de::{self, value, Deserialize, Deserializer, Visitor};
+use serde_json::{json, Map, Value};
+
+/// Names accepted by level settings, whatever their case as for log4rs.
+const LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+
+/// Builds the JSON Schema of the GELF appender configurations.
+///
+/// The properties are the fields of the configuration structs, read from their `Deserialize`
+/// implementations, so that none is left out.
+pub(crate) fn schema() -> Value {
+    let gelf = object(field_names::<Config>(), &["level", "hostname", "port", "additional_fields", "use_tls"], true);
+    let mirror = object(field_names::<MirrorConfig>(), &["destinations"], true);
+    let router = object(field_names::<RouterConfig>(), &["routes"], true);
+    let route = object(field_names::<RouteConfig>(), &["destination"], false);
+    let rule = object(field_names::<RouteRule>(), &[], false);
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "log4rs-gelf appender",
+        "type": "object",
+        "required": ["kind"],
+        "properties": {
+            "kind": { "enum": KINDS },
+        },
+        "if": { "properties": { "kind": { "const": MIRROR_KIND } } },
+        "then": { "$ref": "#/$defs/mirror" },
+        "else": {
+            "if": { "properties": { "kind": { "const": ROUTER_KIND } } },
+            "then": { "$ref": "#/$defs/router" },
+            "else": { "$ref": "#/$defs/gelf" },
+        },
+        "$defs": {
+            "gelf": gelf,
+            "mirror": mirror,
+            "router": router,
+            "route": route,
+            "route_rule": rule,
+        },
+    })
+}
+
+/// Schema of a configuration object. Appenders also take the log4rs `kind` and `filters`, and
+/// profiles.
+fn object(fields: &[&str], required: &[&str], appender: bool) -> Value {
+    let mut properties = Map::new();
+    for field in fields {
+        properties.insert(field.to_string(), property(field));
+    }
+    if appender {
+        properties.insert("kind".to_string(), json!({ "type": "string" }));
+        properties.insert("filters".to_string(), json!({ "type": "array" }));
+        properties.insert("profile_env".to_string(), json!({ "type": "string" }));
+        properties.insert("on_build_error".to_string(), json!({ "enum": ["fail", "skip", "stub"] }));
+        properties.insert("profiles".to_string(), json!({
+            "type": "object",
+            "description": "Settings overriding the base ones, per profile name.",
+            "additionalProperties": { "type": "object" },
+        }));
+    }
+    let required: Vec<&str> = required.iter().filter(|field| fields.contains(*field)).cloned().collect();
+    json!({
+        "type": "object",
+        "required": required,
+        "properties": properties,
+        "additionalProperties": false,
+    })
+}
+
+/// Schema of a setting, from its name. Settings without a known type accept any value.
+fn property(field: &str) -> Value {
+    match field {
+        "level" | "fallback_level" | "min_level" | "max_level" => levels(&LEVELS),
+        "priority_queue_level" => levels(&[&LEVELS[..], &["off"]].concat()),
+        "target_levels" => json!({
+            "type": "object",
+            "additionalProperties": levels(&[&LEVELS[..], &["off"]].concat()),
+        }),
+        "protocol" => json!({ "enum": ["tcp", "http"] }),
+        "framing" => json!({ "enum": ["null", "newline", "length_prefix", "length-prefix"] }),
+        "overflow_policy" => json!({ "enum": ["drop_newest", "drop_oldest", "block"] }),
+        "ordering" => json!({ "enum": ["per_thread", "global", "none"] }),
+        "delivery" => json!({ "enum": ["buffered", "guaranteed"] }),
+        "fallback" => json!({ "enum": ["none", "stdout", "stderr"] }),
+        "schema" => json!({ "enum": ["gelf", "gim", "ecs"] }),
+        "format" => json!({ "enum": ["gelf", "json_lines", "logstash"] }),
+        "level_field" => json!({ "enum": ["keep", "omit"] }),
+        "level_range" => json!({
+            "type": "array",
+            "items": { "type": "integer", "minimum": 0, "maximum": 7 },
+            "minItems": 2,
+            "maxItems": 2,
+        }),
+        "empty_field_policy" => json!({ "enum": ["keep", "drop", "null", "empty_string"] }),
+        "redact" => json!({
+            "type": "array",
+            "items": { "enum": ["credit_cards", "bearer_tokens", "aws_keys"] },
+        }),
+        "hostname" | "name" | "directives" | "severity_field" | "capture_to_file" | "protocol_version" | "http_path" | "encoder_field" | "short_message_template" | "tls_pinned_cert_sha256"
+        | "http_authorization" | "tls_client_cert" | "tls_client_key" => {
+            json!({ "type": "string" })
+        }
+        "connections" => json!({ "type": "integer", "minimum": 1 }),
+        "clock_offset" => json!({ "type": "number", "description": "In seconds, negative for a clock running ahead." }),
+        "port" => json!({ "type": "integer", "minimum": 1, "maximum": 65535 }),
+        "buffer_size" | "max_batch_records" | "max_batch_bytes" | "max_memory_bytes" | "drop_stats_targets"
+        | "max_field_bytes" | "compression_threshold" | "capture_max_bytes" => json!({ "type": "integer", "minimum": 0 }),
+        "full_message_chunk_bytes" | "queue_capacity" => json!({ "type": "integer", "minimum": 1 }),
+        "max_batch_delay" | "fold_window" => json!({ "type": "integer", "minimum": 0, "description": "In milliseconds." }),
+        "connect_timeout" | "write_timeout" | "http_idle_timeout" | "record_ttl" | "flush_timeout"
+        | "stats_interval" | "error_report_interval" => json!({ "type": "integer", "minimum": 0, "description": "In seconds." }),
+        "null_character" | "default_fields" | "windows_fields" | "priority_field" | "sanitize" | "log_config"
+        | "use_tls" | "tls_verify_ca" | "strict" | "synchronous" | "clock_offset_from_http"
+        | "correlation_id" | "deferred_serialization" | "tracing_spans" => json!({ "type": "boolean" }),
+        "additional_fields" | "envelope_fields" | "drop_if" | "only_if" | "fields" => json!({ "type": "object" }),
+        "target_fields" => json!({ "type": "object", "additionalProperties": { "type": "object" } }),
+        "secret_fields" => json!({ "type": "object", "additionalProperties": { "type": "string" } }),
+        "enrichers" | "targets" | "host_tags" => json!({ "type": "array", "items": { "type": "string" } }),
+        "encoder" | "diagnostics" => json!({ "type": "object", "required": ["kind"] }),
+        "destinations" => json!({ "type": "array", "items": { "$ref": "#/$defs/gelf" } }),
+        "destination" => json!({ "$ref": "#/$defs/gelf" }),
+        "routes" => json!({ "type": "array", "items": { "$ref": "#/$defs/route" } }),
+        "match" => json!({ "$ref": "#/$defs/route_rule" }),
+        _ => json!({}),
+    }
+}
+
+/// Schema of a level setting taking one of `names`, in any case, such as `^([Oo][Ff][Ff])$`.
+fn levels(names: &[&str]) -> Value {
+    let names: Vec<String> = names.iter()
+        .map(|name| name.chars().map(|c| format!("[{}{}]", c.to_ascii_uppercase(), c)).collect())
+        .collect();
+    json!({ "type": "string", "pattern": format!("^({})$", names.join("|")) })
+}
+
+/// Names of the fields of a struct deriving `Deserialize`.
+fn field_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// Deserializer only recording the fields of the struct asked for.
+struct FieldNames<'f>(&'f mut &'static [&'static str]);
+
+impl<'de, 'f> Deserializer<'de> for FieldNames<'f> {
+    type Error = value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, value::Error> {
+        Err(de::Error::custom("only structs are inspected"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, value::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("fields recorded"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_setting_has_a_schema() {
+        let fields = [
+            field_names::<Config>(),
+            field_names::<MirrorConfig>(),
+            field_names::<RouterConfig>(),
+            field_names::<RouteConfig>(),
+            field_names::<RouteRule>(),
+        ];
+        for field in fields.iter().flat_map(|fields| fields.iter()) {
+            assert_ne!(property(field), json!({}), "{} has no schema", field);
+        }
+        for field in &["secret_fields", "http_authorization"] {
+            assert!(fields[0].contains(field), "{}", field);
+        }
+        // Only in builds with TLS.
+        #[cfg(feature = "tls")]
+        for field in &["use_tls", "tls_client_cert", "tls_client_key"] {
+            assert!(fields[0].contains(field), "{}", field);
+        }
+    }
+
+    #[test]
+    fn levels_are_matched_whatever_their_case() {
+        assert_eq!(
+            property("level"),
+            json!({ "type": "string", "pattern": "^([Ee][Rr][Rr][Oo][Rr]|[Ww][Aa][Rr][Nn]|[Ii][Nn][Ff][Oo]|[Dd][Ee][Bb][Uu][Gg]|[Tt][Rr][Aa][Cc][Ee])$" }),
+        );
+        let off = property("priority_queue_level");
+        assert!(off["pattern"].as_str().unwrap().ends_with("|[Oo][Ff][Ff])$"), "{}", off);
+        assert_eq!(property("target_levels")["additionalProperties"], off);
+    }
+}
@@ -0,0 +1,1795 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use background::{self, SHUTDOWN_TIMEOUT};
+use capture::Capture;
+use correlation;
+use error::{panic_message, Error};
+#[cfg(feature = "async")]
+use error::FlushError;
+use flush::FlushTracker;
+use hooks::{ConnectionHooks, ErrorThrottle};
+use log::{Level, LevelFilter};
+use message::OwnedMessage;
+use stats::StatsCollector;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, Weak};
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use transport::{new_transport, GelfTransport, TransportConfig};
+
+/// Default upper bound on the memory held by queued and in-flight records.
+pub(crate) const DEFAULT_MAX_MEMORY_BYTES: usize = 64 * 1024 * 1024;
+
+/// Default maximum number of records written in one batch.
+pub(crate) const DEFAULT_MAX_BATCH_RECORDS: usize = 100;
+/// Default maximum size of a batch, in bytes.
+pub(crate) const DEFAULT_MAX_BATCH_BYTES: usize = 1024 * 1024;
+/// Default maximum time a record waits for its batch to fill up.
+pub(crate) const DEFAULT_MAX_BATCH_DELAY: Duration = Duration::from_millis(500);
+/// Default time identical transport errors are held back once one was reported.
+pub(crate) const DEFAULT_ERROR_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Numbers the sender threads, named after it.
+static SENDER_IDS: AtomicUsize = AtomicUsize::new(0);
+
+/// Numbers the logging threads, for their records to stick to a connection.
+static THREAD_SLOTS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static THREAD_SLOT: usize = THREAD_SLOTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The senders alive, restarted by [`post_fork`](fn.post_fork.html).
+static SENDERS: Mutex<Vec<Weak<Shared>>> = Mutex::new(Vec::new());
+
+/// Most records held by the priority queue. Records past that wait in the ordinary queue.
+const MAX_PRIORITY_RECORDS: usize = 1000;
+
+const MIN_RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// What to do with a record which would push the appender past its memory budget.
+#[derive(serde_derive::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Discard the incoming record.
+    #[default]
+    DropNewest,
+    /// Evict the oldest queued records until the incoming one fits.
+    DropOldest,
+    /// Block the logging thread until the sender frees enough memory.
+    Block,
+}
+
+/// Which records a pool of connections sends in the order they were logged, at the cost of
+/// throughput.
+#[derive(serde_derive::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderingPolicy {
+    /// The records of each logging thread: they all go through the same connection.
+    PerThread,
+    /// All the records: a single batch is written at a time, whichever the connection.
+    Global,
+    /// None: the connections write batches in parallel. Priority records also skip ahead.
+    #[default]
+    None,
+}
+
+/// How long the logging thread waits for its records.
+#[derive(serde_derive::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Delivery {
+    /// Return once the record is queued, the background thread sending it later on.
+    #[default]
+    Buffered,
+    /// Block until the record is written to the transport, and fail if it's dropped instead. For
+    /// low-volume audit or security events which must not be lost in the buffer.
+    Guaranteed,
+}
+
+/// When the background sender writes queued records: as soon as any limit is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BatchPolicy {
+    pub(crate) max_records: usize,
+    pub(crate) max_bytes: usize,
+    pub(crate) max_delay: Duration,
+}
+
+impl Default for BatchPolicy {
+    fn default() -> BatchPolicy {
+        BatchPolicy {
+            max_records: DEFAULT_MAX_BATCH_RECORDS,
+            max_bytes: DEFAULT_MAX_BATCH_BYTES,
+            max_delay: DEFAULT_MAX_BATCH_DELAY,
+        }
+    }
+}
+
+/// Settings of the background sender.
+#[derive(Debug)]
+pub(crate) struct SenderConfig {
+    pub(crate) batch: BatchPolicy,
+    pub(crate) max_memory_bytes: usize,
+    /// Most records waiting to be sent, the batch being written aside.
+    pub(crate) queue_capacity: Option<usize>,
+    pub(crate) overflow_policy: OverflowPolicy,
+    pub(crate) hooks: ConnectionHooks,
+    /// How long a record may wait to be sent before being dropped.
+    pub(crate) record_ttl: Option<Duration>,
+    /// Records this severe skip ahead of the others.
+    pub(crate) priority_queue_level: LevelFilter,
+    /// How long identical transport errors are held back once one was reported.
+    pub(crate) error_report_interval: Duration,
+    /// Receives a copy of every payload taken off the queue.
+    pub(crate) capture: Option<Capture>,
+    /// Writes the records from the logging threads instead of a background thread.
+    pub(crate) synchronous: bool,
+    /// Opens the transport again in the child of a fork, unless it was given by the application.
+    /// Also opens the other connections of the pool.
+    pub(crate) reopen: Option<TransportConfig>,
+    /// Connections written to in parallel, each by a thread of its own.
+    pub(crate) connections: usize,
+    pub(crate) ordering: OrderingPolicy,
+}
+
+/// A framed payload waiting to be sent, along with what's needed to account for it if dropped.
+pub(crate) struct Entry {
+    /// The JSON of the message, empty until serialized if `message` is set.
+    pub(crate) payload: Vec<u8>,
+    /// The message, serialized by the worker taking it off the queue.
+    message: Option<Box<OwnedMessage>>,
+    /// Bytes accounted for the entry: the length of the payload, or an estimate of it until it's
+    /// serialized.
+    size: usize,
+    pub(crate) level: Level,
+    pub(crate) target: String,
+    queued_at: Instant,
+    /// Assigned when queued.
+    seq: u64,
+    /// Connection sending the entry, when records are ordered per thread.
+    partition: usize,
+}
+
+impl Entry {
+    pub(crate) fn new(payload: Vec<u8>, level: Level, target: &str) -> Entry {
+        let size = payload.len();
+        Entry { payload, message: None, size, level, target: target.to_string(), queued_at: Instant::now(), seq: 0, partition: 0 }
+    }
+
+    /// An entry whose message is serialized by the worker sending it, off the logging thread.
+    pub(crate) fn deferred(message: OwnedMessage, level: Level, target: &str) -> Entry {
+        let size = message.estimated_len();
+        Entry {
+            payload: Vec::new(),
+            message: Some(Box::new(message)),
+            size,
+            level,
+            target: target.to_string(),
+            queued_at: Instant::now(),
+            seq: 0,
+            partition: 0,
+        }
+    }
+}
+
+/// A serialized message taken off the queue by
+/// [`BufferAppender::drain_pending`](struct.BufferAppender.html#method.drain_pending).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingMessage {
+    /// The GELF message, as JSON.
+    pub payload: Vec<u8>,
+    /// Level of the record.
+    pub level: Level,
+    /// Target of the record.
+    pub target: String,
+}
+
+struct State {
+    queue: VecDeque<Entry>,
+    /// Records sent before those of `queue`.
+    priority: VecDeque<Entry>,
+    /// Bytes held by both queues.
+    queued_bytes: usize,
+    /// Bytes held by the queue plus the batch currently being written.
+    used_bytes: usize,
+    /// Set until the queue is empty once a flush was requested.
+    flush: bool,
+    /// Batches being written.
+    writing: usize,
+    flushes: FlushTracker,
+    shutdown: bool,
+}
+
+impl State {
+    fn len(&self) -> usize {
+        self.queue.len() + self.priority.len()
+    }
+
+    /// The next record to send, taking priority records first.
+    fn front(&self) -> Option<&Entry> {
+        self.priority.front().or_else(|| self.queue.front())
+    }
+
+    fn pop_front(&mut self) -> Option<Entry> {
+        let entry = self.priority.pop_front().or_else(|| self.queue.pop_front())?;
+        self.queued_bytes -= entry.size;
+        Some(entry)
+    }
+
+    /// The record queued first, whichever its queue.
+    fn oldest(&self) -> Option<&Entry> {
+        match (self.queue.front(), self.priority.front()) {
+            (Some(entry), Some(priority)) if priority.seq < entry.seq => Some(priority),
+            (Some(entry), _) => Some(entry),
+            (None, priority) => priority,
+        }
+    }
+
+    fn pop_oldest(&mut self) -> Option<Entry> {
+        let priority_first = match (self.queue.front(), self.priority.front()) {
+            (Some(entry), Some(priority)) => priority.seq < entry.seq,
+            (entry, _) => entry.is_none(),
+        };
+        let entry = if priority_first { self.priority.pop_front() } else { self.queue.pop_front() }?;
+        self.queued_bytes -= entry.size;
+        Some(entry)
+    }
+
+    /// The oldest record of a connection, when records are ordered per thread. They are all in
+    /// the ordinary queue.
+    fn position_of(&self, partition: usize) -> Option<usize> {
+        self.queue.iter().position(|entry| entry.partition == partition)
+    }
+
+    fn remove(&mut self, index: usize) -> Option<Entry> {
+        let entry = self.queue.remove(index)?;
+        self.queued_bytes -= entry.size;
+        Some(entry)
+    }
+
+    /// The record to evict for a newer one, taking ordinary records first.
+    fn pop_evictable(&mut self) -> Option<Entry> {
+        let entry = self.queue.pop_front().or_else(|| self.priority.pop_front())?;
+        self.queued_bytes -= entry.size;
+        Some(entry)
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled when records are queued or on shutdown.
+    work: Condvar,
+    /// Signalled when the worker releases memory.
+    space: Condvar,
+    stats: Arc<StatsCollector>,
+    /// Set while the remote server can't be reached.
+    link_down: AtomicBool,
+    max_memory_bytes: usize,
+    queue_capacity: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    priority_queue_level: LevelFilter,
+    /// The background threads, one per connection, replaced in the child of a fork.
+    workers: Mutex<Vec<background::Thread>>,
+    /// The worker run by the logging threads in synchronous mode, taken out when the sender is
+    /// dropped.
+    inline: Mutex<Option<Worker>>,
+    synchronous: bool,
+    ordering: OrderingPolicy,
+    settings: WorkerSettings,
+}
+
+/// Settings of the worker, kept to start another one in the child of a fork.
+struct WorkerSettings {
+    reopen: Option<TransportConfig>,
+    connections: usize,
+    batch: BatchPolicy,
+    hooks: ConnectionHooks,
+    record_ttl: Option<Duration>,
+    error_report_interval: Duration,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Done with a batch being written, which was sent or dropped.
+    fn release(&self, bytes: usize, seq: Option<u64>) {
+        let mut state = self.lock();
+        state.used_bytes -= bytes;
+        if let Some(seq) = seq {
+            state.flushes.end_batch(seq);
+            state.writing -= 1;
+        }
+        self.space.notify_all();
+        if self.ordering == OrderingPolicy::Global {
+            // Another connection may be waiting to write the next batch.
+            self.work.notify_one();
+        }
+    }
+
+    /// Charges entries serialized by a worker their `actual` length, rather than the `estimated`
+    /// one they were queued with. Records queued meanwhile may have gone past the memory budget
+    /// when the estimate was short, the next ones wait or are dropped until it's met again.
+    fn recharge(&self, estimated: usize, actual: usize) {
+        if estimated == actual {
+            return;
+        }
+        let mut state = self.lock();
+        state.used_bytes = state.used_bytes + actual - estimated;
+        if actual < estimated {
+            self.space.notify_all();
+        }
+    }
+
+    /// Queues an entry, applying the overflow policy when the memory budget is exhausted.
+    ///
+    /// Returns `false` if the entry was dropped.
+    fn send(&self, entry: Entry) -> bool {
+        self.queue(entry).is_some()
+    }
+
+    /// Same as [`send`](#method.send), then waits up to `timeout` for the entry to be written to
+    /// the transport. Returns `false` if it was dropped, or the timeout elapsed first.
+    fn send_wait(&self, entry: Entry, timeout: Option<Duration>) -> bool {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let (mut state, seq) = match self.queue(entry) {
+            Some(queued) => queued,
+            None => return false,
+        };
+        state.flush = true;
+        let id = state.flushes.register_record(seq);
+        loop {
+            let queue_front = state.oldest().map(|entry| entry.seq);
+            if let Some(dropped) = state.flushes.finish(id, queue_front) {
+                return dropped == 0;
+            }
+            // The worker signals `space` whenever records are done with.
+            state = match deadline {
+                None => self.space.wait(state).unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(left) if left > Duration::from_secs(0) => {
+                        self.space.wait_timeout(state, left).unwrap_or_else(PoisonError::into_inner).0
+                    }
+                    _ => {
+                        state.flushes.cancel(id);
+                        return false;
+                    }
+                },
+            };
+        }
+    }
+
+    /// Starts a worker of its own in the child of a fork, with a new connection. The one
+    /// inherited from the parent is left alone, for the parent to keep using it, and so is the
+    /// batch it was writing.
+    fn restart(self: &Arc<Shared>) {
+        let reopen = match &self.settings.reopen {
+            Some(reopen) => reopen.clone(),
+            None => {
+                let message = "GELF transport given by the application can't be reopened after fork(): records are kept queued";
+                self.settings.hooks.diagnostics.report(Level::Warn, message);
+                return;
+            }
+        };
+        {
+            let mut state = self.lock();
+            let queued_bytes = state.queued_bytes;
+            state.used_bytes = queued_bytes;
+            state.flushes.abandon_batches();
+            state.writing = 0;
+        }
+        let transport = new_transport(reopen.clone(), self.stats.clone());
+        if self.synchronous {
+            let mut inline = self.inline.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(inherited) = inline.replace(Worker::new(self.clone(), 0, transport, None)) {
+                // Closing the connection could end the session of the parent, e.g. with a TLS
+                // close_notify.
+                mem::forget(inherited.transport);
+            }
+            return;
+        }
+        let mut transports = vec![transport];
+        transports.extend((1..self.settings.connections).map(|_| new_transport(reopen.clone(), self.stats.clone())));
+        let mut workers = self.workers.lock().unwrap_or_else(PoisonError::into_inner);
+        // The threads of the parent don't exist here, they can neither be joined nor detached.
+        mem::forget(mem::take(&mut *workers));
+        for (index, transport) in transports.into_iter().enumerate() {
+            match spawn_worker(Worker::new(self.clone(), index, transport, None)) {
+                Ok(handle) => workers.push(handle),
+                Err(err) => {
+                    let message = format!("failed to restart the GELF sender after fork(): {}", err);
+                    self.settings.hooks.diagnostics.report(Level::Error, &message);
+                }
+            }
+        }
+    }
+
+    /// Queues an entry, returning the state still locked and the sequence number of the entry,
+    /// or `None` if it was dropped.
+    fn queue(&self, mut entry: Entry) -> Option<(MutexGuard<'_, State>, u64)> {
+        let size = entry.size;
+        if entry.message.is_none() {
+            // Deferred messages are measured once serialized.
+            self.stats.record_size(size);
+        }
+        if size > self.max_memory_bytes {
+            self.stats.record_dropped(entry.level, &entry.target);
+            return None;
+        }
+
+        let mut state = self.lock();
+        while state.used_bytes + size > self.max_memory_bytes
+            || self.queue_capacity.is_some_and(|capacity| state.len() >= capacity)
+        {
+            match self.overflow_policy {
+                OverflowPolicy::DropNewest => {
+                    self.stats.record_dropped(entry.level, &entry.target);
+                    return None;
+                }
+                OverflowPolicy::DropOldest => match state.pop_evictable() {
+                    Some(oldest) => {
+                        state.used_bytes -= oldest.size;
+                        state.flushes.dropped(oldest.seq);
+                        self.stats.record_dropped(oldest.level, &oldest.target);
+                        // Guaranteed deliveries may be waiting for the evicted record.
+                        self.space.notify_all();
+                    }
+                    // Everything left is the batch being written, it can't be evicted.
+                    None => {
+                        self.stats.record_dropped(entry.level, &entry.target);
+                        return None;
+                    }
+                },
+                OverflowPolicy::Block => {
+                    if state.shutdown {
+                        self.stats.record_dropped(entry.level, &entry.target);
+                        return None;
+                    }
+                    state = self.space.wait(state).unwrap_or_else(PoisonError::into_inner);
+                }
+            }
+        }
+        let seq = state.flushes.assign();
+        entry.seq = seq;
+        if self.ordering == OrderingPolicy::PerThread {
+            entry.partition = THREAD_SLOT.with(|slot| *slot) % self.settings.connections;
+        }
+        state.used_bytes += size;
+        state.queued_bytes += size;
+        if self.ordering == OrderingPolicy::None
+            && entry.level <= self.priority_queue_level
+            && state.priority.len() < MAX_PRIORITY_RECORDS
+        {
+            state.priority.push_back(entry);
+        } else {
+            state.queue.push_back(entry);
+        }
+        if self.ordering == OrderingPolicy::PerThread {
+            // Only the connection of this thread takes the entry.
+            self.work.notify_all();
+        } else {
+            self.work.notify_one();
+        }
+        Some((state, seq))
+    }
+}
+
+/// Handle on the background thread which ships serialized records to the remote server.
+pub(crate) struct Sender {
+    shared: Arc<Shared>,
+}
+
+/// Restarts the GELF senders in the child of a `fork()`, which inherits neither their threads nor
+/// exclusive use of their connections. Writing to the connections of the parent would interleave
+/// the frames of both processes.
+///
+/// Call it in the child, right after forking and before logging: each sender then opens a
+/// connection of its own, the records queued before the fork being sent by both processes.
+/// Senders of appenders built with
+/// [`BufferAppender::with_transport`](struct.BufferAppender.html#method.with_transport) can't be
+/// restarted. Forking while a sender holds its lock, which it only does briefly, leaves it
+/// locked in the child; the threads of `stats_interval` and `fold_window` aren't restarted, nor
+/// is `capture_to_file`. The child gets a `correlation_id` of its own.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// #[macro_use]
+/// extern crate log;
+///
+/// fn main() {
+///     // In the child, once fork() returned 0, e.g. when daemonizing:
+///     log4rs_gelf::post_fork();
+///     info!("daemon started");
+/// }
+/// ```
+pub fn post_fork() {
+    correlation::renew_process_id();
+    let senders: Vec<Arc<Shared>> = SENDERS.lock().unwrap_or_else(PoisonError::into_inner).iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+    for shared in senders {
+        shared.restart();
+    }
+}
+
+/// Queues records for a [`Sender`](struct.Sender.html) from another thread. Records sent after
+/// the sender is dropped are dropped too.
+#[derive(Clone)]
+pub(crate) struct SenderHandle {
+    shared: Arc<Shared>,
+}
+
+impl SenderHandle {
+    /// Same as [`Sender::send`](struct.Sender.html#method.send).
+    pub(crate) fn send(&self, entry: Entry) -> bool {
+        self.shared.send(entry)
+    }
+
+    /// Whether both handles queue for the same sender.
+    pub(crate) fn is_same(&self, other: &SenderHandle) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+
+    /// Number of records waiting to be sent, the batch being written aside.
+    pub(crate) fn queue_depth(&self) -> usize {
+        self.shared.lock().len()
+    }
+
+    /// Takes up to `max` queued records off the queue, oldest first. The batch being written is
+    /// left to the background thread.
+    pub(crate) fn drain(&self, max: usize) -> Vec<PendingMessage> {
+        let mut drained = Vec::new();
+        let mut state = self.shared.lock();
+        while drained.len() < max {
+            let entry = match state.pop_oldest() {
+                Some(entry) => entry,
+                None => break,
+            };
+            state.used_bytes -= entry.size;
+            // Flushes waiting for these records will never see them sent.
+            state.flushes.dropped(entry.seq);
+            let payload = match entry.message.map(|message| message.to_json()) {
+                None => entry.payload,
+                Some(Ok(payload)) => payload,
+                Some(Err(_)) => {
+                    self.shared.stats.record_dropped(entry.level, &entry.target);
+                    continue;
+                }
+            };
+            drained.push(PendingMessage { payload, level: entry.level, target: entry.target });
+        }
+        self.shared.space.notify_all();
+        drained
+    }
+
+    /// Wakes the background thread up so it ships whatever is queued without waiting for the
+    /// batch to fill up.
+    pub(crate) fn flush(&self) {
+        self.shared.lock().flush = true;
+        self.shared.work.notify_all();
+    }
+
+    /// Same as [`flush`](#method.flush), then waits up to `timeout` for the records queued so far
+    /// to be sent or dropped. Returns `false` if the timeout elapsed first.
+    pub(crate) fn flush_wait(&self, timeout: Duration) -> bool {
+        let mut state = self.shared.lock();
+        state.flush = true;
+        let target = state.flushes.next_seq();
+        self.shared.work.notify_all();
+        // The worker signals `space` whenever records are done with.
+        let (_state, result) = self.shared.space
+            .wait_timeout_while(state, timeout, |state| {
+                let queue_front = state.oldest().map(|entry| entry.seq);
+                state.flushes.oldest_pending(queue_front) < target
+            })
+            .unwrap_or_else(PoisonError::into_inner);
+        !result.timed_out()
+    }
+
+    /// Same as [`flush`](#method.flush), the future resolving once the records queued so far
+    /// were sent or dropped.
+    #[cfg(feature = "async")]
+    pub(crate) fn flush_async(&self) -> Flush {
+        let id = {
+            let mut state = self.shared.lock();
+            state.flush = true;
+            state.flushes.register()
+        };
+        self.shared.work.notify_all();
+        Flush { shared: self.shared.clone(), id }
+    }
+}
+
+impl Sender {
+    /// Starts the background thread. No connection is opened until the first record is sent.
+    pub(crate) fn spawn(config: SenderConfig, transport: Box<dyn GelfTransport>, stats: Arc<StatsCollector>) -> Result<Sender, Error> {
+        let settings = WorkerSettings {
+            reopen: config.reopen,
+            connections: config.connections,
+            batch: BatchPolicy {
+                max_records: cmp::max(config.batch.max_records, 1),
+                ..config.batch
+            },
+            hooks: config.hooks,
+            record_ttl: config.record_ttl,
+            error_report_interval: config.error_report_interval,
+        };
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                priority: VecDeque::new(),
+                queued_bytes: 0,
+                used_bytes: 0,
+                flush: false,
+                writing: 0,
+                flushes: FlushTracker::default(),
+                shutdown: false,
+            }),
+            work: Condvar::new(),
+            space: Condvar::new(),
+            stats,
+            link_down: AtomicBool::new(false),
+            max_memory_bytes: config.max_memory_bytes,
+            queue_capacity: config.queue_capacity,
+            overflow_policy: config.overflow_policy,
+            priority_queue_level: config.priority_queue_level,
+            workers: Mutex::new(Vec::new()),
+            inline: Mutex::new(None),
+            synchronous: config.synchronous,
+            ordering: config.ordering,
+            settings,
+        });
+        let worker = Worker::new(shared.clone(), 0, transport, config.capture);
+        if config.synchronous {
+            *shared.inline.lock().unwrap_or_else(PoisonError::into_inner) = Some(worker);
+        } else {
+            let mut workers = shared.workers.lock().unwrap_or_else(PoisonError::into_inner);
+            workers.push(spawn_worker(worker)?);
+            if let Some(reopen) = &shared.settings.reopen {
+                for index in 1..shared.settings.connections {
+                    let transport = new_transport(reopen.clone(), shared.stats.clone());
+                    workers.push(spawn_worker(Worker::new(shared.clone(), index, transport, None))?);
+                }
+            }
+        }
+
+        let mut senders = SENDERS.lock().unwrap_or_else(PoisonError::into_inner);
+        senders.retain(|sender| sender.strong_count() > 0);
+        senders.push(Arc::downgrade(&shared));
+        Ok(Sender { shared })
+    }
+
+    /// Writes what's queued, in synchronous mode.
+    fn run_inline(&self) {
+        if self.shared.synchronous {
+            if let Some(worker) = self.shared.inline.lock().unwrap_or_else(PoisonError::into_inner).as_mut() {
+                worker.run_inline();
+            }
+        }
+    }
+
+    /// Queues an entry, applying the overflow policy when the memory budget is exhausted. In
+    /// synchronous mode, the queue is then written out before returning.
+    ///
+    /// Returns `false` if the entry was dropped.
+    pub(crate) fn send(&self, entry: Entry) -> bool {
+        let queued = self.shared.send(entry);
+        self.run_inline();
+        queued
+    }
+
+    /// Same as [`send`](#method.send), then waits up to `timeout` for the entry to be written to
+    /// the transport. Returns `false` if it was dropped, or the timeout elapsed first. In
+    /// synchronous mode, the entry is written before returning and the timeout is ignored.
+    pub(crate) fn send_wait(&self, entry: Entry, timeout: Option<Duration>) -> bool {
+        if !self.shared.synchronous {
+            return self.shared.send_wait(entry, timeout);
+        }
+        let id = match self.shared.queue(entry) {
+            Some((mut state, seq)) => state.flushes.register_record(seq),
+            None => return false,
+        };
+        self.run_inline();
+        let mut state = self.shared.lock();
+        let queue_front = state.oldest().map(|entry| entry.seq);
+        match state.flushes.finish(id, queue_front) {
+            Some(dropped) => dropped == 0,
+            None => {
+                state.flushes.cancel(id);
+                false
+            }
+        }
+    }
+
+    pub(crate) fn handle(&self) -> SenderHandle {
+        SenderHandle { shared: self.shared.clone() }
+    }
+
+    /// Same as [`SenderHandle::drain`](struct.SenderHandle.html#method.drain).
+    pub(crate) fn drain(&self, max: usize) -> Vec<PendingMessage> {
+        self.handle().drain(max)
+    }
+
+    /// Whether the last attempt to reach the remote server failed.
+    pub(crate) fn is_link_down(&self) -> bool {
+        self.shared.link_down.load(Ordering::Relaxed)
+    }
+
+    /// Same as [`SenderHandle::flush`](struct.SenderHandle.html#method.flush).
+    pub(crate) fn flush(&self) {
+        self.handle().flush()
+    }
+
+    /// Same as [`SenderHandle::flush_wait`](struct.SenderHandle.html#method.flush_wait).
+    pub(crate) fn flush_wait(&self, timeout: Duration) -> bool {
+        self.handle().flush_wait(timeout)
+    }
+
+    /// Same as [`SenderHandle::flush_async`](struct.SenderHandle.html#method.flush_async).
+    #[cfg(feature = "async")]
+    pub(crate) fn flush_async(&self) -> Flush {
+        self.handle().flush_async()
+    }
+}
+
+/// Future returned by [`SenderHandle::flush_async`](struct.SenderHandle.html#method.flush_async).
+#[cfg(feature = "async")]
+pub(crate) struct Flush {
+    shared: Arc<Shared>,
+    id: u64,
+}
+
+#[cfg(feature = "async")]
+impl Future for Flush {
+    type Output = Result<(), FlushError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.shared.lock();
+        let queue_front = state.oldest().map(|entry| entry.seq);
+        match state.flushes.poll(self.id, queue_front, cx.waker()) {
+            Poll::Ready(0) => Poll::Ready(Ok(())),
+            Poll::Ready(dropped) => Poll::Ready(Err(FlushError::new(dropped))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for Flush {
+    fn drop(&mut self) {
+        self.shared.lock().flushes.cancel(self.id);
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.work.notify_all();
+        self.shared.space.notify_all();
+        // The inline worker holds on to the shared state too.
+        drop(self.shared.inline.lock().unwrap_or_else(PoisonError::into_inner).take());
+        let workers = mem::take(&mut *self.shared.workers.lock().unwrap_or_else(PoisonError::into_inner));
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        for worker in workers {
+            background::join_until(worker, deadline);
+        }
+    }
+}
+
+/// Starts a background thread running `worker`.
+fn spawn_worker(worker: Worker) -> io::Result<background::Thread> {
+    background::spawn_thread(format!("gelf-sender-{}", SENDER_IDS.fetch_add(1, Ordering::Relaxed)), move || worker.run())
+}
+
+struct Worker {
+    shared: Arc<Shared>,
+    transport: Box<dyn GelfTransport>,
+    batch: BatchPolicy,
+    hooks: ConnectionHooks,
+    errors: ErrorThrottle,
+    record_ttl: Option<Duration>,
+    capture: Option<Capture>,
+    /// Whether a connection was ever established, telling reconnections apart.
+    has_connected: bool,
+    /// The batch being written, kept here so it can be accounted for if the loop panics.
+    in_flight: Vec<Entry>,
+    /// Bytes held by the batch being written, released once it's done with.
+    in_flight_bytes: usize,
+    /// Lowest sequence number of the batch being written.
+    in_flight_seq: Option<u64>,
+    /// The only records taken, when they are ordered per thread.
+    partition: Option<usize>,
+}
+
+impl Worker {
+    /// Creates the worker writing to the connection numbered `index` of the pool.
+    fn new(shared: Arc<Shared>, index: usize, transport: Box<dyn GelfTransport>, capture: Option<Capture>) -> Worker {
+        let settings = &shared.settings;
+        let ordered = shared.ordering == OrderingPolicy::PerThread && settings.connections > 1;
+        Worker {
+            partition: if ordered { Some(index) } else { None },
+            transport,
+            batch: settings.batch,
+            hooks: settings.hooks.clone(),
+            errors: ErrorThrottle::new(settings.error_report_interval),
+            record_ttl: settings.record_ttl,
+            capture,
+            has_connected: false,
+            in_flight: Vec::new(),
+            in_flight_bytes: 0,
+            in_flight_seq: None,
+            shared,
+        }
+    }
+
+    /// Runs the sender loop until shut down. A panic, e.g. in a connection callback, loses the
+    /// batch being written and restarts the loop.
+    fn run(mut self) {
+        if self.shared.settings.connections > 1 {
+            self.guarded(Worker::preconnect);
+        }
+        while !self.guarded(Worker::send_all) {}
+    }
+
+    /// Opens the connection ahead of the first batch, for the pool to be ready when records
+    /// come in.
+    fn preconnect(&mut self) {
+        if let Err(err) = self.connect() {
+            self.failed(err);
+        }
+    }
+
+    /// Writes what's queued from the calling thread, in synchronous mode. Each batch is tried
+    /// once, and dropped if that fails.
+    fn run_inline(&mut self) {
+        self.guarded(Worker::send_queued);
+    }
+
+    /// Runs `work`, recovering from a panic, e.g. in a connection callback, which loses the batch
+    /// being written. Returns `false` if it panicked.
+    fn guarded(&mut self, work: fn(&mut Worker)) -> bool {
+        let panic = match panic::catch_unwind(AssertUnwindSafe(|| work(self))) {
+            Ok(()) => return true,
+            Err(panic) => panic,
+        };
+        let message = format!("GELF sender panicked, restarting it: {}", panic_message(&*panic));
+        self.hooks.diagnostics.report(Level::Error, &message);
+        self.transport.disconnect();
+        let batch = mem::take(&mut self.in_flight);
+        self.discard(&batch);
+        self.release();
+        false
+    }
+
+    fn send_all(&mut self) {
+        while let Some(batch) = self.next_batch() {
+            self.start(batch);
+            self.send_batch();
+            self.in_flight.clear();
+            self.release();
+        }
+    }
+
+    fn send_queued(&mut self) {
+        loop {
+            let batch = {
+                let mut state = self.shared.lock();
+                self.expire_queue(&mut state);
+                self.take_batch(&mut state)
+            };
+            if batch.is_empty() {
+                return;
+            }
+            self.start(batch);
+            let started = Instant::now();
+            match self.write() {
+                Ok(()) => self.sent(started),
+                Err(err) => {
+                    self.failed(err);
+                    let batch = mem::take(&mut self.in_flight);
+                    self.discard(&batch);
+                }
+            }
+            self.in_flight.clear();
+            self.release();
+        }
+    }
+
+    /// Puts a batch in flight. It's accounted for before being serialized, for a panic to drop
+    /// and release all of it.
+    fn start(&mut self, batch: Vec<Entry>) {
+        self.in_flight_seq = batch.iter().map(|entry| entry.seq).min();
+        self.in_flight_bytes = batch.iter().map(|entry| entry.size).sum();
+        self.in_flight = batch;
+        self.serialize();
+        if let Some(capture) = &mut self.capture {
+            for entry in &self.in_flight {
+                capture.write(&entry.payload);
+            }
+            capture.flush();
+        }
+    }
+
+    /// Serializes the deferred messages in flight, dropping those which can't be, and charges
+    /// them their serialized length instead of their estimate. The bytes of the messages dropped
+    /// are released along with the batch.
+    fn serialize(&mut self) {
+        let mut failed = Vec::new();
+        let (mut estimated, mut actual) = (0, 0);
+        let mut index = 0;
+        while index < self.in_flight.len() {
+            let entry = &mut self.in_flight[index];
+            match entry.message.as_ref().map(|message| message.to_json()) {
+                None => index += 1,
+                Some(Ok(payload)) => {
+                    self.shared.stats.record_size(payload.len());
+                    estimated += entry.size;
+                    actual += payload.len();
+                    entry.size = payload.len();
+                    entry.payload = payload;
+                    entry.message = None;
+                    index += 1;
+                }
+                Some(Err(err)) => {
+                    self.hooks.diagnostics.report(Level::Error, &format!("failed to serialize GELF record: {}", err));
+                    failed.push(self.in_flight.remove(index));
+                }
+            }
+        }
+        if !failed.is_empty() {
+            self.discard(&failed);
+        }
+        self.shared.recharge(estimated, actual);
+        self.in_flight_bytes = self.in_flight_bytes + actual - estimated;
+    }
+
+    /// Done with the batch in flight.
+    fn release(&mut self) {
+        self.shared.release(mem::replace(&mut self.in_flight_bytes, 0), self.in_flight_seq.take());
+    }
+
+    /// Writes the batch in flight, retrying until it's sent, expired or the sender shuts down.
+    /// Messages the server rejects are dropped instead.
+    fn send_batch(&mut self) {
+        let mut delay = MIN_RETRY_DELAY;
+        loop {
+            let started = Instant::now();
+            match self.write() {
+                Ok(()) => {
+                    self.sent(started);
+                    return;
+                }
+                Err(err) if err.is_rejection() && self.transport.acknowledges() => {
+                    self.rejected(err);
+                    if self.in_flight.is_empty() {
+                        return;
+                    }
+                    continue;
+                }
+                Err(err) => self.failed(err),
+            }
+            let pause = self.transport.retry_after().map_or(delay, |retry_after| cmp::max(delay, retry_after));
+            if !self.wait_retry(pause) {
+                // Shutting down with the server unreachable: give up on what's left.
+                self.discard(&self.in_flight);
+                self.discard_queue();
+                return;
+            }
+            delay = cmp::min(delay * 2, MAX_RETRY_DELAY);
+            self.expire_in_flight();
+            if self.in_flight.is_empty() {
+                return;
+            }
+        }
+    }
+
+    /// Accounts for the batch in flight, written since `started`.
+    fn sent(&mut self, started: Instant) {
+        self.shared.stats.record_sent(self.in_flight.iter().map(|entry| entry.queued_at));
+        let bytes = self.in_flight.iter().map(|entry| entry.payload.len()).sum();
+        self.hooks.batch_sent(self.in_flight.len(), bytes, started.elapsed());
+    }
+
+    /// Disconnects after a failed write, and reports the error.
+    fn failed(&mut self, err: Error) {
+        self.shared.link_down.store(true, Ordering::Relaxed);
+        if self.transport.is_connected() {
+            self.transport.disconnect();
+            self.hooks.disconnected(&err);
+        }
+        self.report(err);
+    }
+
+    /// Drops the first message of the batch in flight, which the server rejected: the others
+    /// before it were delivered, and those after it are still to be sent.
+    fn rejected(&mut self, err: Error) {
+        // Its memory is released with the batch.
+        let rejected = self.in_flight.remove(0);
+        self.discard(&[rejected]);
+        self.report(err);
+    }
+
+    /// Hands the error over to the application or the diagnostics, unless identical ones are held
+    /// back, and records it.
+    fn report(&mut self, err: Error) {
+        let err = Arc::new(err);
+        if let Some(held_back) = self.errors.admit(&err) {
+            if !self.hooks.error(&err) {
+                let dropped = if err.is_rejection() { ", GELF record dropped" } else { "" };
+                let message = match held_back {
+                    0 => format!("{err:?}{dropped}"),
+                    _ => format!("{err:?}{dropped} ({held_back} identical errors held back since the last one reported)"),
+                };
+                self.hooks.diagnostics.report(Level::Error, &message);
+            }
+        }
+        self.shared.stats.record_error(err);
+    }
+
+    /// Waits for the batch policy to be met and takes the batch off the queue. Its memory stays
+    /// accounted for until released. Returns `None` once shut down and drained.
+    fn next_batch(&self) -> Option<Vec<Entry>> {
+        let mut state = self.shared.lock();
+        loop {
+            self.expire_queue(&mut state);
+            let oldest = match self.partition {
+                Some(partition) => state.position_of(partition).and_then(|index| state.queue.get(index)),
+                None => state.oldest(),
+            };
+            let age = match oldest {
+                Some(_) if self.shared.ordering == OrderingPolicy::Global && state.writing > 0 => {
+                    // Released batches wake a waiting connection up.
+                    state = self.shared.work.wait(state).unwrap_or_else(PoisonError::into_inner);
+                    continue;
+                }
+                Some(oldest) => oldest.queued_at.elapsed(),
+                None => {
+                    if state.len() == 0 {
+                        state.flush = false;
+                    }
+                    if state.shutdown {
+                        return None;
+                    }
+                    state = self.shared.work.wait(state).unwrap_or_else(PoisonError::into_inner);
+                    continue;
+                }
+            };
+            if state.shutdown
+                || state.flush
+                || !state.priority.is_empty()
+                || state.len() >= self.batch.max_records
+                || state.queued_bytes >= self.batch.max_bytes
+                || age >= self.batch.max_delay
+            {
+                break;
+            }
+            state = self.shared.work
+                .wait_timeout(state, self.batch.max_delay - age)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        Some(self.take_batch(&mut state))
+    }
+
+    /// Takes the next batch off the queue, empty if nothing is queued.
+    fn take_batch(&self, state: &mut State) -> Vec<Entry> {
+        // Always take at least one record, even if it's larger than a whole batch.
+        let mut bytes = 0;
+        let mut batch = Vec::new();
+        while batch.len() < self.batch.max_records {
+            let index = match self.partition {
+                Some(partition) => match state.position_of(partition) {
+                    Some(index) => Some(index),
+                    None => break,
+                },
+                None => None,
+            };
+            let next = match index {
+                Some(index) => state.queue.get(index),
+                None => state.front(),
+            };
+            match next {
+                Some(next) if batch.is_empty() || bytes + next.size <= self.batch.max_bytes => {
+                    bytes += next.size;
+                }
+                _ => break,
+            }
+            batch.extend(match index {
+                Some(index) => state.remove(index),
+                None => state.pop_front(),
+            });
+        }
+        if let Some(oldest) = batch.iter().map(|entry| entry.seq).min() {
+            state.flushes.start_batch(oldest);
+            state.writing += 1;
+            if self.shared.queue_capacity.is_some() {
+                // The batch left room in the queue, before its memory is released.
+                self.shared.space.notify_all();
+            }
+        }
+        batch
+    }
+
+    fn write(&mut self) -> Result<(), Error> {
+        self.connect()?;
+        for delivered in 0..self.in_flight.len() {
+            if let Err(err) = self.transport.send(&self.in_flight[delivered].payload) {
+                if delivered > 0 && self.transport.acknowledges() {
+                    // The server has these already: only the rest of the batch is retried.
+                    let sent = self.in_flight.drain(..delivered);
+                    self.shared.stats.record_sent(sent.map(|entry| entry.queued_at));
+                }
+                return Err(err);
+            }
+        }
+        self.transport.flush()
+    }
+
+    fn connect(&mut self) -> Result<(), Error> {
+        if !self.transport.is_connected() {
+            self.transport.connect()?;
+            self.shared.link_down.store(false, Ordering::Relaxed);
+            if self.has_connected {
+                self.shared.stats.record_reconnect();
+            }
+            self.hooks.connected(self.has_connected);
+            self.has_connected = true;
+        }
+        Ok(())
+    }
+
+    /// Sleeps before the next attempt. Returns `false` if the sender is shutting down.
+    fn wait_retry(&self, delay: Duration) -> bool {
+        let state = self.shared.lock();
+        if state.shutdown {
+            return false;
+        }
+        let (mut state, _) = self.shared.work
+            .wait_timeout_while(state, delay, |state| !state.shutdown)
+            .unwrap_or_else(PoisonError::into_inner);
+        // Records pile up while the server is unreachable.
+        self.expire_queue(&mut state);
+        !state.shutdown
+    }
+
+    fn is_expired(&self, entry: &Entry) -> bool {
+        self.record_ttl.is_some_and(|ttl| entry.queued_at.elapsed() >= ttl)
+    }
+
+    /// Drops the queued records older than the TTL. They are the oldest ones.
+    fn expire_queue(&self, state: &mut State) {
+        let mut expired = false;
+        while state.oldest().is_some_and(|oldest| self.is_expired(oldest)) {
+            if let Some(oldest) = state.pop_oldest() {
+                state.used_bytes -= oldest.size;
+                state.flushes.dropped(oldest.seq);
+                self.shared.stats.record_dropped(oldest.level, &oldest.target);
+                expired = true;
+            }
+        }
+        if expired {
+            self.shared.space.notify_all();
+        }
+    }
+
+    /// Drops the records of the batch in flight older than the TTL. Their memory is released
+    /// with the batch.
+    fn expire_in_flight(&mut self) {
+        if self.record_ttl.is_none() {
+            return;
+        }
+        let batch = mem::take(&mut self.in_flight);
+        let (expired, kept): (Vec<Entry>, Vec<Entry>) = batch.into_iter().partition(|entry| self.is_expired(entry));
+        self.in_flight = kept;
+        if !expired.is_empty() {
+            self.discard(&expired);
+        }
+    }
+
+    fn discard(&self, entries: &[Entry]) {
+        let mut state = self.shared.lock();
+        for entry in entries {
+            state.flushes.dropped(entry.seq);
+            self.shared.stats.record_dropped(entry.level, &entry.target);
+        }
+    }
+
+    fn discard_queue(&self) {
+        let dropped: Vec<Entry> = {
+            let mut state = self.shared.lock();
+            let queued_bytes = state.queued_bytes;
+            state.used_bytes -= queued_bytes;
+            state.queued_bytes = 0;
+            let mut dropped: Vec<Entry> = state.priority.drain(..).collect();
+            dropped.extend(state.queue.drain(..));
+            dropped
+        };
+        self.discard(&dropped);
+        self.shared.space.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// A transport failing while `down`, and keeping what it sent otherwise along with the
+    /// `connection` sending it.
+    #[derive(Debug, Clone, Default)]
+    struct Stub {
+        connected: bool,
+        connection: usize,
+        /// How long each write takes.
+        latency: Duration,
+        down: Arc<AtomicBool>,
+        /// Makes the next write panic.
+        panic: Arc<AtomicBool>,
+        attempts: Arc<AtomicUsize>,
+        sent: Arc<Mutex<Vec<(usize, String)>>>,
+    }
+
+    impl Stub {
+        fn down() -> Stub {
+            let stub = Stub::default();
+            stub.down.store(true, Ordering::SeqCst);
+            stub
+        }
+
+        fn up(&self) {
+            self.down.store(false, Ordering::SeqCst);
+        }
+
+        fn sent(&self) -> Vec<String> {
+            self.sent.lock().unwrap().iter().map(|(_, payload)| payload.clone()).collect()
+        }
+
+        fn sent_on(&self) -> Vec<(usize, String)> {
+            self.sent.lock().unwrap().clone()
+        }
+
+        /// The connection numbered `index` of a pool, sharing what's sent but failing on its own.
+        fn connection(&self, index: usize) -> Stub {
+            let down = Arc::new(AtomicBool::new(self.down.load(Ordering::SeqCst)));
+            Stub { connection: index, down, ..self.clone() }
+        }
+
+        /// Waits for `attempts` writes: the first batch is in flight from the first one.
+        fn wait_attempts(&self, attempts: usize) {
+            wait_until(|| self.attempts.load(Ordering::SeqCst) >= attempts);
+        }
+    }
+
+    impl GelfTransport for Stub {
+        fn is_connected(&self) -> bool {
+            self.connected
+        }
+        fn connect(&mut self) -> Result<(), Error> {
+            self.connected = true;
+            Ok(())
+        }
+        fn disconnect(&mut self) {
+            self.connected = false;
+        }
+        fn send(&mut self, payload: &[u8]) -> Result<(), Error> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            if self.panic.swap(false, Ordering::SeqCst) {
+                panic!("stub panicked");
+            }
+            thread::sleep(self.latency);
+            if self.down.load(Ordering::SeqCst) {
+                return Err(Error::Io(io::Error::new(io::ErrorKind::ConnectionRefused, "down")));
+            }
+            let payload = String::from_utf8(payload.to_vec()).unwrap().trim_end().to_string();
+            self.sent.lock().unwrap().push((self.connection, payload));
+            Ok(())
+        }
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    /// Batches of a single record, written at once, and transport errors kept quiet.
+    fn config() -> SenderConfig {
+        SenderConfig {
+            batch: BatchPolicy { max_records: 1, max_delay: Duration::from_millis(0), ..BatchPolicy::default() },
+            max_memory_bytes: DEFAULT_MAX_MEMORY_BYTES,
+            queue_capacity: None,
+            overflow_policy: OverflowPolicy::default(),
+            hooks: ConnectionHooks { on_error: Some(Arc::new(|_| {})), ..ConnectionHooks::default() },
+            record_ttl: None,
+            priority_queue_level: LevelFilter::Off,
+            error_report_interval: DEFAULT_ERROR_REPORT_INTERVAL,
+            capture: None,
+            synchronous: false,
+            reopen: None,
+            connections: 1,
+            ordering: OrderingPolicy::default(),
+        }
+    }
+
+    fn spawn(config: SenderConfig, stub: &Stub) -> (Sender, Arc<StatsCollector>) {
+        let stats = Arc::new(StatsCollector::new(10));
+        (Sender::spawn(config, Box::new(stub.clone()), stats.clone()).unwrap(), stats)
+    }
+
+    /// Sends through the connections of `stubs`, a worker each, as if they were reopened.
+    fn pool(config: SenderConfig, stubs: &[Stub]) -> (Sender, Arc<StatsCollector>) {
+        let (sender, stats) = spawn(SenderConfig { connections: stubs.len(), ..config }, &stubs[0]);
+        let mut workers = sender.shared.workers.lock().unwrap();
+        for (index, stub) in stubs.iter().enumerate().skip(1) {
+            workers.push(spawn_worker(Worker::new(sender.shared.clone(), index, Box::new(stub.clone()), None)).unwrap());
+        }
+        drop(workers);
+        (sender, stats)
+    }
+
+    /// An entry of 100 bytes.
+    fn entry(name: &str) -> Entry {
+        Entry::new(format!("{:<100}", name).into_bytes(), Level::Info, "tests")
+    }
+
+    fn wait_until<F: Fn() -> bool>(condition: F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn records_are_sent_in_order_once_the_server_is_back() {
+        let stub = Stub::down();
+        let (sender, stats) = spawn(config(), &stub);
+        for name in &["a", "b", "c"] {
+            assert!(sender.send(entry(name)));
+        }
+        // The first one, in flight, is tried again.
+        stub.wait_attempts(2);
+        stub.up();
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        assert_eq!(stub.sent(), vec!["a", "b", "c"]);
+        let stats = stats.snapshot();
+        assert_eq!((stats.sent, stats.dropped), (3, 0));
+        assert!(stats.reconnects >= 1);
+        assert!(stats.last_error.is_some());
+    }
+
+    /// Fills a budget of 300 bytes with `a`, in flight, then `b` and `c`, and sends `d`.
+    fn overflow(overflow_policy: OverflowPolicy) -> (Stub, Sender, Arc<StatsCollector>) {
+        let stub = Stub::down();
+        let (sender, stats) = spawn(SenderConfig { max_memory_bytes: 300, overflow_policy, ..config() }, &stub);
+        assert!(sender.send(entry("a")));
+        stub.wait_attempts(1);
+        assert!(sender.send(entry("b")));
+        assert!(sender.send(entry("c")));
+        (stub, sender, stats)
+    }
+
+    #[test]
+    fn drop_newest_discards_the_record_past_the_cap() {
+        let (stub, sender, stats) = overflow(OverflowPolicy::DropNewest);
+        assert!(!sender.send(entry("d")));
+        assert_eq!(sender.handle().queue_depth(), 2);
+        stub.up();
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        assert_eq!(stub.sent(), vec!["a", "b", "c"]);
+        assert_eq!(stats.snapshot().dropped, 1);
+        // The memory of the records sent is available again.
+        assert!(sender.send(entry("e")));
+        assert!(sender.send(entry("f")));
+        assert!(sender.send(entry("g")));
+    }
+
+    #[test]
+    fn drop_oldest_evicts_queued_records_but_not_the_batch_in_flight() {
+        let (stub, sender, stats) = overflow(OverflowPolicy::DropOldest);
+        assert!(sender.send(entry("d")));
+        stub.up();
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        assert_eq!(stub.sent(), vec!["a", "c", "d"]);
+        assert_eq!(stats.snapshot().dropped, 1);
+    }
+
+    #[test]
+    fn block_waits_for_the_sender_to_free_memory() {
+        let (stub, sender, stats) = overflow(OverflowPolicy::Block);
+        let handle = sender.handle();
+        let (sent, blocked) = mpsc::channel();
+        thread::spawn(move || sent.send(handle.send(entry("d"))).unwrap());
+        assert!(blocked.recv_timeout(Duration::from_millis(200)).is_err(), "send didn't block");
+        stub.up();
+        assert_eq!(blocked.recv_timeout(Duration::from_secs(5)), Ok(true));
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        assert_eq!(stub.sent(), vec!["a", "b", "c", "d"]);
+        assert_eq!(stats.snapshot().dropped, 0);
+    }
+
+    #[test]
+    fn records_larger_than_the_budget_are_dropped() {
+        for overflow_policy in &[OverflowPolicy::DropNewest, OverflowPolicy::DropOldest, OverflowPolicy::Block] {
+            let stub = Stub::default();
+            let (sender, stats) = spawn(SenderConfig { max_memory_bytes: 99, overflow_policy: *overflow_policy, ..config() }, &stub);
+            assert!(!sender.send(entry("a")));
+            assert_eq!(stats.snapshot().dropped, 1);
+        }
+    }
+
+    /// Batches of up to 10 records, held for a minute.
+    fn held() -> SenderConfig {
+        SenderConfig { batch: BatchPolicy { max_records: 10, max_delay: Duration::from_secs(60), ..BatchPolicy::default() }, ..config() }
+    }
+
+    #[test]
+    fn flush_sends_the_records_held_back() {
+        let stub = Stub::default();
+        let (sender, _) = spawn(held(), &stub);
+        assert!(sender.send(entry("a")));
+        sender.flush();
+        wait_until(|| stub.sent() == ["a"]);
+    }
+
+    #[test]
+    fn flush_wait_returns_once_the_records_are_sent() {
+        let stub = Stub::default();
+        let (sender, _) = spawn(held(), &stub);
+        assert!(sender.send(entry("a")));
+        assert!(sender.send(entry("b")));
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        assert_eq!(stub.sent(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn flush_wait_times_out_while_the_server_is_down() {
+        let stub = Stub::down();
+        let (sender, _) = spawn(held(), &stub);
+        assert!(sender.send(entry("a")));
+        let started = Instant::now();
+        assert!(!sender.flush_wait(Duration::from_millis(200)));
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(stub.sent().is_empty());
+        stub.up();
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        assert_eq!(stub.sent(), vec!["a"]);
+    }
+
+    #[test]
+    fn send_wait_returns_once_the_record_is_written() {
+        let stub = Stub::default();
+        let (sender, stats) = spawn(held(), &stub);
+        assert!(sender.send_wait(entry("a"), Some(Duration::from_secs(5))));
+        assert_eq!(stub.sent(), vec!["a"]);
+        assert_eq!(stats.snapshot().sent, 1);
+    }
+
+    #[test]
+    fn send_wait_fails_once_the_record_is_dropped_or_late() {
+        let stub = Stub::down();
+        let (sender, _) = spawn(SenderConfig { record_ttl: Some(Duration::from_millis(100)), ..config() }, &stub);
+        let started = Instant::now();
+        assert!(!sender.send_wait(entry("a"), Some(Duration::from_secs(5))));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let started = Instant::now();
+        assert!(!sender.send_wait(entry("b"), Some(Duration::from_millis(50))));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(stub.sent().is_empty());
+    }
+
+    #[test]
+    fn guaranteed_appends_fail_unless_delivered() {
+        use appender::BufferAppender;
+        use log::Record;
+        use log4rs::append::Append;
+
+        let stub = Stub::down();
+        let (errors, _received) = mpsc::channel();
+        let appender = BufferAppender::with_transport(Box::new(stub.clone()))
+            .set_delivery(Delivery::Guaranteed)
+            .set_flush_timeout(Some(Duration::from_millis(200)))
+            .set_error_channel(errors)
+            .build()
+            .unwrap();
+        let log = |message: &str| appender.append(&Record::builder().args(format_args!("{}", message)).level(Level::Info).build());
+        let err = log("lost").unwrap_err();
+        assert_eq!(err.to_string(), "GELF record was not delivered");
+        stub.up();
+        log("delivered").unwrap();
+        assert!(stub.sent().iter().any(|payload| payload.contains("\"delivered\"")));
+    }
+
+    #[test]
+    fn records_within_their_ttl_are_delivered() {
+        let stub = Stub::down();
+        let (sender, stats) = spawn(SenderConfig { record_ttl: Some(Duration::from_secs(5)), ..config() }, &stub);
+        assert!(sender.send(entry("a")));
+        assert!(sender.send(entry("b")));
+        stub.wait_attempts(2);
+        stub.up();
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        assert_eq!(stub.sent(), vec!["a", "b"]);
+        assert_eq!(stats.snapshot().dropped, 0);
+    }
+
+    #[test]
+    fn records_past_their_ttl_are_dropped_queued_or_in_flight() {
+        let stub = Stub::down();
+        let (sender, stats) = spawn(SenderConfig { record_ttl: Some(Duration::from_millis(100)), ..config() }, &stub);
+        assert!(sender.send(entry("a")));
+        stub.wait_attempts(1);
+        assert!(sender.send(entry("b")));
+        thread::sleep(Duration::from_millis(150));
+        assert!(sender.send(entry("c")));
+        stub.up();
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        assert_eq!(stub.sent(), vec!["c"]);
+        let stats = stats.snapshot();
+        assert_eq!(stats.dropped, 2);
+        assert_eq!(stats.dropped_by_target, vec![("tests".to_string(), 2)]);
+        // Their memory was released.
+        assert_eq!(sender.shared.lock().used_bytes, 0);
+    }
+
+    /// An error entry of 100 bytes.
+    fn error(name: &str) -> Entry {
+        Entry { level: Level::Error, ..entry(name) }
+    }
+
+    #[test]
+    fn priority_records_are_sent_ahead_of_the_queue() {
+        let stub = Stub::down();
+        let (sender, _) = spawn(SenderConfig { priority_queue_level: LevelFilter::Error, ..config() }, &stub);
+        assert!(sender.send(entry("a")));
+        stub.wait_attempts(1);
+        for entry in [entry("b"), entry("c"), error("d"), error("e")] {
+            assert!(sender.send(entry));
+        }
+        stub.up();
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        // The batch in flight goes first, then the priority records in their order.
+        assert_eq!(stub.sent(), vec!["a", "d", "e", "b", "c"]);
+    }
+
+    #[test]
+    fn priority_records_are_evicted_last() {
+        let stub = Stub::down();
+        let config = SenderConfig {
+            max_memory_bytes: 400,
+            overflow_policy: OverflowPolicy::DropOldest,
+            priority_queue_level: LevelFilter::Error,
+            ..config()
+        };
+        let (sender, stats) = spawn(config, &stub);
+        assert!(sender.send(entry("a")));
+        stub.wait_attempts(1);
+        for entry in [error("b"), entry("c"), error("d"), entry("e"), entry("f")] {
+            assert!(sender.send(entry));
+        }
+        stub.up();
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        assert_eq!(stub.sent(), vec!["a", "b", "d", "f"]);
+        let stats = stats.snapshot();
+        assert_eq!((stats.dropped, stats.dropped_by_level.get(&Level::Error)), (2, None));
+    }
+
+    #[cfg(feature = "tcp")]
+    #[test]
+    fn restarted_senders_send_the_queue_over_a_connection_of_their_own() {
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+        use transport::{Framing, Protocol};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let reopen = TransportConfig {
+            protocol: Protocol::Tcp,
+            hostname: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            #[cfg(feature = "tls")]
+            use_tls: false,
+            #[cfg(feature = "tls")]
+            pinned_cert_sha256: None,
+            #[cfg(feature = "tls")]
+            verify_ca: true,
+            #[cfg(feature = "tls")]
+            client_identity: None,
+            connect_timeout: None,
+            write_timeout: None,
+            framing: Framing::Newline,
+            http_path: String::new(),
+            http_authorization: None,
+            http_idle_timeout: None,
+            resolver: None,
+            compression_threshold: None,
+            server_clock: None,
+        };
+        let stub = Stub::down();
+        let (sender, _) = spawn(SenderConfig { reopen: Some(reopen), ..config() }, &stub);
+        assert!(sender.send(entry("a")));
+        stub.wait_attempts(1);
+        assert!(sender.send(entry("b")));
+
+        sender.shared.restart();
+        // The batch the parent was writing isn't waited for.
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        let mut line = String::new();
+        BufReader::new(listener.accept().unwrap().0).read_line(&mut line).unwrap();
+        assert_eq!(line.trim_end(), "b");
+        let state = sender.shared.lock();
+        assert_eq!((state.used_bytes, state.writing), (0, 0));
+        drop(state);
+        assert!(stub.sent().is_empty());
+        // The worker of the parent, which a fork leaves behind, still exists here: it's left
+        // retrying rather than releasing the batch the restart abandoned.
+        mem::forget(sender);
+    }
+
+    #[test]
+    fn senders_of_given_transports_keep_their_queue_after_fork() {
+        let stub = Stub::down();
+        let (sender, _) = spawn(config(), &stub);
+        assert!(sender.send(entry("a")));
+        stub.wait_attempts(1);
+        assert!(sender.send(entry("b")));
+
+        sender.shared.restart();
+        assert_eq!(sender.handle().queue_depth(), 1);
+        stub.up();
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        assert_eq!(stub.sent(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn batches_are_spread_over_the_pool() {
+        let stub = Stub { latency: Duration::from_millis(30), ..Stub::default() };
+        let (sender, _) = pool(config(), &[stub.connection(0), stub.connection(1), stub.connection(2)]);
+        for name in &["a", "b", "c", "d", "e", "f"] {
+            assert!(sender.send(entry(name)));
+        }
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        let mut sent = stub.sent();
+        sent.sort();
+        assert_eq!(sent, vec!["a", "b", "c", "d", "e", "f"]);
+        let mut connections: Vec<usize> = stub.sent_on().into_iter().map(|(connection, _)| connection).collect();
+        connections.dedup();
+        assert!(connections.len() > 1, "{:?}", stub.sent_on());
+    }
+
+    #[test]
+    fn a_failing_connection_doesnt_hold_up_the_pool() {
+        let stub = Stub::default();
+        let failing = stub.connection(1);
+        failing.down.store(true, Ordering::SeqCst);
+        let (sender, stats) = pool(config(), &[stub.connection(0), failing.clone()]);
+        let names = ["a", "b", "c", "d", "e", "f"];
+        for name in &names {
+            assert!(sender.send(entry(name)));
+            thread::sleep(Duration::from_millis(5));
+        }
+        // The failing connection holds at most the batch it took.
+        wait_until(|| stub.sent().len() >= names.len() - 1);
+        failing.up();
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        let mut sent = stub.sent();
+        sent.sort();
+        assert_eq!(sent, names);
+        assert_eq!(stats.snapshot().dropped, 0);
+    }
+
+    #[test]
+    fn global_ordering_writes_one_batch_at_a_time() {
+        let stub = Stub { latency: Duration::from_millis(10), ..Stub::default() };
+        let config = SenderConfig { ordering: OrderingPolicy::Global, ..config() };
+        let (sender, _) = pool(config, &[stub.connection(0), stub.connection(1), stub.connection(2)]);
+        let names: Vec<String> = (0..10).map(|index| index.to_string()).collect();
+        for name in &names {
+            assert!(sender.send(entry(name)));
+        }
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        assert_eq!(stub.sent(), names);
+    }
+
+    #[test]
+    fn per_thread_ordering_keeps_each_thread_on_a_connection() {
+        let stub = Stub { latency: Duration::from_millis(5), ..Stub::default() };
+        let config = SenderConfig { ordering: OrderingPolicy::PerThread, ..config() };
+        let (sender, _) = pool(config, &[stub.connection(0), stub.connection(1), stub.connection(2)]);
+        let threads: Vec<_> = (0..3)
+            .map(|thread| {
+                let handle = sender.handle();
+                thread::spawn(move || {
+                    for index in 0..5 {
+                        assert!(handle.send(entry(&format!("{}-{}", thread, index))));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        for thread in 0..3 {
+            let prefix = format!("{}-", thread);
+            let sent: Vec<(usize, String)> = stub.sent_on().into_iter().filter(|(_, name)| name.starts_with(&prefix)).collect();
+            assert_eq!(sent.iter().map(|(_, name)| name.clone()).collect::<Vec<_>>(), (0..5).map(|index| format!("{}{}", prefix, index)).collect::<Vec<_>>());
+            assert!(sent.iter().all(|(connection, _)| *connection == sent[0].0), "{:?}", sent);
+        }
+    }
+
+    #[test]
+    fn ordered_records_dont_skip_ahead() {
+        let stub = Stub::down();
+        let config = SenderConfig { ordering: OrderingPolicy::Global, priority_queue_level: LevelFilter::Error, ..config() };
+        let (sender, _) = spawn(config, &stub);
+        assert!(sender.send(entry("a")));
+        stub.wait_attempts(1);
+        assert!(sender.send(entry("b")));
+        assert!(sender.send(error("c")));
+        stub.up();
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        assert_eq!(stub.sent(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn a_panic_drops_and_releases_the_batch_in_flight() {
+        let stub = Stub::default();
+        stub.panic.store(true, Ordering::SeqCst);
+        let (sender, stats) = spawn(SenderConfig { max_memory_bytes: 300, ..config() }, &stub);
+        assert!(sender.send(entry("a")));
+        let started = Instant::now();
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(stats.snapshot().dropped, 1);
+        // The whole budget is available again, and the restarted worker sends the next records.
+        for name in &["b", "c", "d"] {
+            assert!(sender.send(entry(name)));
+        }
+        assert!(sender.flush_wait(Duration::from_secs(5)));
+        assert_eq!(stub.sent(), vec!["b", "c", "d"]);
+    }
+
+    #[test]
+    fn a_panicking_encoder_doesnt_hold_up_flush() {
+        use appender::BufferAppender;
+        use log::Record;
+        use log4rs::append::Append;
+        use log4rs::encode::{Encode, Write};
+
+        #[derive(Debug)]
+        struct Panicking;
+
+        impl Encode for Panicking {
+            fn encode(&self, _: &mut dyn Write, _: &Record) -> anyhow::Result<()> {
+                panic!("encoder panicked");
+            }
+        }
+
+        let stub = Stub::default();
+        let appender = BufferAppender::with_transport(Box::new(stub.clone()))
+            .set_encoder(Box::new(Panicking))
+            .set_flush_timeout(Some(Duration::from_secs(5)))
+            .build()
+            .unwrap();
+        let record = Record::builder().args(format_args!("a")).level(Level::Info).build();
+        assert!(appender.append(&record).is_err());
+        let started = Instant::now();
+        appender.flush();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(appender.stats().sent, 0);
+        assert!(stub.sent().is_empty());
+    }
+}
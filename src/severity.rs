@@ -0,0 +1,98 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use context::LEVEL_KEY;
+use log::{Level, Record};
+use serde_value::Value;
+use std::fmt;
+
+/// Logs a message at a GELF (syslog) severity, including those `log::Level` can't express such as
+/// `Emergency`, `Alert`, `Critical` and `Notice`.
+///
+/// The record goes through `log` at the closest `log::Level` and carries the exact severity in the
+/// reserved [`LEVEL_KEY`](constant.LEVEL_KEY.html) key-value, which the GELF appender uses for the
+/// `level` field.
+///
+/// ## Example
+///
+/// ```rust
+/// #[macro_use]
+/// extern crate log4rs_gelf;
+///
+/// use log4rs_gelf::GelfLevel;
+///
+/// fn main() {
+///     gelf_log!(GelfLevel::Alert, "disk {} is failing", "/dev/sda");
+///     gelf_log!(target: "storage", GelfLevel::Notice, "disk replaced");
+/// }
+/// ```
+#[macro_export]
+macro_rules! gelf_log {
+    (target: $target:expr, $level:expr, $($arg:tt)+) => {
+        $crate::__log_with_severity(
+            $level as u32, $target, module_path!(), file!(), line!(), format_args!($($arg)+)
+        )
+    };
+    ($level:expr, $($arg:tt)+) => {
+        $crate::__log_with_severity(
+            $level as u32, module_path!(), module_path!(), file!(), line!(), format_args!($($arg)+)
+        )
+    };
+}
+
+#[doc(hidden)]
+pub fn __log_with_severity(severity: u32, target: &str, module_path: &'static str, file: &'static str,
+                           line: u32, args: fmt::Arguments) {
+    let level = log_level(severity);
+    if level > log::max_level() {
+        return;
+    }
+    log::logger().log(&Record::builder()
+        .args(args)
+        .level(level)
+        .target(target)
+        .module_path_static(Some(module_path))
+        .file_static(Some(file))
+        .line(Some(line))
+        .key_values(&(LEVEL_KEY, severity))
+        .build());
+}
+
+/// Closest `log::Level` of a syslog severity.
+pub(crate) fn log_level(severity: u32) -> Level {
+    match severity {
+        0..=3 => Level::Error,
+        4 => Level::Warn,
+        5 | 6 => Level::Info,
+        _ => Level::Debug,
+    }
+}
+
+/// Reads a severity given as a syslog number or name.
+pub(crate) fn parse_severity(value: &Value) -> Option<u32> {
+    let severity = match value {
+        Value::U8(value) => u64::from(*value),
+        Value::U16(value) => u64::from(*value),
+        Value::U32(value) => u64::from(*value),
+        Value::U64(value) => *value,
+        Value::I64(value) if *value >= 0 => *value as u64,
+        Value::String(name) => return severity_from_name(name),
+        _ => return None,
+    };
+    if severity <= 7 { Some(severity as u32) } else { None }
+}
+
+fn severity_from_name(name: &str) -> Option<u32> {
+    match name.to_ascii_lowercase().as_str() {
+        "emergency" | "emerg" => Some(0),
+        "alert" => Some(1),
+        "critical" | "crit" => Some(2),
+        "error" | "err" => Some(3),
+        "warning" | "warn" => Some(4),
+        "notice" => Some(5),
+        "informational" | "info" => Some(6),
+        "debugging" | "debug" => Some(7),
+        _ => None,
+    }
+}
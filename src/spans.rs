@@ -0,0 +1,198 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+//! Span context of the records bridged from `tracing`.
+//!
+//! With its `log` feature, `tracing` logs the lifecycle of its spans as plain records: `++ name;
+//! field=value; span=1` when one is created, `-> name;; span=1` and `<- name;; span=1` when a
+//! thread enters and leaves it, and `-- name;; span=1` when it's closed. Following them tells in
+//! which spans a thread logs its events.
+
+use enrich::{Enricher, MessageFields};
+use log::Record;
+use serde_value::Value;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::sync::{Mutex, PoisonError};
+use std::thread::{self, ThreadId};
+
+/// Target of the records of `tracing` about the creation and the closing of spans, and of the
+/// creation of spans without fields.
+const LIFECYCLE_TARGET: &str = "tracing::span";
+
+/// Target of the records of `tracing` about the spans a thread enters and leaves.
+const ACTIVITY_TARGET: &str = "tracing::span::active";
+
+/// Most spans followed at once, in case their closing records are filtered out.
+const MAX_SPANS: usize = 10_000;
+
+/// Spans open, and those entered by each thread, innermost last.
+#[derive(Debug, Default)]
+pub(crate) struct SpanTracker {
+    state: Mutex<Spans>,
+}
+
+#[derive(Debug, Default)]
+struct Spans {
+    spans: HashMap<u64, Span>,
+    entered: HashMap<ThreadId, Vec<u64>>,
+}
+
+#[derive(Debug)]
+struct Span {
+    name: String,
+    fields: Vec<(String, Value)>,
+}
+
+impl SpanTracker {
+    /// Follows a record of the lifecycle of a span. Returns `false` if `record` isn't one, and is
+    /// to be sent.
+    ///
+    /// Spans with fields are created under their own target: their records are told apart by
+    /// their message, whose start is checked before it's formatted as a whole.
+    pub(crate) fn observe(&self, record: &Record) -> bool {
+        let lifecycle = record.target() == LIFECYCLE_TARGET || record.target() == ACTIVITY_TARGET;
+        if !lifecycle && !starts_with(record.args(), "++ ") {
+            return false;
+        }
+        let text = record.args().to_string();
+        let (event, id) = match text.rsplit_once("; span=").and_then(|(event, id)| Some((event, id.parse::<u64>().ok()?))) {
+            Some(parsed) => parsed,
+            // Spans disabled in `tracing` have no id.
+            None => return lifecycle,
+        };
+        let (name, fields) = match event.get(3..).and_then(|rest| rest.split_once(';')) {
+            Some((name, fields)) => (name, fields.trim()),
+            None => return lifecycle,
+        };
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let state = &mut *state;
+        match &event[..3] {
+            "++ " => {
+                if state.spans.len() < MAX_SPANS {
+                    state.spans.insert(id, Span { name: name.to_string(), fields: parse_fields(fields) });
+                }
+            }
+            "-> " => {
+                if state.spans.len() < MAX_SPANS {
+                    state.spans.entry(id).or_insert_with(|| Span { name: name.to_string(), fields: Vec::new() });
+                }
+                state.entered.entry(thread::current().id()).or_default().push(id);
+            }
+            "<- " => {
+                let thread = thread::current().id();
+                if let Some(entered) = state.entered.get_mut(&thread) {
+                    if let Some(position) = entered.iter().rposition(|entered| *entered == id) {
+                        entered.remove(position);
+                    }
+                    if entered.is_empty() {
+                        state.entered.remove(&thread);
+                    }
+                }
+            }
+            "-- " => {
+                state.spans.remove(&id);
+            }
+            _ => return lifecycle,
+        }
+        true
+    }
+}
+
+impl Enricher for SpanTracker {
+    /// Adds `_span_name` and `_span_id`, those of the innermost span entered by the logging thread,
+    /// and the fields of the spans entered as `_span_<field>`, the innermost span winning.
+    fn enrich(&self, _record: &Record, fields: &mut MessageFields) {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let entered = match state.entered.get(&thread::current().id()) {
+            Some(entered) => entered,
+            None => return,
+        };
+        if let Some(id) = entered.last() {
+            if let Some(span) = state.spans.get(id) {
+                fields.insert_if_absent("span_name", Value::String(span.name.clone()));
+            }
+            fields.insert_if_absent("span_id", Value::U64(*id));
+        }
+        for span in entered.iter().rev().filter_map(|id| state.spans.get(id)) {
+            for (key, value) in &span.fields {
+                fields.insert_if_absent(&format!("span_{}", key), value.clone());
+            }
+        }
+    }
+}
+
+/// Whether the formatted `args` start with `prefix`, formatting no more than needed.
+fn starts_with(args: &fmt::Arguments, prefix: &str) -> bool {
+    if let Some(text) = args.as_str() {
+        return text.starts_with(prefix);
+    }
+    struct Prefix<'p>(&'p str);
+    impl<'p> Write for Prefix<'p> {
+        fn write_str(&mut self, text: &str) -> fmt::Result {
+            let len = text.len().min(self.0.len());
+            if text.as_bytes()[..len] != self.0.as_bytes()[..len] {
+                return Err(fmt::Error);
+            }
+            self.0 = &self.0[len..];
+            // Stop formatting once the prefix matched.
+            if self.0.is_empty() {
+                return Err(fmt::Error);
+            }
+            Ok(())
+        }
+    }
+    let mut rest = Prefix(prefix);
+    let _ = rest.write_fmt(*args);
+    rest.0.is_empty()
+}
+
+/// Parses the fields of a span, `Debug`-formatted as `key=value`, separated by spaces.
+fn parse_fields(mut text: &str) -> Vec<(String, Value)> {
+    let mut fields = Vec::new();
+    while let Some((key, rest)) = text.trim_start().split_once('=') {
+        let (value, rest) = if let Some(quoted) = rest.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((index, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        end = index + 1;
+                        break;
+                    }
+                    '\\' => match chars.next().map(|(_, escaped)| escaped) {
+                        Some('n') => value.push('\n'),
+                        Some('r') => value.push('\r'),
+                        Some('t') => value.push('\t'),
+                        Some(escaped) => value.push(escaped),
+                        None => {}
+                    },
+                    c => value.push(c),
+                }
+            }
+            (Value::String(value), &quoted[end..])
+        } else {
+            let (value, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+            (parse_scalar(value), rest)
+        };
+        fields.push((key.to_string(), value));
+        text = rest;
+    }
+    fields
+}
+
+fn parse_scalar(value: &str) -> Value {
+    if let Ok(value) = value.parse::<u64>() {
+        Value::U64(value)
+    } else if let Ok(value) = value.parse::<i64>() {
+        Value::I64(value)
+    } else if let Ok(value) = value.parse::<f64>() {
+        Value::F64(value)
+    } else if let Ok(value) = value.parse::<bool>() {
+        Value::Bool(value)
+    } else {
+        Value::String(value.to_string())
+    }
+}
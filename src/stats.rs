@@ -0,0 +1,304 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use error::Error;
+use log::Level;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Default number of targets tracked in [`Stats::dropped_by_target`](struct.Stats.html).
+pub(crate) const DEFAULT_DROP_STATS_TARGETS: usize = 10;
+
+/// Snapshot of the appender counters, returned by
+/// [`BufferAppender::stats`](struct.BufferAppender.html#method.stats).
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    /// Records written to the remote server.
+    pub sent: u64,
+    /// Records shed, whatever the reason.
+    pub dropped: u64,
+    /// Dropped records, per level.
+    pub dropped_by_level: BTreeMap<Level, u64>,
+    /// Targets which lost the most records, most affected first.
+    ///
+    /// Only a bounded number of targets is tracked: once more distinct targets than that have
+    /// dropped records, the counts become upper-bound estimates.
+    pub dropped_by_target: Vec<(String, u64)>,
+    /// When the last attempt to write to the remote server failed, and why.
+    pub last_error: Option<(Instant, Arc<Error>)>,
+    /// When a batch was last written to the remote server.
+    pub last_successful_send: Option<Instant>,
+    /// Connections reestablished after a failure.
+    pub reconnects: u64,
+    /// Sizes of the serialized messages handed over to the sender.
+    pub message_sizes: MessageSizes,
+    /// Time from the logging call to the successful write of the records.
+    pub send_latency: SendLatency,
+    /// Messages compressed before being sent, once per attempt.
+    pub compressed: u64,
+    /// Bytes saved by compression.
+    pub compression_saved_bytes: u64,
+}
+
+/// Distribution of the serialized message sizes, in bytes.
+///
+/// Percentiles are upper bounds rounded up to a power of two, and never above `max`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageSizes {
+    /// Median size.
+    pub p50: usize,
+    /// 95th percentile.
+    pub p95: usize,
+    /// Largest size.
+    pub max: usize,
+}
+
+/// Distribution of the time records take to be written to the remote server.
+///
+/// Percentiles are upper bounds rounded up to a power of two microseconds, and never above `max`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SendLatency {
+    /// Median latency.
+    pub p50: Duration,
+    /// 99th percentile.
+    pub p99: Duration,
+    /// Longest latency.
+    pub max: Duration,
+}
+
+/// Errors are equal when they are the same occurrence.
+impl PartialEq for Stats {
+    fn eq(&self, other: &Stats) -> bool {
+        let same_error = match (&self.last_error, &other.last_error) {
+            (Some((at, err)), Some((other_at, other_err))) => at == other_at && Arc::ptr_eq(err, other_err),
+            (None, None) => true,
+            _ => false,
+        };
+        self.sent == other.sent
+            && self.dropped == other.dropped
+            && self.dropped_by_level == other.dropped_by_level
+            && self.dropped_by_target == other.dropped_by_target
+            && same_error
+            && self.last_successful_send == other.last_successful_send
+            && self.reconnects == other.reconnects
+            && self.message_sizes == other.message_sizes
+            && self.send_latency == other.send_latency
+            && self.compressed == other.compressed
+            && self.compression_saved_bytes == other.compression_saved_bytes
+    }
+}
+
+impl Eq for Stats {}
+
+/// Counters shared by the appender and its background sender.
+#[derive(Debug)]
+pub(crate) struct StatsCollector {
+    counters: Mutex<Counters>,
+}
+
+#[derive(Debug)]
+struct Counters {
+    sent: u64,
+    dropped: u64,
+    dropped_by_level: BTreeMap<Level, u64>,
+    dropped_by_target: TopTargets,
+    last_error: Option<(Instant, Arc<Error>)>,
+    last_successful_send: Option<Instant>,
+    reconnects: u64,
+    message_sizes: Histogram,
+    /// In microseconds.
+    send_latency: Histogram,
+    compressed: u64,
+    compression_saved_bytes: u64,
+}
+
+impl StatsCollector {
+    pub(crate) fn new(tracked_targets: usize) -> StatsCollector {
+        StatsCollector {
+            counters: Mutex::new(Counters {
+                sent: 0,
+                dropped: 0,
+                dropped_by_level: BTreeMap::new(),
+                dropped_by_target: TopTargets { capacity: tracked_targets, counts: Vec::new() },
+                last_error: None,
+                last_successful_send: None,
+                reconnects: 0,
+                message_sizes: Histogram::default(),
+                send_latency: Histogram::default(),
+                compressed: 0,
+                compression_saved_bytes: 0,
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Counters> {
+        self.counters.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Records written, given the time each of them was queued at.
+    pub(crate) fn record_sent<I: IntoIterator<Item = Instant>>(&self, queued_at: I) {
+        let now = Instant::now();
+        let mut counters = self.lock();
+        for queued_at in queued_at {
+            counters.sent += 1;
+            let latency = now.saturating_duration_since(queued_at);
+            counters.send_latency.record(u64::try_from(latency.as_micros()).unwrap_or(u64::MAX));
+        }
+        counters.last_successful_send = Some(now);
+    }
+
+    pub(crate) fn record_size(&self, size: usize) {
+        self.lock().message_sizes.record(size as u64);
+    }
+
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub(crate) fn record_compressed(&self, size: usize, compressed_size: usize) {
+        let mut counters = self.lock();
+        counters.compressed += 1;
+        counters.compression_saved_bytes += size.saturating_sub(compressed_size) as u64;
+    }
+
+    pub(crate) fn record_reconnect(&self) {
+        self.lock().reconnects += 1;
+    }
+
+    pub(crate) fn record_error(&self, err: Arc<Error>) {
+        self.lock().last_error = Some((Instant::now(), err));
+    }
+
+    pub(crate) fn record_dropped(&self, level: Level, target: &str) {
+        let mut counters = self.lock();
+        counters.dropped += 1;
+        *counters.dropped_by_level.entry(level).or_insert(0) += 1;
+        counters.dropped_by_target.record(target);
+    }
+
+    pub(crate) fn snapshot(&self) -> Stats {
+        let counters = self.lock();
+        Stats {
+            sent: counters.sent,
+            dropped: counters.dropped,
+            dropped_by_level: counters.dropped_by_level.clone(),
+            dropped_by_target: counters.dropped_by_target.top(),
+            last_error: counters.last_error.clone(),
+            last_successful_send: counters.last_successful_send,
+            reconnects: counters.reconnects,
+            message_sizes: MessageSizes {
+                p50: counters.message_sizes.percentile(0.5) as usize,
+                p95: counters.message_sizes.percentile(0.95) as usize,
+                max: counters.message_sizes.max as usize,
+            },
+            send_latency: SendLatency {
+                p50: Duration::from_micros(counters.send_latency.percentile(0.5)),
+                p99: Duration::from_micros(counters.send_latency.percentile(0.99)),
+                max: Duration::from_micros(counters.send_latency.max),
+            },
+            compressed: counters.compressed,
+            compression_saved_bytes: counters.compression_saved_bytes,
+        }
+    }
+}
+
+/// Counts of values per power of two: bucket `i` holds the values up to `2^i`, above `2^(i - 1)`.
+#[derive(Debug)]
+struct Histogram {
+    buckets: [u64; 65],
+    count: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram { buckets: [0; 65], count: 0, max: 0 }
+    }
+}
+
+impl Histogram {
+    fn record(&mut self, value: u64) {
+        let bucket = if value <= 1 { 0 } else { 64 - (value - 1).leading_zeros() as usize };
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.max = cmp::max(self.max, value);
+    }
+
+    /// Upper bound of the bucket holding the `quantile` of the values.
+    fn percentile(&self, quantile: f64) -> u64 {
+        let rank = cmp::max((self.count as f64 * quantile).ceil() as u64, 1);
+        let mut seen = 0;
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let bound = 1u64.checked_shl(bucket as u32).unwrap_or(u64::MAX);
+                return cmp::min(bound, self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// Bounded heavy-hitters counter, using the Space-Saving algorithm.
+#[derive(Debug)]
+struct TopTargets {
+    capacity: usize,
+    counts: Vec<(String, u64)>,
+}
+
+impl TopTargets {
+    fn record(&mut self, target: &str) {
+        if let Some(entry) = self.counts.iter_mut().find(|entry| entry.0 == target) {
+            entry.1 += 1;
+            return;
+        }
+        if self.counts.len() < self.capacity {
+            self.counts.push((target.to_string(), 1));
+            return;
+        }
+        // The least affected target makes room and the newcomer inherits its count.
+        if let Some(entry) = self.counts.iter_mut().min_by_key(|entry| entry.1) {
+            let count = entry.1 + 1;
+            *entry = (target.to_string(), count);
+        }
+    }
+
+    fn top(&self) -> Vec<(String, u64)> {
+        let mut top = self.counts.clone();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_are_counted_by_level_and_target() {
+        let stats = StatsCollector::new(DEFAULT_DROP_STATS_TARGETS);
+        stats.record_dropped(Level::Warn, "app::db");
+        stats.record_dropped(Level::Info, "app::http");
+        stats.record_dropped(Level::Info, "app::db");
+        stats.record_dropped(Level::Info, "app::db");
+
+        let stats = stats.snapshot();
+        assert_eq!(stats.dropped, 4);
+        assert_eq!(stats.dropped_by_level.into_iter().collect::<Vec<_>>(), vec![(Level::Warn, 1), (Level::Info, 3)]);
+        assert_eq!(stats.dropped_by_target, vec![("app::db".to_string(), 3), ("app::http".to_string(), 1)]);
+    }
+
+    #[test]
+    fn untracked_targets_take_over_the_least_affected_one() {
+        let stats = StatsCollector::new(2);
+        for target in &["a", "a", "a", "b", "c", "c"] {
+            stats.record_dropped(Level::Error, target);
+        }
+
+        // `c` replaced `b`, and inherited its count: its own is an upper bound.
+        let stats = stats.snapshot();
+        assert_eq!(stats.dropped, 6);
+        assert_eq!(stats.dropped_by_target, vec![("a".to_string(), 3), ("c".to_string(), 3)]);
+    }
+}
@@ -0,0 +1,257 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use serde_value::Value;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Record key-values whose value replaces the time of the logging call as the `timestamp` of the
+/// message.
+pub(crate) const TIMESTAMP_KEYS: [&str; 2] = ["timestamp", "event_time"];
+
+/// Reads a timestamp given as seconds since the UNIX epoch, possibly fractional, or as an
+/// RFC 3339 date such as `2024-05-17T09:30:00.250+02:00`.
+pub(crate) fn parse_timestamp(value: &Value) -> Option<f64> {
+    let seconds = match value {
+        Value::U64(seconds) => *seconds as f64,
+        Value::I64(seconds) => *seconds as f64,
+        Value::F64(seconds) => *seconds,
+        Value::String(text) => match text.parse::<f64>() {
+            Ok(seconds) => seconds,
+            Err(_) => parse_rfc3339(text)?,
+        },
+        _ => return None,
+    };
+    if seconds.is_finite() && seconds >= 0.0 {
+        Some(seconds)
+    } else {
+        None
+    }
+}
+
+fn parse_rfc3339(text: &str) -> Option<f64> {
+    let bytes = text.as_bytes();
+    if !text.is_ascii() || bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[13] != b':' || bytes[16] != b':'
+        || !matches!(bytes[10], b'T' | b't' | b' ') {
+        return None;
+    }
+    let (year, month, day) = (digits(&text[0..4])?, digits(&text[5..7])?, digits(&text[8..10])?);
+    let (hour, minute, second) = (digits(&text[11..13])?, digits(&text[14..16])?, digits(&text[17..19])?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let mut rest = &text[19..];
+    let mut fraction = 0.0;
+    if let Some(decimals) = rest.strip_prefix('.') {
+        let count = decimals.bytes().take_while(u8::is_ascii_digit).count();
+        if count == 0 {
+            return None;
+        }
+        fraction = format!("0.{}", &decimals[..count]).parse().ok()?;
+        rest = &decimals[count..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first() {
+                Some(b'+') => 1,
+                Some(b'-') => -1,
+                _ => return None,
+            };
+            if rest.len() != 6 || rest.as_bytes()[3] != b':' {
+                return None;
+            }
+            sign * (digits(&rest[1..3])? * 3600 + digits(&rest[4..6])? * 60)
+        }
+    };
+
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    Some(seconds as f64 + fraction)
+}
+
+fn digits(text: &str) -> Option<i64> {
+    if text.bytes().all(|byte| byte.is_ascii_digit()) {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Reads an HTTP `Date` header, such as `Sun, 06 Nov 1994 08:49:37 GMT`. The obsolete formats
+/// aren't accepted.
+#[cfg(feature = "http")]
+pub(crate) fn parse_http_date(text: &str) -> Option<f64> {
+    let parts: Vec<&str> = text.split_whitespace().collect();
+    if !text.is_ascii() || parts.len() != 6 || parts[5] != "GMT" || parts[4].len() != 8 {
+        return None;
+    }
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let month = MONTHS.iter().position(|month| *month == parts[2])? as i64 + 1;
+    let (day, year) = (digits(parts[1])?, digits(parts[3])?);
+    let time = parts[4];
+    if time.as_bytes()[2] != b':' || time.as_bytes()[5] != b':' {
+        return None;
+    }
+    let (hour, minute, second) = (digits(&time[0..2])?, digits(&time[3..5])?, digits(&time[6..8])?);
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    Some((days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second) as f64)
+}
+
+/// Seconds since the UNIX epoch, with millisecond precision.
+fn now() -> f64 {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    elapsed.as_secs() as f64 + f64::from(elapsed.subsec_millis()) / 1000.0
+}
+
+/// The local clock, corrected by an offset for hosts whose clock is known to be wrong. The offset
+/// is set once, or follows the `Date` of the HTTP answers.
+#[derive(Debug)]
+pub(crate) struct Clock {
+    offset_millis: AtomicI64,
+}
+
+impl Clock {
+    /// A clock `offset` seconds after the local one.
+    pub(crate) fn new(offset: f64) -> Clock {
+        Clock { offset_millis: AtomicI64::new((offset * 1000.0).round() as i64) }
+    }
+
+    /// Corrected seconds since the UNIX epoch, with millisecond precision.
+    pub(crate) fn now(&self) -> f64 {
+        now() + self.offset_millis.load(Ordering::Relaxed) as f64 / 1000.0
+    }
+
+    /// Takes the offset from the `Date` of an HTTP answer just read. The header is to the second:
+    /// the server time is taken as the middle of that second, and a skew below a second is taken
+    /// as none, not to shift correct clocks back and forth.
+    #[cfg(feature = "http")]
+    pub(crate) fn observe_http_date(&self, date: f64) {
+        let offset = date + 0.5 - now();
+        let offset_millis = if offset.abs() < 1.0 { 0 } else { (offset * 1000.0).round() as i64 };
+        self.offset_millis.store(offset_millis, Ordering::Relaxed);
+    }
+}
+
+/// Days between the UNIX epoch and a date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Years start in March, so that the leap day ends them.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Formats seconds since the UNIX epoch as an RFC 3339 date in UTC, to the millisecond, such as
+/// `2024-05-17T07:30:00.250Z`.
+pub(crate) fn format_rfc3339(seconds: f64) -> String {
+    let millis = (seconds * 1000.0).round() as i64;
+    let (days, millis) = (millis.div_euclid(86_400_000), millis.rem_euclid(86_400_000));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, millis / 3_600_000, millis / 60_000 % 60, millis / 1000 % 60, millis % 1000
+    )
+}
+
+/// Date of the proleptic Gregorian calendar, a number of days after the UNIX epoch.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months are counted from March, as in days_from_civil.
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3339_dates_are_parsed() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0.0));
+        assert_eq!(parse_rfc3339("2024-05-17T09:30:00.250+02:00"), Some(1_715_931_000.25));
+        assert_eq!(parse_rfc3339("2024-05-17 07:30:00z"), Some(1_715_931_000.0));
+        assert_eq!(parse_rfc3339("2024-05-16T21:00:00-10:30"), Some(1_715_931_000.0));
+        // Leap days and a leap second.
+        assert_eq!(parse_rfc3339("2000-02-29T00:00:00Z"), Some(951_782_400.0));
+        assert_eq!(parse_rfc3339("2024-03-01T00:00:00Z"), Some(1_709_251_200.0));
+        assert_eq!(parse_rfc3339("2016-12-31T23:59:60Z"), Some(1_483_228_800.0));
+    }
+
+    #[test]
+    fn malformed_rfc3339_dates_are_rejected() {
+        for text in &[
+            "",
+            "2024-05-17",
+            "2024-05-17T09:30:00",
+            "2024-13-17T09:30:00Z",
+            "2024-05-00T09:30:00Z",
+            "2024-05-17T24:00:00Z",
+            "2024-05-17T09:30:00.Z",
+            "2024-05-17T09:30:00+0200",
+            "2024-05-17T09:30:00 UTC",
+            "2024/05/17T09:30:00Z",
+            "2024-05-1éT09:30:00Z",
+            "2024-05-17T09:30:00+0é:0",
+        ] {
+            assert_eq!(parse_rfc3339(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn timestamps_are_read_from_numbers_and_dates() {
+        assert_eq!(parse_timestamp(&Value::U64(1_715_931_000)), Some(1_715_931_000.0));
+        assert_eq!(parse_timestamp(&Value::String("1715931000.5".to_string())), Some(1_715_931_000.5));
+        assert_eq!(parse_timestamp(&Value::String("2024-05-17T07:30:00Z".to_string())), Some(1_715_931_000.0));
+        assert_eq!(parse_timestamp(&Value::I64(-1)), None);
+        assert_eq!(parse_timestamp(&Value::F64(f64::NAN)), None);
+        assert_eq!(parse_timestamp(&Value::Bool(true)), None);
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn http_dates_are_parsed() {
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784_111_777.0));
+        assert_eq!(parse_http_date("Thu, 29 Feb 2024 12:00:00 GMT"), Some(1_709_208_000.0));
+        for text in &[
+            "",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 08-49-37 GMT",
+            "Sun, 06 Nov 1994 0é:49:3 GMT",
+        ] {
+            assert_eq!(parse_http_date(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn days_convert_both_ways() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        // 1900 and 2100 aren't leap years.
+        assert_eq!(civil_from_days(days_from_civil(1900, 3, 1) - 1), (1900, 2, 28));
+        assert_eq!(civil_from_days(days_from_civil(2100, 3, 1) - 1), (2100, 2, 28));
+        for days in (-800_000..800_000).step_by(97) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(format_rfc3339(951_782_400.25), "2000-02-29T00:00:00.250Z");
+    }
+}
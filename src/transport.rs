@@ -0,0 +1,403 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use error::Error;
+#[cfg(feature = "http")]
+use http::HttpTransport;
+use stats::StatsCollector;
+#[cfg(feature = "tcp")]
+use std::convert::TryFrom;
+use std::fmt;
+#[cfg(feature = "tcp")]
+use std::io::BufWriter;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use timestamp::Clock;
+
+#[cfg(not(any(feature = "tcp", feature = "http")))]
+compile_error!("log4rs-gelf needs a transport: enable the `tcp` or `http` feature");
+
+/// Default path of the Graylog GELF HTTP input.
+pub(crate) const DEFAULT_HTTP_PATH: &str = "/gelf";
+
+/// Protocol spoken with the remote server. Each one is behind the feature of the same name.
+#[derive(serde_derive::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    /// GELF TCP input: messages are written to a stream, separated by a delimiter.
+    #[cfg(feature = "tcp")]
+    Tcp,
+    /// GELF HTTP input: each message is the body of a `POST` request.
+    #[cfg(feature = "http")]
+    Http,
+}
+
+/// TCP, or HTTP when built without the `tcp` feature.
+impl Default for Protocol {
+    #[cfg(feature = "tcp")]
+    fn default() -> Protocol {
+        Protocol::Tcp
+    }
+    #[cfg(not(feature = "tcp"))]
+    fn default() -> Protocol {
+        Protocol::Http
+    }
+}
+
+/// How messages are delimited on a TCP stream.
+#[derive(serde_derive::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    /// Each message is followed by a NUL byte (`\0`), as Graylog expects.
+    #[default]
+    Null,
+    /// Each message is followed by a newline, as Vector, Logstash and others expect.
+    Newline,
+    /// Each message is preceded by its length, on 4 bytes in big-endian order. Payloads may then
+    /// hold any byte.
+    #[serde(alias = "length-prefix")]
+    LengthPrefix,
+}
+
+/// Settings needed to open a connection to the remote server.
+#[derive(Debug, Clone)]
+pub(crate) struct TransportConfig {
+    pub(crate) protocol: Protocol,
+    pub(crate) hostname: String,
+    pub(crate) port: u16,
+    #[cfg(feature = "tls")]
+    pub(crate) use_tls: bool,
+    /// SHA-256 digest the DER encoding of the server certificate must have.
+    #[cfg(feature = "tls")]
+    pub(crate) pinned_cert_sha256: Option<[u8; 32]>,
+    /// Whether the server certificate must be signed by a trusted CA and match the hostname.
+    #[cfg(feature = "tls")]
+    pub(crate) verify_ca: bool,
+    /// PEM certificate chain and PKCS #8 key presented to the server.
+    #[cfg(feature = "tls")]
+    pub(crate) client_identity: Option<Secret<(String, String)>>,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    #[cfg_attr(not(feature = "tcp"), allow(dead_code))]
+    pub(crate) framing: Framing,
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub(crate) http_path: String,
+    /// Value of the `Authorization` header of the HTTP requests.
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub(crate) http_authorization: Option<Secret<String>>,
+    /// How long a kept-alive HTTP connection may stay unused before a new one is opened.
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub(crate) http_idle_timeout: Option<Duration>,
+    pub(crate) resolver: Option<Resolver>,
+    /// Smallest payload compressed, when the protocol supports it.
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub(crate) compression_threshold: Option<usize>,
+    /// Clock whose offset follows the `Date` of the HTTP answers.
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub(crate) server_clock: Option<Arc<Clock>>,
+}
+
+/// A setting such as a key or a token, kept out of `Debug` output.
+#[derive(Clone, PartialEq)]
+pub(crate) struct Secret<T>(pub(crate) T);
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("***")
+    }
+}
+
+/// Addresses of a hostname.
+type Resolve = dyn Fn(&str) -> io::Result<Vec<SocketAddr>> + Send + Sync;
+
+/// Resolves the hostname of the remote server instead of the system resolver.
+#[derive(Clone)]
+pub(crate) struct Resolver(pub(crate) Arc<Resolve>);
+
+impl Resolver {
+    /// The addresses of `hostname`, all with the given port.
+    fn resolve(&self, hostname: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let mut addrs = (self.0)(hostname)?;
+        for addr in &mut addrs {
+            addr.set_port(port);
+        }
+        Ok(addrs)
+    }
+}
+
+/// Resolvers are equal when they are the same closure.
+impl PartialEq for Resolver {
+    fn eq(&self, other: &Resolver) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for Resolver {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Resolver").finish()
+    }
+}
+
+/// A way of delivering serialized messages to the remote server, driven by the background sender.
+///
+/// The appender uses the GELF TCP or HTTP transport set up by its builder, unless another one is
+/// given to [`BufferAppender::with_transport`](struct.BufferAppender.html#method.with_transport).
+/// When a call fails, the sender disconnects, waits, then connects again and retries the batch.
+pub trait GelfTransport: fmt::Debug + Send {
+    /// Whether `connect` succeeded and the transport wasn't disconnected since.
+    fn is_connected(&self) -> bool;
+
+    /// Opens the connection to the remote server. Called before a batch is written, when not
+    /// connected.
+    fn connect(&mut self) -> Result<(), Error>;
+
+    /// Closes the connection, after a failure or on shutdown.
+    fn disconnect(&mut self);
+
+    /// Sends one serialized message. It may be buffered until the next `flush`.
+    fn send(&mut self, payload: &[u8]) -> Result<(), Error>;
+
+    /// Writes the buffered messages. Called at the end of each batch.
+    fn flush(&mut self) -> Result<(), Error>;
+
+    /// Whether a successful `send` means the server received the message, rather than only
+    /// that it was written to the connection.
+    fn acknowledges(&self) -> bool {
+        false
+    }
+
+    /// How long the server asked to wait before the next attempt, after a failed `send`.
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// A transport given by the embedder. The clones of a builder share it, until one of them builds
+/// an appender.
+#[derive(Debug, Clone)]
+pub(crate) struct TransportSlot(Arc<Mutex<Option<Box<dyn GelfTransport>>>>);
+
+impl TransportSlot {
+    pub(crate) fn new(transport: Box<dyn GelfTransport>) -> TransportSlot {
+        TransportSlot(Arc::new(Mutex::new(Some(transport))))
+    }
+
+    pub(crate) fn is_taken(&self) -> bool {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).is_none()
+    }
+
+    pub(crate) fn take(&self) -> Option<Box<dyn GelfTransport>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).take()
+    }
+}
+
+impl PartialEq for TransportSlot {
+    fn eq(&self, other: &TransportSlot) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Creates the transport speaking `config.protocol`. No connection is opened yet.
+#[cfg_attr(not(feature = "http"), allow(unused_variables))]
+pub(crate) fn new_transport(config: TransportConfig, stats: Arc<StatsCollector>) -> Box<dyn GelfTransport> {
+    match config.protocol {
+        #[cfg(feature = "tcp")]
+        Protocol::Tcp => Box::new(StreamTransport { config, connector: Connector::default(), connection: None }),
+        #[cfg(feature = "http")]
+        Protocol::Http => Box::new(HttpTransport::new(config, stats)),
+    }
+}
+
+/// GELF TCP transport: messages are written one after the other, delimited as configured.
+#[cfg(feature = "tcp")]
+#[derive(Debug)]
+struct StreamTransport {
+    config: TransportConfig,
+    connector: Connector,
+    connection: Option<BufWriter<Connection>>,
+}
+
+#[cfg(feature = "tcp")]
+impl GelfTransport for StreamTransport {
+    fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    fn connect(&mut self) -> Result<(), Error> {
+        self.connection = Some(BufWriter::new(self.connector.open(&self.config)?));
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.connection = None;
+    }
+
+    fn send(&mut self, payload: &[u8]) -> Result<(), Error> {
+        let connection = self.connection.as_mut().ok_or_else(not_connected)?;
+        if self.config.framing == Framing::LengthPrefix {
+            let length = u32::try_from(payload.len()).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "message too large for a 4-byte length prefix")
+            })?;
+            connection.write_all(&length.to_be_bytes())?;
+        }
+        connection.write_all(payload)?;
+        match self.config.framing {
+            Framing::Null => connection.write_all(b"\0")?,
+            Framing::Newline => connection.write_all(b"\n")?,
+            Framing::LengthPrefix => {}
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        let connection = self.connection.as_mut().ok_or_else(not_connected)?;
+        connection.flush()?;
+        Ok(())
+    }
+}
+
+pub(crate) fn not_connected() -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::NotConnected, "not connected to the remote server"))
+}
+
+/// An established connection to the remote server.
+#[derive(Debug)]
+pub(crate) enum Connection {
+    Tcp(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(native_tls::TlsStream<TcpStream>),
+}
+
+/// Opens the connections of a transport.
+///
+/// The TLS connector, loading the trusted certificates, is only set up once and reused on
+/// reconnection. Sessions aren't resumed: native-tls gives no access to sessions or tickets, so
+/// every connection performs a full handshake.
+#[derive(Debug, Default)]
+pub(crate) struct Connector {
+    #[cfg(feature = "tls")]
+    tls: Option<native_tls::TlsConnector>,
+}
+
+impl Connector {
+    /// Opens a new connection, performing the TLS handshake when enabled.
+    pub(crate) fn open(&mut self, config: &TransportConfig) -> Result<Connection, Error> {
+        let stream = connect_tcp(config)?;
+        stream.set_write_timeout(config.write_timeout)?;
+        stream.set_read_timeout(config.write_timeout)?;
+
+        #[cfg(feature = "tls")]
+        {
+            if config.use_tls {
+                let connector = match self.tls.take() {
+                    Some(connector) => connector,
+                    None => {
+                        let mut builder = native_tls::TlsConnector::builder();
+                        builder
+                            .danger_accept_invalid_certs(!config.verify_ca)
+                            .danger_accept_invalid_hostnames(!config.verify_ca);
+                        if let Some(Secret((certificate, key))) = &config.client_identity {
+                            builder.identity(native_tls::Identity::from_pkcs8(certificate.as_bytes(), key.as_bytes())?);
+                        }
+                        builder.build()?
+                    }
+                };
+                let stream = connector.connect(&config.hostname, stream);
+                self.tls = Some(connector);
+                let stream = stream?;
+                if let Some(pinned) = config.pinned_cert_sha256 {
+                    check_pinned_cert(&stream, &pinned)?;
+                }
+                return Ok(Connection::Tls(stream));
+            }
+        }
+
+        Ok(Connection::Tcp(stream))
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Fails unless the certificate presented by the server has the pinned fingerprint.
+#[cfg(feature = "tls")]
+fn check_pinned_cert(stream: &native_tls::TlsStream<TcpStream>, pinned: &[u8; 32]) -> Result<(), Error> {
+    use sha2::{Digest, Sha256};
+
+    let certificate = stream.peer_certificate()?.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "the remote server presented no certificate")
+    })?;
+    if Sha256::digest(certificate.to_der()?).as_slice() != &pinned[..] {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the certificate of the remote server doesn't match the pinned fingerprint",
+        )));
+    }
+    Ok(())
+}
+
+/// Parses a SHA-256 fingerprint in hexadecimal, its bytes optionally separated by colons as
+/// `openssl x509 -fingerprint` prints them.
+#[cfg(feature = "tls")]
+pub(crate) fn parse_fingerprint(fingerprint: &str) -> Option<[u8; 32]> {
+    let digits: Vec<u8> = fingerprint.bytes().filter(|&byte| byte != b':').collect();
+    if digits.len() != 64 {
+        return None;
+    }
+    let mut parsed = [0; 32];
+    for (byte, pair) in parsed.iter_mut().zip(digits.chunks(2)) {
+        let pair = std::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(parsed)
+}
+
+fn connect_tcp(config: &TransportConfig) -> io::Result<TcpStream> {
+    let addrs: Vec<SocketAddr> = match &config.resolver {
+        Some(resolver) => resolver.resolve(&config.hostname, config.port)?,
+        None => (config.hostname.as_str(), config.port).to_socket_addrs()?.collect(),
+    };
+
+    let mut last_error = None;
+    for addr in addrs {
+        let stream = match config.connect_timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+        match stream {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::AddrNotAvailable, "hostname did not resolve to any address")
+    }))
+}
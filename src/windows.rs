@@ -0,0 +1,50 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+use serde_value::Value;
+
+/// Fields identifying the Windows session and account the process runs under:
+/// `windows_session_id` and `windows_user`. Empty on other platforms.
+pub(crate) fn fields() -> Vec<(String, Value)> {
+    #[cfg_attr(not(windows), allow(unused_mut))]
+    let mut fields = Vec::new();
+    #[cfg(windows)]
+    {
+        if let Some(session_id) = session_id() {
+            fields.push(("windows_session_id".to_string(), Value::U32(session_id)));
+        }
+        if let Some(user) = user_name() {
+            fields.push(("windows_user".to_string(), Value::String(user)));
+        }
+    }
+    fields
+}
+
+/// Remote Desktop Services session of the current process, `0` for services.
+#[cfg(windows)]
+fn session_id() -> Option<u32> {
+    use windows_sys::Win32::System::RemoteDesktop::ProcessIdToSessionId;
+
+    let mut session_id = 0;
+    // SAFETY: `session_id` is a valid pointer for the duration of the call.
+    let ok = unsafe { ProcessIdToSessionId(std::process::id(), &mut session_id) };
+    if ok != 0 { Some(session_id) } else { None }
+}
+
+/// Name of the account the current thread runs as.
+#[cfg(windows)]
+fn user_name() -> Option<String> {
+    use windows_sys::Win32::System::WindowsProgramming::GetUserNameW;
+
+    // UNLEN + 1
+    let mut buffer = [0u16; 257];
+    let mut len = buffer.len() as u32;
+    // SAFETY: `len` holds the capacity of `buffer`, in UTF-16 units.
+    let ok = unsafe { GetUserNameW(buffer.as_mut_ptr(), &mut len) };
+    if ok == 0 {
+        return None;
+    }
+    // `len` counts the terminating NUL.
+    String::from_utf16(&buffer[..len.saturating_sub(1) as usize]).ok()
+}
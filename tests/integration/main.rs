@@ -0,0 +1,272 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+//! End-to-end tests: records go through the appender, its background sender and a real socket
+//! to a GELF input which checks every message it receives.
+//!
+//! Run with `cargo test --features integration-tests`. The inputs are served in-process by
+//! `server.rs`; `graylog_http_input` also ships to a real Graylog when `GRAYLOG_GELF_HTTP` holds
+//! the `host:port` of a GELF HTTP input.
+
+extern crate flate2;
+extern crate log;
+extern crate log4rs;
+extern crate log4rs_gelf;
+extern crate native_tls;
+extern crate serde_json;
+
+mod server;
+
+use log::{Level, LevelFilter, Record};
+use log4rs::append::Append;
+use log4rs::config::{Appender, Config, Root};
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs_gelf::{BufferAppender, BufferAppenderBuilder, Framing, Protocol};
+use server::{GelfServer, Input, CERTIFICATE_SHA256};
+use std::env;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn builder(port: u16) -> BufferAppenderBuilder {
+    BufferAppender::builder()
+        .set_hostname("127.0.0.1")
+        .set_port(port)
+        .set_use_tls(false)
+        .set_max_batch_delay(Duration::from_millis(10))
+        .set_flush_timeout(Some(Duration::from_secs(5)))
+}
+
+fn log(appender: &BufferAppender, message: &str) {
+    appender.append(&Record::builder()
+        .args(format_args!("{}", message))
+        .level(Level::Info)
+        .target("integration")
+        .build()).expect("Failed to append");
+}
+
+/// A log4rs configuration sending everything to `appender`.
+fn config(appender: BufferAppender) -> Config {
+    Config::builder()
+        .appender(Appender::builder().build("gelf", Box::new(appender)))
+        .build(Root::builder().appender("gelf").build(LevelFilter::Info))
+        .unwrap()
+}
+
+fn short_messages(messages: &[serde_json::Value]) -> Vec<&str> {
+    messages.iter().map(|message| message["short_message"].as_str().unwrap()).collect()
+}
+
+/// Sends 100 records in a row, and checks they all arrive in order.
+fn check_delivery(server: &GelfServer, appender: &BufferAppender) {
+    let expected: Vec<String> = (0..100).map(|i| format!("message {}", i)).collect();
+    for message in &expected {
+        log(appender, message);
+    }
+    appender.flush();
+    let messages = server.wait_for(expected.len());
+    assert_eq!(short_messages(&messages), expected);
+    assert_eq!(messages[0]["level"], 6);
+    assert_eq!(appender.stats().sent, 100);
+}
+
+#[test]
+fn tcp_null_framing() {
+    let server = GelfServer::start(Input::Tcp(Framing::Null));
+    check_delivery(&server, &builder(server.port()).build().unwrap());
+}
+
+#[test]
+fn tcp_newline_framing() {
+    let server = GelfServer::start(Input::Tcp(Framing::Newline));
+    check_delivery(&server, &builder(server.port()).set_framing(Framing::Newline).build().unwrap());
+}
+
+#[test]
+fn tcp_length_prefix_framing() {
+    let server = GelfServer::start(Input::Tcp(Framing::LengthPrefix));
+    check_delivery(&server, &builder(server.port()).set_framing(Framing::LengthPrefix).build().unwrap());
+}
+
+#[test]
+fn http_input() {
+    let server = GelfServer::start(Input::Http);
+    let builder = builder(server.port())
+        .set_protocol(Protocol::Http)
+        .set_compression_threshold(None)
+        .set_http_authorization("Bearer 7f3c9a");
+    assert!(!builder.describe().contains("7f3c9a"));
+    let appender = builder.build().unwrap();
+    check_delivery(&server, &appender);
+    assert_eq!(server.authorizations(), vec!["Bearer 7f3c9a"; 100]);
+    assert_eq!(server.gzipped(), 0);
+    assert_eq!(server.connections(), 1, "the connection should be kept alive");
+}
+
+/// Each connection of the pool is kept alive, and together they deliver every message.
+#[test]
+fn http_connection_pool() {
+    let server = GelfServer::start(Input::Http);
+    let appender = builder(server.port()).set_protocol(Protocol::Http).set_connections(4).build().unwrap();
+    let mut expected: Vec<String> = (0..100).map(|i| format!("message {}", i)).collect();
+    for message in &expected {
+        log(&appender, message);
+    }
+    appender.flush();
+    let messages = server.wait_for(expected.len());
+    let mut received = short_messages(&messages);
+    received.sort_unstable();
+    expected.sort_unstable();
+    assert_eq!(received, expected);
+    assert_eq!(server.connections(), 4);
+}
+
+#[test]
+fn http_input_gzip() {
+    let server = GelfServer::start(Input::Http);
+    let appender = builder(server.port())
+        .set_protocol(Protocol::Http)
+        .set_compression_threshold(Some(1))
+        .build()
+        .unwrap();
+    check_delivery(&server, &appender);
+    assert_eq!(server.gzipped(), 100);
+    assert_eq!(appender.stats().compressed, 100);
+}
+
+#[test]
+fn tls_pinned_certificate() {
+    let server = GelfServer::start(Input::Tls(Framing::Null));
+    let appender = builder(server.port())
+        .set_use_tls(true)
+        .set_tls_pinned_cert_sha256(CERTIFICATE_SHA256)
+        .set_tls_verify_ca(false)
+        .build()
+        .unwrap();
+    check_delivery(&server, &appender);
+}
+
+#[test]
+fn tls_pin_mismatch() {
+    let server = GelfServer::start(Input::Tls(Framing::Null));
+    let appender = builder(server.port())
+        .set_use_tls(true)
+        .set_tls_pinned_cert_sha256(&"00".repeat(32))
+        .set_tls_verify_ca(false)
+        .set_flush_timeout(Some(Duration::from_secs(1)))
+        .build()
+        .unwrap();
+    log(&appender, "never sent");
+    appender.flush();
+    assert!(server.connections() >= 1);
+    assert!(server.messages().is_empty());
+    let stats = appender.stats();
+    assert_eq!(stats.sent, 0);
+    let (_, error) = stats.last_error.expect("the pin mismatch should be reported");
+    assert!(error.to_string().contains("pinned fingerprint"), "{}", error);
+}
+
+#[test]
+fn chunked_full_message() {
+    let server = GelfServer::start(Input::Tcp(Framing::Null));
+    let appender = builder(server.port())
+        .set_encoder(Box::new(PatternEncoder::new("{m}")))
+        .set_full_message_chunk_bytes(Some(1000))
+        .build()
+        .unwrap();
+    // Two-byte characters, so that chunks can't all end on a byte boundary.
+    let full_message = "é".repeat(2200);
+    log(&appender, &full_message);
+    appender.flush();
+
+    let mut messages = server.wait_for(5);
+    assert_eq!(messages.len(), 5);
+    messages.sort_by_key(|message| message["_chunk_index"].as_u64().unwrap());
+    let correlation_id = &messages[0]["_correlation_id"];
+    assert!(correlation_id.is_string());
+    let mut reassembled = String::new();
+    for (index, message) in messages.iter().enumerate() {
+        assert_eq!(message["_chunk_index"], index);
+        assert_eq!(message["_chunk_total"], 5);
+        assert_eq!(&message["_correlation_id"], correlation_id);
+        let chunk = message["full_message"].as_str().unwrap();
+        assert!(chunk.len() <= 1000);
+        reassembled.push_str(chunk);
+    }
+    assert_eq!(reassembled, full_message);
+}
+
+/// The server closes the connection, as when it restarts: the appender reconnects and the
+/// records logged from then on are delivered.
+#[test]
+fn reconnection() {
+    let server = GelfServer::start(Input::Tcp(Framing::Null));
+    let appender = builder(server.port()).build().unwrap();
+    log(&appender, "before");
+    appender.flush();
+    server.wait_for(1);
+
+    server.drop_connections();
+    // Records written before the appender notices the connection is gone are lost.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut attempt = 0;
+    while !short_messages(&server.messages()).iter().any(|message| message.starts_with("after")) {
+        assert!(Instant::now() < deadline, "Nothing was delivered after the disconnection");
+        log(&appender, &format!("after {}", attempt));
+        appender.flush();
+        attempt += 1;
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(server.connections() >= 2);
+    assert!(appender.stats().reconnects >= 1);
+}
+
+/// `log4rs::Handle::set_config` replaces the appender over and over: each one delivers what it was
+/// given before it's dropped, and dropping one stuck writing to a server which stopped reading
+/// doesn't hold up the call.
+#[test]
+fn reconfiguration() {
+    let server = GelfServer::start(Input::Tcp(Framing::Null));
+    let handle = log4rs::init_config(config(builder(server.port()).build().unwrap())).unwrap();
+    let mut expected = Vec::new();
+    for i in 0..5 {
+        let message = format!("configuration {}", i);
+        log::info!(target: "integration", "{}", message);
+        expected.push(message);
+        handle.set_config(config(builder(server.port()).build().unwrap()));
+    }
+    let messages = server.wait_for(expected.len());
+    let mut received = short_messages(&messages);
+    received.sort_unstable();
+    assert_eq!(received, expected);
+
+    // Enough to fill the socket buffers, so that the sender blocks on a write which never ends.
+    let stalled = GelfServer::start(Input::Stalled);
+    handle.set_config(config(builder(stalled.port()).set_flush_timeout(None).build().unwrap()));
+    let record = "x".repeat(64 * 1024);
+    for _ in 0..400 {
+        log::info!(target: "integration", "{}", record);
+    }
+    thread::sleep(Duration::from_millis(500));
+    let start = Instant::now();
+    handle.set_config(config(builder(server.port()).build().unwrap()));
+    assert!(start.elapsed() < Duration::from_secs(10), "set_config took {:?}", start.elapsed());
+}
+
+/// Ships a record to the Graylog GELF HTTP input at `GRAYLOG_GELF_HTTP` (`host:port`), which
+/// acknowledges it once accepted.
+#[test]
+#[ignore = "needs a Graylog server, see GRAYLOG_GELF_HTTP"]
+fn graylog_http_input() {
+    let address = env::var("GRAYLOG_GELF_HTTP").expect("GRAYLOG_GELF_HTTP isn't set");
+    let (hostname, port) = address.rsplit_once(':').expect("GRAYLOG_GELF_HTTP isn't host:port");
+    let appender = builder(port.parse().expect("Invalid port"))
+        .set_hostname(hostname)
+        .set_protocol(Protocol::Http)
+        .build()
+        .unwrap();
+    log(&appender, "log4rs-gelf integration test");
+    appender.flush();
+    let stats = appender.stats();
+    assert_eq!(stats.sent, 1, "Graylog didn't accept the message: {:?}", stats.last_error);
+}
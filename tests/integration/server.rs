@@ -0,0 +1,295 @@
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+// Copyright 2009 The log4rs-gelf Authors. All rights reserved.
+
+//! GELF input for the tests: accepts messages over TCP, TLS or HTTP as Graylog does, and checks
+//! that each one conforms to the GELF 1.1 specification.
+//!
+//! The listener and connection threads are detached, they end with the test process.
+
+use flate2::read::GzDecoder;
+use log4rs_gelf::Framing;
+use native_tls::{Identity, TlsAcceptor};
+use serde_json::Value;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Certificate presented by the TLS input, self-signed for `localhost` and `127.0.0.1`.
+const CERTIFICATE: &[u8] = include_bytes!("fixtures/server.crt");
+const PRIVATE_KEY: &[u8] = include_bytes!("fixtures/server.key");
+
+/// SHA-256 fingerprint of `fixtures/server.crt`, from
+/// `openssl x509 -in server.crt -noout -fingerprint -sha256`.
+pub const CERTIFICATE_SHA256: &str =
+    "DF:13:41:B7:E7:4A:AF:DB:0B:D0:79:4E:16:81:BA:58:F7:7E:40:E6:04:10:EB:9A:70:F4:F0:1C:62:DF:BA:F3";
+
+/// How long to wait for messages before failing.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Kind of GELF input.
+#[derive(Debug, Clone, Copy)]
+pub enum Input {
+    Tcp(Framing),
+    Tls(Framing),
+    /// GELF HTTP input, on `/gelf`.
+    Http,
+    /// TCP input which accepts connections and never reads from them, as a server stuck behind
+    /// a full disk does.
+    Stalled,
+}
+
+/// A GELF input listening on an ephemeral port of the loopback interface.
+pub struct GelfServer {
+    port: u16,
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    received: Mutex<Received>,
+    arrived: Condvar,
+    connections: AtomicUsize,
+    /// Connections still open, to close them from the tests.
+    open: Mutex<Vec<TcpStream>>,
+}
+
+#[derive(Default)]
+struct Received {
+    messages: Vec<Value>,
+    /// Requests posted with `Content-Encoding: gzip`.
+    gzipped: usize,
+    /// `Authorization` headers of the requests.
+    authorizations: Vec<String>,
+    /// Departures from the specification, failing the test.
+    violations: Vec<String>,
+}
+
+impl GelfServer {
+    pub fn start(input: Input) -> GelfServer {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind the GELF input");
+        let port = listener.local_addr().unwrap().port();
+        let shared = Arc::new(Shared::default());
+        let acceptor = match input {
+            Input::Tls(_) => {
+                let identity = Identity::from_pkcs8(CERTIFICATE, PRIVATE_KEY).expect("Invalid test certificate");
+                Some(Arc::new(TlsAcceptor::new(identity).expect("Failed to set up TLS")))
+            }
+            _ => None,
+        };
+        let accepting = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                accepting.connections.fetch_add(1, Ordering::SeqCst);
+                if let Ok(clone) = stream.try_clone() {
+                    accepting.open.lock().unwrap_or_else(PoisonError::into_inner).push(clone);
+                }
+                let shared = accepting.clone();
+                let acceptor = acceptor.clone();
+                thread::spawn(move || {
+                    let result = match (input, acceptor) {
+                        (Input::Tls(framing), Some(acceptor)) => match acceptor.accept(stream) {
+                            Ok(stream) => read_frames(BufReader::new(stream), framing, &shared),
+                            // Clients refusing the certificate close the connection.
+                            Err(_) => Ok(()),
+                        },
+                        (Input::Tcp(framing), _) => read_frames(BufReader::new(stream), framing, &shared),
+                        // The connection stays open, its clone is kept in `open`.
+                        (Input::Stalled, _) => Ok(()),
+                        _ => serve_http(stream, &shared),
+                    };
+                    if let Err(error) = result {
+                        if error.kind() == io::ErrorKind::InvalidData {
+                            shared.violation(error.to_string());
+                        }
+                    }
+                });
+            }
+        });
+        GelfServer { port, shared }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Connections accepted so far.
+    pub fn connections(&self) -> usize {
+        self.shared.connections.load(Ordering::SeqCst)
+    }
+
+    /// Requests posted gzipped so far.
+    pub fn gzipped(&self) -> usize {
+        self.shared.lock().gzipped
+    }
+
+    /// `Authorization` headers of the requests posted so far.
+    pub fn authorizations(&self) -> Vec<String> {
+        self.shared.lock().authorizations.clone()
+    }
+
+    /// Messages received so far.
+    pub fn messages(&self) -> Vec<Value> {
+        let received = self.shared.lock();
+        assert!(received.violations.is_empty(), "Invalid GELF messages: {:?}", received.violations);
+        received.messages.clone()
+    }
+
+    /// Waits for at least `count` messages, and returns them all.
+    pub fn wait_for(&self, count: usize) -> Vec<Value> {
+        let deadline = Instant::now() + TIMEOUT;
+        let mut received = self.shared.lock();
+        while received.messages.len() < count && received.violations.is_empty() {
+            let now = Instant::now();
+            assert!(now < deadline, "Received {} messages out of {}", received.messages.len(), count);
+            received = self.shared.arrived
+                .wait_timeout(received, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        assert!(received.violations.is_empty(), "Invalid GELF messages: {:?}", received.violations);
+        received.messages.clone()
+    }
+
+    /// Closes the open connections, as a restarting server would.
+    pub fn drop_connections(&self) {
+        for stream in self.shared.open.lock().unwrap_or_else(PoisonError::into_inner).drain(..) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Received> {
+        self.received.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn receive(&self, payload: &[u8]) {
+        let mut received = self.lock();
+        match parse(payload) {
+            Ok(message) => received.messages.push(message),
+            Err(violation) => received.violations.push(violation),
+        }
+        self.arrived.notify_all();
+    }
+
+    fn violation(&self, violation: String) {
+        self.lock().violations.push(violation);
+        self.arrived.notify_all();
+    }
+}
+
+/// Reads the messages of a GELF TCP stream until it's closed.
+fn read_frames<R: Read>(mut reader: BufReader<R>, framing: Framing, shared: &Shared) -> io::Result<()> {
+    loop {
+        let mut frame = Vec::new();
+        match framing {
+            Framing::Null | Framing::Newline => {
+                let delimiter = if let Framing::Null = framing { b'\0' } else { b'\n' };
+                if reader.read_until(delimiter, &mut frame)? == 0 {
+                    return Ok(());
+                }
+                if frame.pop() != Some(delimiter) {
+                    return Err(invalid("stream closed in the middle of a frame"));
+                }
+            }
+            Framing::LengthPrefix => {
+                let mut length = [0; 4];
+                match reader.read_exact(&mut length) {
+                    Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                    result => result?,
+                }
+                frame.resize(u32::from_be_bytes(length) as usize, 0);
+                reader.read_exact(&mut frame)?;
+            }
+        }
+        shared.receive(&frame);
+    }
+}
+
+/// Answers the `POST /gelf` requests of a kept-alive HTTP/1.1 connection with `202 Accepted`.
+fn serve_http(stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if line.trim_end() != "POST /gelf HTTP/1.1" {
+            return Err(invalid(format!("unexpected request line {:?}", line)));
+        }
+        let mut length = None;
+        let mut gzipped = false;
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(':').ok_or_else(|| invalid("malformed header"))?;
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            } else if name.eq_ignore_ascii_case("content-encoding") {
+                gzipped = value.trim() == "gzip";
+            } else if name.eq_ignore_ascii_case("authorization") {
+                shared.lock().authorizations.push(value.trim().to_string());
+            }
+        }
+        let mut body = vec![0; length.ok_or_else(|| invalid("no Content-Length"))?];
+        reader.read_exact(&mut body)?;
+        if gzipped {
+            let mut decoded = Vec::new();
+            GzDecoder::new(&body[..]).read_to_end(&mut decoded).map_err(|_| invalid("invalid gzip body"))?;
+            body = decoded;
+            shared.lock().gzipped += 1;
+        }
+        shared.receive(&body);
+        writer.write_all(b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n")?;
+    }
+}
+
+/// Parses a message, checking it against the GELF 1.1 specification.
+fn parse(payload: &[u8]) -> Result<Value, String> {
+    let message: Value = serde_json::from_slice(payload)
+        .map_err(|error| format!("{}: {}", error, String::from_utf8_lossy(payload)))?;
+    let fields = message.as_object().ok_or_else(|| format!("not an object: {}", message))?;
+    if fields.get("version").and_then(Value::as_str) != Some("1.1") {
+        return Err(format!("no version 1.1: {}", message));
+    }
+    for key in &["host", "short_message"] {
+        if fields.get(*key).and_then(Value::as_str).is_none_or(str::is_empty) {
+            return Err(format!("no {}: {}", key, message));
+        }
+    }
+    for (key, value) in fields {
+        let valid = match key.as_str() {
+            "version" | "host" | "short_message" | "full_message" => value.is_string(),
+            "timestamp" => value.is_number(),
+            "level" => value.as_u64().is_some_and(|level| level <= 7),
+            "_id" => false,
+            _ => {
+                key.len() > 1
+                    && key.starts_with('_')
+                    && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-')
+                    && (value.is_string() || value.is_number())
+            }
+        };
+        if !valid {
+            return Err(format!("invalid field {:?}: {}", key, message));
+        }
+    }
+    Ok(message)
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}